@@ -0,0 +1,132 @@
+use clap::Args;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::expr::{Allocator, ExprRef};
+
+use super::executor::{EvalOptions, Executor, ReductionStrategy};
+
+/// Time how long it takes to evaluate a named variable
+#[derive(Args)]
+pub struct BenchArgs {
+  /// Name of the variable to evaluate
+  name: String,
+
+  /// File to load after the prelude before evaluating. Can be repeated, and files load in order.
+  #[clap(short, long = "file", value_name = "FILE")]
+  files: Vec<PathBuf>,
+
+  /// Don't load the prelude, so only the definitions from the files exist
+  #[clap(long)]
+  no_prelude: bool,
+
+  /// Number of timed evaluations
+  #[clap(short = 'n', long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+  iterations: u64,
+
+  /// Number of evaluations to run before timing, which aren't counted in the results
+  #[clap(long, value_name = "N", default_value_t = 1)]
+  warmup: u64,
+
+  /// Load the prelude and files again for every evaluation, so the time includes parsing the code
+  #[clap(long)]
+  fresh_parse: bool,
+
+  /// Order to reduce the redexes in
+  #[clap(long, value_enum, default_value_t)]
+  strategy: ReductionStrategy,
+
+  /// Remember the result of every reduced redex, to compare against the default of reducing every copy
+  #[clap(long)]
+  memo: bool,
+}
+
+impl BenchArgs {
+  pub fn execute(self) -> super::CommandResult {
+    let options = EvalOptions {
+      strategy: self.strategy,
+      memoize: self.memo,
+      ..EvalOptions::default()
+    };
+
+    // The step count is the same every time, so it comes from one extra run that isn't timed
+    let executor = Executor::new();
+    self.load(&executor)?;
+    let eval_allocator = Allocator::new();
+    let expr = self.lookup(&executor)?;
+    let (_, stats) = executor.evaluate_with_stats(&eval_allocator, expr, options);
+
+    for _ in 0..self.warmup {
+      self.time_once(&executor, options)?;
+    }
+
+    let mut times = Vec::with_capacity(self.iterations as usize);
+    for _ in 0..self.iterations {
+      times.push(self.time_once(&executor, options)?);
+    }
+    times.sort();
+
+    // Every line is `key: value` with the times always in milliseconds, so results can be diffed
+    println!("name: {}", self.name);
+    println!("iterations: {}", self.iterations);
+    println!("steps: {}", stats.steps);
+    println!("min: {:.3} ms", as_millis(times[0]));
+    println!("median: {:.3} ms", as_millis(median(&times)));
+    println!("max: {:.3} ms", as_millis(times[times.len() - 1]));
+
+    Ok(())
+  }
+
+  /// Load the prelude and all of the files
+  fn load<'s>(&self, executor: &'s Executor<'s>) -> super::CommandResult {
+    if !self.no_prelude {
+      executor.load_code(crate::PRELUDE, Some("prelude"))?;
+    }
+    for file in self.files.iter() {
+      executor.load_file(file)?;
+    }
+
+    Ok(())
+  }
+
+  fn lookup<'s>(&self, executor: &Executor<'s>) -> Result<ExprRef<'s>, String> {
+    executor
+      .get_global(&self.name)
+      .ok_or_else(|| format!("unknown term: {}", self.name))
+  }
+
+  /// Evaluate the variable once using a new allocator, including the time to load the code if using `--fresh-parse`
+  fn time_once(&self, executor: &Executor<'_>, options: EvalOptions) -> Result<Duration, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    if self.fresh_parse {
+      let executor = Executor::new();
+      self.load(&executor)?;
+      self.evaluate(&executor, options)?;
+    } else {
+      self.evaluate(executor, options)?;
+    }
+
+    Ok(start.elapsed())
+  }
+
+  fn evaluate(&self, executor: &Executor<'_>, options: EvalOptions) -> Result<(), String> {
+    let eval_allocator = Allocator::new();
+    let expr = self.lookup(executor)?;
+    executor.evaluate(&eval_allocator, expr, options);
+    Ok(())
+  }
+}
+
+/// Middle time of the sorted list, or the average of the two middle times
+fn median(sorted: &[Duration]) -> Duration {
+  let middle = sorted.len() / 2;
+  if sorted.len().is_multiple_of(2) {
+    (sorted[middle - 1] + sorted[middle]) / 2
+  } else {
+    sorted[middle]
+  }
+}
+
+fn as_millis(duration: Duration) -> f64 {
+  duration.as_secs_f64() * 1000.0
+}
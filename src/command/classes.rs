@@ -0,0 +1,83 @@
+use clap::Args;
+use crossterm::style::Stylize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use super::executor::Executor;
+use crate::expr::ExprRef;
+
+/// Group all definitions that are equal to each other, ignoring parameter names
+#[derive(Args)]
+pub struct EquivClassesArgs {
+  /// Also print definitions that are not equal to any other definition
+  #[clap(short, long)]
+  all: bool,
+
+  /// Don't load the prelude, so only the definitions from the files exist
+  #[clap(long)]
+  no_prelude: bool,
+
+  /// List of files to load after the prelude, in order
+  files: Vec<PathBuf>,
+}
+
+impl EquivClassesArgs {
+  pub fn execute(self) -> super::CommandResult {
+    let executor = Executor::new();
+    if !self.no_prelude {
+      executor.load_code(crate::PRELUDE, Some("prelude"))?;
+    }
+    for file in self.files.iter() {
+      executor.load_file(file)?;
+    }
+
+    let all_globals = executor.all_globals().borrow();
+    for (names, value) in equivalence_classes(&all_globals) {
+      if names.len() > 1 || self.all {
+        println!("{} = {value:#}", names.join(", ").white().bold());
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// Split the globals into classes that are structurally equal, in the order each class is first found.
+/// Every class is returned along with the value that its globals are equal to.
+fn equivalence_classes<'a>(globals: &BTreeMap<&'a str, ExprRef<'a>>) -> Vec<(Vec<&'a str>, ExprRef<'a>)> {
+  // Bucket by hash first, then split each bucket into classes that are actually equal
+  let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+  let mut classes: Vec<(Vec<&str>, ExprRef)> = Vec::new();
+  for (name, value) in globals.iter() {
+    let bucket = buckets.entry(value.structural_hash()).or_default();
+    match bucket.iter().find(|class| value.structurally_eq(classes[**class].1)) {
+      Some(class) => classes[*class].0.push(name),
+      None => {
+        bucket.push(classes.len());
+        classes.push((vec![name], *value));
+      },
+    }
+  }
+
+  classes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn alpha_equivalent_globals_share_a_class() {
+    let executor = Executor::new();
+    executor
+      .load_code("id = \\x.x\nfirst = \\x y.x\nalso-id = \\y.y", None)
+      .unwrap();
+
+    let globals = executor.all_globals().borrow();
+    let classes: Vec<Vec<&str>> = equivalence_classes(&globals)
+      .into_iter()
+      .map(|(names, _)| names)
+      .collect();
+    assert_eq!(classes, [vec!["also-id", "id"], vec!["first"]]);
+  }
+}
@@ -0,0 +1,187 @@
+use clap::{Args, ValueEnum};
+use std::num::NonZero;
+use std::path::PathBuf;
+
+use crate::expr::{Allocator, ExprRef, UnpackedExpr};
+
+use super::executor::{EvalOptions, Executor};
+
+/// Compile a named variable into a different language
+#[derive(Args)]
+pub struct CompileArgs {
+  /// Name of the variable to compile
+  name: String,
+
+  /// Language to compile into
+  #[clap(long, value_enum, default_value_t)]
+  target: CompileTarget,
+
+  /// Also use the B and C combinators, which usually gives a much shorter result
+  #[clap(long)]
+  optimize: bool,
+
+  /// Evaluate the variable before compiling it
+  #[clap(short, long)]
+  evaluate: bool,
+
+  /// File to load after the prelude before compiling. Can be repeated, and files load in order.
+  #[clap(short, long = "file", value_name = "FILE")]
+  files: Vec<PathBuf>,
+
+  /// Don't load the prelude, so only the definitions from the files exist
+  #[clap(long)]
+  no_prelude: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CompileTarget {
+  /// Combinatory logic using S, K and I, plus B and C with --optimize
+  #[default]
+  Ski,
+}
+
+impl CompileArgs {
+  pub fn execute(self) -> super::CommandResult {
+    let executor = Executor::new();
+    if !self.no_prelude {
+      executor.load_code(crate::PRELUDE, Some("prelude"))?;
+    }
+    for file in self.files.iter() {
+      executor.load_file(file)?;
+    }
+
+    let expr = executor
+      .get_global(&self.name)
+      .ok_or_else(|| format!("unknown term: {}", self.name))?;
+
+    let eval_allocator = Allocator::new();
+    let expr = if self.evaluate {
+      executor.evaluate(&eval_allocator, expr, EvalOptions::default())
+    } else {
+      expr
+    };
+
+    match self.target {
+      CompileTarget::Ski => {
+        let compiled = compile_ski(&eval_allocator, expr, self.optimize)?;
+        println!("{}", compiled.display_in_context(&COMBINATOR_CONTEXT));
+      },
+    }
+
+    Ok(())
+  }
+}
+
+/// Names of the combinators, listed like outer lambdas from outermost to innermost.
+/// The compiled expression references each combinator as a free variable, so S is index 1, K is index 2, etc.
+const COMBINATOR_CONTEXT: [&str; 5] = ["C", "B", "I", "K", "S"];
+
+/// Convert a closed lambda expression into combinatory logic using bracket abstraction.
+///
+/// The result only contains applications of the free variables named by [COMBINATOR_CONTEXT]:
+/// - `S x y z = x z (y z)`
+/// - `K x y = x`
+/// - `I x = x`
+/// - `B x y z = x (y z)` (only with `optimize`)
+/// - `C x y z = x z y` (only with `optimize`)
+fn compile_ski<'a>(allocator: &'a Allocator, expr: ExprRef<'_>, optimize: bool) -> Result<ExprRef<'a>, String> {
+  if !expr.is_closed() {
+    return Err("term is not closed, so it cannot be compiled into combinators".into());
+  }
+
+  Ok(Combinator::from_expr(expr, optimize).build(allocator))
+}
+
+/// Partly compiled expression. Variables use de Bruijn indices, just like [ExprRef].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Combinator {
+  Var(u64),
+  S,
+  K,
+  I,
+  B,
+  C,
+  App(Box<Combinator>, Box<Combinator>),
+}
+
+impl Combinator {
+  fn app(left: Combinator, right: Combinator) -> Self {
+    Combinator::App(Box::new(left), Box::new(right))
+  }
+
+  /// Compile the lambdas from the inside out, so each abstraction only sees variables and combinators
+  fn from_expr(expr: ExprRef<'_>, optimize: bool) -> Self {
+    match expr.unpack() {
+      UnpackedExpr::Term { de_bruijn_index } => Combinator::Var(de_bruijn_index.get()),
+      UnpackedExpr::Lambda { body, .. } => Self::from_expr(body, optimize).abstract_var(optimize),
+      UnpackedExpr::Eval { left, right } => {
+        Combinator::app(Self::from_expr(left, optimize), Self::from_expr(right, optimize))
+      },
+    }
+  }
+
+  /// Test if the variable bound by the innermost lambda (index 1) appears anywhere
+  fn uses_var(&self) -> bool {
+    match self {
+      Combinator::Var(index) => *index == 1,
+      Combinator::App(left, right) => left.uses_var() || right.uses_var(),
+      _ => false,
+    }
+  }
+
+  /// Remove the innermost lambda from the context, which must not be used
+  fn shift_down(self) -> Self {
+    match self {
+      Combinator::Var(index) => Combinator::Var(index - 1),
+      Combinator::App(left, right) => Combinator::app(left.shift_down(), right.shift_down()),
+      other => other,
+    }
+  }
+
+  /// Bracket abstraction: build an expression without variable 1 that gives back `self` when applied to it
+  fn abstract_var(self, optimize: bool) -> Self {
+    if !self.uses_var() {
+      return Combinator::app(Combinator::K, self.shift_down());
+    }
+
+    match self {
+      Combinator::Var(_) => Combinator::I,
+      Combinator::App(left, right) => match (optimize, left.uses_var(), right.uses_var()) {
+        // Eta reduction: \x.(f x) = f
+        (true, false, true) if *right == Combinator::Var(1) => left.shift_down(),
+        (true, false, true) => Combinator::app(
+          Combinator::app(Combinator::B, left.shift_down()),
+          right.abstract_var(optimize),
+        ),
+        (true, true, false) => Combinator::app(
+          Combinator::app(Combinator::C, left.abstract_var(optimize)),
+          right.shift_down(),
+        ),
+        _ => Combinator::app(
+          Combinator::app(Combinator::S, left.abstract_var(optimize)),
+          right.abstract_var(optimize),
+        ),
+      },
+      _ => unreachable!("only variables and applications can use a variable"),
+    }
+  }
+
+  /// Build the expression, referencing each combinator as a free variable
+  fn build(self, allocator: &Allocator) -> ExprRef<'_> {
+    let index = match self {
+      Combinator::Var(_) => unreachable!("closed terms have no variables left after compiling"),
+      Combinator::S => 1,
+      Combinator::K => 2,
+      Combinator::I => 3,
+      Combinator::B => 4,
+      Combinator::C => 5,
+      Combinator::App(left, right) => {
+        let left = left.build(allocator);
+        let right = right.build(allocator);
+        return allocator.new_eval(left, right);
+      },
+    };
+
+    allocator.new_term(NonZero::new(index).expect("index is zero"))
+  }
+}
@@ -1,13 +1,14 @@
 use clap::{
-  Args,
+  Args, ValueEnum,
   builder::{ArgPredicate, NonEmptyStringValueParser},
 };
-use std::{fs, io::Read, num::NonZero, path::PathBuf};
+use std::{error::Error, fmt, fs, io::Read, num::NonZero, path::PathBuf};
 use typed_arena::Arena;
 
 use crate::{
-  command::executor::Executor,
-  expr::{Allocator, ExprRef},
+  command::executor::{EvalOptions, Executor, TraceFormat},
+  expr::{AllocError, Allocator, ExprRef},
+  json,
 };
 
 /// Decode a Binary Lambda Calculus expression
@@ -24,11 +25,19 @@ pub struct DecodeArgs {
   #[clap(short, long, group = "format")]
   zero_width: bool,
 
+  /// Parse input as an S-expression like (lam x (app (var 1) (var 1))), keeping the parameter names
+  #[clap(long, group = "format")]
+  sexpr: bool,
+
+  /// Parse input as a JSON tree, keeping the parameter names
+  #[clap(long, group = "format")]
+  json: bool,
+
   /// Character to output for a "0"
   #[clap(
     long,
     value_parser = NonEmptyStringValueParser::new(),
-    conflicts_with = "binary",
+    conflicts_with_all = ["binary", "zero_width", "sexpr", "json"],
     default_value = "0",
      default_value_if("zero_width", ArgPredicate::Equals("true".into()), Some("\u{ffa0}"))
   )]
@@ -38,7 +47,7 @@ pub struct DecodeArgs {
   #[clap(
     long,
     value_parser = NonEmptyStringValueParser::new(),
-    conflicts_with = "binary",
+    conflicts_with_all = ["binary", "zero_width", "sexpr", "json"],
     default_value = "1",
     default_value_if("zero_width", ArgPredicate::Equals("true".into()), Some("\u{3164}"))
   )]
@@ -51,6 +60,26 @@ pub struct DecodeArgs {
   /// Print the reduction steps to stderr if --evaluate is set
   #[clap(short, long, requires = "evaluate")]
   steps: bool,
+
+  /// Format of the reduction steps printed by --steps
+  #[clap(long, value_enum, default_value_t, requires = "steps")]
+  trace_format: TraceFormat,
+
+  /// Allow terms to reference N outer lambdas that surround the decoded expression, named ctx1 to ctxN
+  #[clap(long, value_name = "N", default_value_t = 0)]
+  context_depth: u64,
+
+  /// How to name the parameters when the expression is printed
+  #[clap(long, value_enum, default_value_t)]
+  names: ParameterNames,
+
+  /// Fail if the decoded expression has any free variables, even ones allowed by the input format
+  #[clap(long, conflicts_with = "context_depth")]
+  require_closed: bool,
+
+  /// Print the number of reduction steps and the size of the result to stderr if --evaluate is set
+  #[clap(long, requires = "evaluate")]
+  stats: bool,
 }
 
 impl DecodeArgs {
@@ -67,34 +96,61 @@ impl DecodeArgs {
     };
 
     let mut s = String::new();
-    let mut bit_iter: Box<dyn Iterator<Item = bool>> = if self.binary {
-      // Parse as binary
-      let mut bytes = Vec::new();
-      reader.read_to_end(&mut bytes)?;
-      Box::new(get_byte_iter(bytes))
-    } else {
-      // Parse as text
-      reader.read_to_string(&mut s)?;
-      Box::new(Extractor::new(&self.zero, &self.one, &s))
-    };
-
     let text_data = Arena::new();
     let allocator = Allocator::new();
+    let mut decoder = Decoder::new(&text_data, &allocator, self.context_depth);
 
-    let mut decoder = Decoder::new(&text_data, &allocator);
-    let mut expr = match decoder.decode_expr(&mut bit_iter) {
-      None => return Err("failed to decode lambda expression".into()),
-      Some(expr) => expr,
+    let expr = if self.sexpr {
+      // Parse as an S-expression
+      reader.read_to_string(&mut s)?;
+      decoder.decode_sexpr(&s)
+    } else if self.json {
+      // Parse as JSON
+      reader.read_to_string(&mut s)?;
+      Ok(json::expr_from_json(&allocator, &text_data, &s)?)
+    } else {
+      let mut bit_iter: Box<dyn Iterator<Item = bool>> = if self.binary {
+        // Parse as binary
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Box::new(get_byte_iter(bytes))
+      } else {
+        // Parse as text
+        reader.read_to_string(&mut s)?;
+        Box::new(Extractor::new(&self.zero, &self.one, &s))
+      };
+
+      decoder.decode_all(&mut bit_iter, self.binary)
     };
 
+    let expr = if self.require_closed {
+      expr.and_then(require_closed)
+    } else {
+      expr
+    };
+    let mut expr = expr.map_err(|e| format!("failed to decode lambda expression: {e}"))?;
+
     // Possibly evaluate the expression
     if self.evaluate {
       let executor = Executor::new();
-      expr = executor.evaluate(&allocator, expr, self.steps);
+      let options = EvalOptions::new(self.steps, self.trace_format);
+      if self.stats {
+        let (result, stats) = executor.evaluate_with_stats(&allocator, expr, options);
+        eprintln!("Steps: {}, Size: {}", stats.steps, stats.size);
+        expr = result;
+      } else {
+        expr = executor.evaluate(&allocator, expr, options);
+      }
     }
 
-    // Print decoded expression
-    println!("{expr}");
+    // Print decoded expression, naming any references to the outer lambdas
+    let context: Vec<&str> = (1..=self.context_depth)
+      .map(|i| text_data.alloc(format!("ctx{i}")).as_str())
+      .collect();
+    match self.names {
+      ParameterNames::Numbered => println!("{}", expr.display_in_context(&context)),
+      ParameterNames::Alpha => println!("{}", expr.display_with_fresh_names(&context)),
+    }
 
     Ok(())
   }
@@ -126,121 +182,462 @@ impl Iterator for Extractor<'_, '_, '_> {
   type Item = bool;
 
   fn next(&mut self) -> Option<Self::Item> {
-    // O(2*n) inefficient but I don't care algorithm is simple
-    let next_zero = self.s.find(self.zero);
-    let next_one = self.s.find(self.one);
-
-    match (next_zero, next_one) {
-      (None, None) => None,
-      (Some(zero), None) => {
-        self.s = &self.s[(zero + self.zero.len())..];
-        Some(false)
-      },
-      (None, Some(one)) => {
-        self.s = &self.s[(one + self.one.len())..];
-        Some(true)
-      },
-      (Some(zero), Some(one)) if zero < one => {
-        self.s = &self.s[(zero + self.zero.len())..];
-        Some(false)
-      },
-      (Some(_), Some(one)) => {
-        self.s = &self.s[(one + self.one.len())..];
-        Some(true)
-      },
+    // Scan forward once, skipping any characters that don't start a marker.
+    // Checking "1" first matches the old behavior if both markers start at the same position.
+    loop {
+      if let Some(rest) = self.s.strip_prefix(self.one) {
+        self.s = rest;
+        return Some(true);
+      }
+      if let Some(rest) = self.s.strip_prefix(self.zero) {
+        self.s = rest;
+        return Some(false);
+      }
+
+      let mut chars = self.s.chars();
+      chars.next()?;
+      self.s = chars.as_str();
     }
   }
 }
 
+/// Naming scheme for the parameters of the decoded expression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ParameterNames {
+  /// Keep the decoded names: x1, x2, x3, ... for Binary Lambda Calculus, or the names stored in the S-expression or JSON
+  #[default]
+  Numbered,
+
+  /// Rename every parameter x, y, z, a, b, ..., xx, xy, ... by how deeply it is nested, like the rest of the tool
+  Alpha,
+}
+
 struct Decoder<'alloc> {
   text_data: &'alloc Arena<String>,
   allocator: &'alloc Allocator,
   variable_names: Vec<&'alloc str>,
   current_scope: u64,
+  context_depth: u64,
 }
 
 impl<'alloc> Decoder<'alloc> {
-  pub fn new(text_data: &'alloc Arena<String>, allocator: &'alloc Allocator) -> Self {
+  /// Context depth is the number of outer lambdas that terms can reference, which are not part of the expression
+  pub fn new(text_data: &'alloc Arena<String>, allocator: &'alloc Allocator, context_depth: u64) -> Self {
     Self {
       text_data,
       allocator,
       variable_names: Vec::new(),
-      current_scope: 0,
+      current_scope: context_depth,
+      context_depth,
     }
   }
 
   fn get_parameter_name(&mut self) -> &'alloc str {
-    for i in self.variable_names.len()..=(self.current_scope as usize) {
+    let level = self.current_scope - self.context_depth;
+    for i in self.variable_names.len()..=(level as usize) {
       let data = self.text_data.alloc(format!("x{}", i + 1));
       self.variable_names.push(data.as_str());
     }
 
-    self.variable_names[(self.current_scope - 1) as usize]
+    self.variable_names[(level - 1) as usize]
   }
 
-  pub fn decode_expr(&mut self, iter: &mut dyn Iterator<Item = bool>) -> Option<ExprRef<'alloc>> {
-    match iter.next() {
-      None => {
-        println!("failed to decode expression: unexpected end of input");
-        None
-      },
-      Some(false) => match iter.next() {
-        None => {
-          println!("failed to decode expression: unexpected end of input");
-          None
-        },
-        Some(false) => self.decode_lambda(iter),
-        Some(true) => self.decode_eval(iter),
+  pub fn decode_expr(&mut self, iter: &mut dyn Iterator<Item = bool>) -> Result<ExprRef<'alloc>, DecodeError> {
+    match iter.next().ok_or(DecodeError::UnexpectedEof)? {
+      false => match iter.next().ok_or(DecodeError::UnexpectedEof)? {
+        false => self.decode_lambda(iter),
+        true => self.decode_eval(iter),
       },
-      Some(true) => self.decode_term(iter),
+      true => self.decode_term(iter),
+    }
+  }
+
+  /// Same as decode_expr(), but fails if any bits are left over afterwards.
+  /// Raw bytes can have up to 7 bits of zero padding at the end.
+  pub fn decode_all(
+    &mut self,
+    iter: &mut dyn Iterator<Item = bool>,
+    binary: bool,
+  ) -> Result<ExprRef<'alloc>, DecodeError> {
+    let expr = self.decode_expr(iter)?;
+
+    let remaining: Vec<bool> = iter.collect();
+    let is_padding = binary && remaining.len() < 8 && remaining.iter().all(|bit| !bit);
+    if !remaining.is_empty() && !is_padding {
+      return Err(DecodeError::TrailingBits);
     }
+
+    Ok(expr)
   }
 
-  fn decode_term(&mut self, iter: &mut dyn Iterator<Item = bool>) -> Option<ExprRef<'alloc>> {
+  fn decode_term(&mut self, iter: &mut dyn Iterator<Item = bool>) -> Result<ExprRef<'alloc>, DecodeError> {
     let mut term_index = 1;
-    loop {
-      match iter.next() {
-        None => {
-          println!("failed to decode term: unexpected end of input");
-          return None;
-        },
-        Some(true) => {
-          term_index += 1;
-        },
-        Some(false) => {
-          break;
-        },
-      }
+    while iter.next().ok_or(DecodeError::UnexpectedEof)? {
+      term_index += 1;
     }
 
-    if term_index > self.current_scope {
-      println!(
-        "invalid term: index {term_index} > current lambda index {}",
-        self.current_scope
-      );
-      None
-    } else {
-      let term = self
-        .allocator
-        .new_term(NonZero::new(term_index).expect("index is zero"));
-      Some(term)
+    self.new_term(term_index)
+  }
+
+  fn new_term(&self, index: u64) -> Result<ExprRef<'alloc>, DecodeError> {
+    if index > self.current_scope {
+      return Err(DecodeError::InvalidTermIndex {
+        index,
+        scope: self.current_scope,
+      });
     }
+
+    let term = self
+      .allocator
+      .try_new_term(NonZero::new(index).expect("index is zero"))
+      .map_err(DecodeError::IndexTooLarge)?;
+    Ok(term)
   }
 
-  fn decode_lambda(&mut self, iter: &mut dyn Iterator<Item = bool>) -> Option<ExprRef<'alloc>> {
+  fn decode_lambda(&mut self, iter: &mut dyn Iterator<Item = bool>) -> Result<ExprRef<'alloc>, DecodeError> {
     self.current_scope += 1;
     let body = self.decode_expr(iter)?;
     let param_name = self.get_parameter_name();
     self.current_scope -= 1;
 
     let lambda = self.allocator.new_lambda(param_name, body);
-    Some(lambda)
+    Ok(lambda)
   }
 
-  fn decode_eval(&mut self, iter: &mut dyn Iterator<Item = bool>) -> Option<ExprRef<'alloc>> {
+  fn decode_eval(&mut self, iter: &mut dyn Iterator<Item = bool>) -> Result<ExprRef<'alloc>, DecodeError> {
     let left = self.decode_expr(iter)?;
     let right = self.decode_expr(iter)?;
     let eval = self.allocator.new_eval(left, right);
-    Some(eval)
+    Ok(eval)
+  }
+
+  /// Decode an S-expression made of `(lam name BODY)`, `(app LEFT RIGHT)` and `(var INDEX)`
+  pub fn decode_sexpr(&mut self, s: &str) -> Result<ExprRef<'alloc>, DecodeError> {
+    let mut tokens = SexprTokens { s };
+    let expr = self.decode_sexpr_expr(&mut tokens)?;
+
+    match tokens.next() {
+      None => Ok(expr),
+      Some(token) => Err(DecodeError::UnexpectedToken {
+        expected: "end of input",
+        found: token.to_string(),
+      }),
+    }
+  }
+
+  /// Lambdas and applications wait on a stack for their children instead of recursing,
+  /// so deeply nested input can't overflow the call stack
+  fn decode_sexpr_expr(&mut self, tokens: &mut SexprTokens<'_>) -> Result<ExprRef<'alloc>, DecodeError> {
+    let mut parents = Vec::new();
+    loop {
+      // Read down to the next variable, opening every lambda and application along the way
+      expect_token(tokens, "(")?;
+      let mut expr = match tokens.next().ok_or(DecodeError::UnexpectedEof)? {
+        "var" => {
+          let token = tokens.next().ok_or(DecodeError::UnexpectedEof)?;
+          let index = token
+            .parse()
+            .ok()
+            .filter(|index| *index > 0)
+            .ok_or_else(|| DecodeError::UnexpectedToken {
+              expected: "a positive index",
+              found: token.to_string(),
+            })?;
+          self.new_term(index)?
+        },
+        "lam" => {
+          let param_name = match tokens.next().ok_or(DecodeError::UnexpectedEof)? {
+            token @ ("(" | ")") => {
+              return Err(DecodeError::UnexpectedToken {
+                expected: "a parameter name",
+                found: token.to_string(),
+              });
+            },
+            name => self.text_data.alloc(name.to_string()).as_str(),
+          };
+
+          self.current_scope += 1;
+          parents.push(SexprParent::Lambda(param_name));
+          continue;
+        },
+        "app" => {
+          parents.push(SexprParent::EvalLeft);
+          continue;
+        },
+        token => {
+          return Err(DecodeError::UnexpectedToken {
+            expected: "'lam', 'app' or 'var'",
+            found: token.to_string(),
+          });
+        },
+      };
+      expect_token(tokens, ")")?;
+
+      // Then close every parent that is now complete, until one still needs its right side
+      loop {
+        match parents.pop() {
+          None => return Ok(expr),
+          Some(SexprParent::Lambda(param_name)) => {
+            self.current_scope -= 1;
+            expr = self.allocator.new_lambda(param_name, expr);
+          },
+          Some(SexprParent::EvalLeft) => {
+            parents.push(SexprParent::EvalRight(expr));
+            break;
+          },
+          Some(SexprParent::EvalRight(left)) => {
+            expr = self.allocator.new_eval(left, expr);
+          },
+        }
+        expect_token(tokens, ")")?;
+      }
+    }
+  }
+}
+
+/// Lambda or application in an S-expression that is still waiting for its children
+enum SexprParent<'alloc> {
+  Lambda(&'alloc str),
+  EvalLeft,
+  EvalRight(ExprRef<'alloc>),
+}
+
+/// Check that a fully decoded expression is closed, no matter which format it came from
+fn require_closed(expr: ExprRef<'_>) -> Result<ExprRef<'_>, DecodeError> {
+  match expr.first_free_term() {
+    None => Ok(expr),
+    Some((index, scope)) => Err(DecodeError::FreeVariable {
+      index: index.get(),
+      scope,
+    }),
+  }
+}
+
+fn expect_token(tokens: &mut SexprTokens<'_>, expected: &'static str) -> Result<(), DecodeError> {
+  match tokens.next().ok_or(DecodeError::UnexpectedEof)? {
+    token if token == expected => Ok(()),
+    token => Err(DecodeError::UnexpectedToken {
+      expected,
+      found: token.to_string(),
+    }),
+  }
+}
+
+/// Input could not be decoded into an expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+  /// Input ended in the middle of an expression
+  UnexpectedEof,
+
+  /// Term references a lambda that doesn't exist
+  InvalidTermIndex { index: u64, scope: u64 },
+
+  /// Term references outside of the whole expression, but the expression must be closed
+  FreeVariable { index: u64, scope: u64 },
+
+  /// Term index is too large to be stored
+  IndexTooLarge(AllocError),
+
+  /// Input continues after the end of the expression
+  TrailingBits,
+
+  /// S-expression has the wrong token at this point
+  UnexpectedToken { expected: &'static str, found: String },
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UnexpectedEof => write!(f, "unexpected end of input"),
+      Self::InvalidTermIndex { index, scope } => {
+        write!(f, "invalid term: index {index} > current lambda index {scope}")
+      },
+      Self::FreeVariable { index, scope } => {
+        write!(f, "expression is not closed: index {index} > lambda index {scope}")
+      },
+      Self::IndexTooLarge(e) => write!(f, "invalid term: {e}"),
+      Self::TrailingBits => write!(f, "unexpected input after the end of the expression"),
+      Self::UnexpectedToken { expected, found } => write!(f, "expected {expected}, found '{found}'"),
+    }
+  }
+}
+
+impl Error for DecodeError {}
+
+/// Splits an S-expression into parentheses and atoms
+struct SexprTokens<'s> {
+  s: &'s str,
+}
+
+impl<'s> Iterator for SexprTokens<'s> {
+  type Item = &'s str;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.s = self.s.trim_start();
+    let first = self.s.chars().next()?;
+
+    let length = if first == '(' || first == ')' {
+      1
+    } else {
+      self
+        .s
+        .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .unwrap_or(self.s.len())
+    };
+
+    let (token, rest) = self.s.split_at(length);
+    self.s = rest;
+    Some(token)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use clap::Parser;
+  use clap::error::ErrorKind;
+
+  use super::*;
+
+  /// Parse the arguments of the decode command the same way as the command line
+  fn parse_decode_args(args: &[&str]) -> Result<DecodeArgs, clap::Error> {
+    #[derive(Parser)]
+    struct Cli {
+      #[clap(flatten)]
+      decode: DecodeArgs,
+    }
+
+    Cli::try_parse_from(["lambda"].iter().chain(args)).map(|cli| cli.decode)
+  }
+
+  #[test]
+  fn conflicting_formats_are_rejected() {
+    for args in [
+      &["--binary", "--zero-width"][..],
+      &["--sexpr", "--json"],
+      &["--zero-width", "--zero", "a"],
+      &["--binary", "--one", "b"],
+      &["--require-closed", "--context-depth", "2"],
+    ] {
+      let error = parse_decode_args(args)
+        .err()
+        .unwrap_or_else(|| panic!("{args:?} should conflict"));
+      assert_eq!(error.kind(), ErrorKind::ArgumentConflict, "{args:?}");
+    }
+  }
+
+  #[test]
+  fn evaluation_flags_require_evaluate() {
+    for args in [&["--steps"][..], &["--stats"]] {
+      let error = parse_decode_args(args)
+        .err()
+        .unwrap_or_else(|| panic!("{args:?} should need --evaluate"));
+      assert_eq!(error.kind(), ErrorKind::MissingRequiredArgument, "{args:?}");
+      assert!(error.to_string().contains("--evaluate"), "{args:?}: {error}");
+    }
+
+    assert!(parse_decode_args(&["--evaluate", "--steps", "--stats"]).is_ok());
+    assert!(parse_decode_args(&["--zero", "a", "--one", "b"]).is_ok());
+    assert!(parse_decode_args(&["--zero-width", "--context-depth", "2"]).is_ok());
+  }
+
+  #[test]
+  fn context_depth_names_the_outer_lambdas() {
+    let text_data = Arena::new();
+    let allocator = Allocator::new();
+    let context = ["ctx1", "ctx2"];
+    let decode = |bits: &str| {
+      let mut decoder = Decoder::new(&text_data, &allocator, 2);
+      decoder.decode_all(&mut Extractor::new("0", "1", bits), false)
+    };
+
+    // Both outer binders can be referenced, from the top level and from inside a lambda
+    let expr = decode("0111010").unwrap();
+    assert_eq!(expr.display_in_context(&context).to_string(), "(ctx1 ctx2)");
+    let expr = decode("00011101110").unwrap();
+    assert_eq!(expr.display_in_context(&context).to_string(), r"\x1.(ctx2 ctx1)");
+
+    // But nothing outside of them
+    assert!(matches!(
+      decode("01101110"),
+      Err(DecodeError::InvalidTermIndex { index: 3, scope: 2 })
+    ));
+  }
+
+  #[test]
+  fn extracting_a_megabyte_of_text_is_linear() {
+    // The "1" marker only appears at the very end, which used to be searched for again on every bit
+    let (zero, one) = ("\u{ffa0}", "\u{3164}");
+    let zeros = 1_000_000 / zero.len();
+    let text = format!("{}{one} junk", zero.repeat(zeros));
+    assert!(text.len() >= 1_000_000);
+
+    let bits: Vec<_> = Extractor::new(zero, one, &text).collect();
+    assert_eq!(bits.len(), zeros + 1);
+    assert!(bits[..zeros].iter().all(|bit| !bit));
+    assert!(bits[zeros]);
+
+    // Characters that aren't part of either marker are skipped
+    let bits: Vec<_> = Extractor::new("ab", "ac", "xabyacaab").collect();
+    assert_eq!(bits, [false, true, false]);
+  }
+
+  #[test]
+  fn malformed_blobs_are_errors() {
+    let text_data = Arena::new();
+    let allocator = Allocator::new();
+    let decode = |bits: &str, binary| {
+      let mut decoder = Decoder::new(&text_data, &allocator, 0);
+      decoder.decode_all(&mut Extractor::new("0", "1", bits), binary)
+    };
+
+    assert!(decode("0010", false).is_ok());
+    assert!(matches!(decode("001", false), Err(DecodeError::UnexpectedEof)));
+    assert!(matches!(decode("01001", false), Err(DecodeError::UnexpectedEof)));
+    assert!(matches!(decode("00101", false), Err(DecodeError::TrailingBits)));
+    assert!(matches!(
+      decode("00110", false),
+      Err(DecodeError::InvalidTermIndex { index: 2, scope: 1 })
+    ));
+
+    // Up to 7 zero bits can pad out the last byte of binary input, but nothing else
+    assert!(decode("00100000", true).is_ok());
+    assert!(matches!(decode("00100001", true), Err(DecodeError::TrailingBits)));
+    assert!(matches!(decode("001000000000", true), Err(DecodeError::TrailingBits)));
+
+    // JSON can have free variables, which are only rejected if the expression must be closed
+    let json = r#"{"type":"lambda","parameter_name":"x","body":{"type":"term","de_bruijn_index":3}}"#;
+    let expr = json::expr_from_json(&allocator, &text_data, json).unwrap();
+    assert!(matches!(
+      require_closed(expr),
+      Err(DecodeError::FreeVariable { index: 3, scope: 1 })
+    ));
+  }
+
+  #[test]
+  fn sexprs_decode_without_recursion() {
+    let text_data = Arena::new();
+    let allocator = Allocator::new();
+    let decode = |s: &str| Decoder::new(&text_data, &allocator, 0).decode_sexpr(s);
+
+    let expr = decode("(app (lam x (app (var 1) (var 1))) (lam y (lam z (var 2))))").unwrap();
+    assert_eq!(expr.to_string(), r"(\x.(x x) \y.\z.y)");
+
+    // Far deeper than the call stack could handle
+    let depth = 200_000;
+    let deep = format!("{}(var 1){}", "(lam x ".repeat(depth), ")".repeat(depth));
+    assert_eq!(decode(&deep).unwrap().size(), depth as u64 + 1);
+
+    assert!(matches!(
+      decode("(lam x (var 2))"),
+      Err(DecodeError::InvalidTermIndex { index: 2, scope: 1 })
+    ));
+    assert!(matches!(
+      decode("(app (lam x (var 1))"),
+      Err(DecodeError::UnexpectedEof)
+    ));
+    assert!(matches!(
+      decode("(lam x (var 1) (var 1))"),
+      Err(DecodeError::UnexpectedToken { .. })
+    ));
   }
 }
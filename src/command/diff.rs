@@ -0,0 +1,64 @@
+use clap::Args;
+use std::path::PathBuf;
+
+use crate::expr::Allocator;
+
+use super::executor::{EvalOptions, Executor};
+
+/// Show where two named variables first have a different structure, ignoring parameter names
+#[derive(Args)]
+pub struct DiffArgs {
+  /// Name of the first variable
+  first: String,
+
+  /// Name of the second variable
+  second: String,
+
+  /// File to load after the prelude before comparing. Can be repeated, and files load in order.
+  #[clap(short, long = "file", value_name = "FILE")]
+  files: Vec<PathBuf>,
+
+  /// Don't load the prelude, so only the definitions from the files exist
+  #[clap(long)]
+  no_prelude: bool,
+
+  /// Evaluate both variables before comparing them
+  #[clap(short, long)]
+  evaluate: bool,
+}
+
+impl DiffArgs {
+  pub fn execute(self) -> super::CommandResult {
+    let executor = Executor::new();
+    if !self.no_prelude {
+      executor.load_code(crate::PRELUDE, Some("prelude"))?;
+    }
+    for file in self.files.iter() {
+      executor.load_file(file)?;
+    }
+
+    let first = executor
+      .get_global(&self.first)
+      .ok_or_else(|| format!("unknown term: {}", self.first))?;
+    let second = executor
+      .get_global(&self.second)
+      .ok_or_else(|| format!("unknown term: {}", self.second))?;
+
+    let eval_allocator = Allocator::new();
+    let (first, second) = if self.evaluate {
+      (
+        executor.evaluate(&eval_allocator, first, EvalOptions::default()),
+        executor.evaluate(&eval_allocator, second, EvalOptions::default()),
+      )
+    } else {
+      (first, second)
+    };
+
+    match first.first_difference(second) {
+      None => println!("structurally identical"),
+      Some(difference) => println!("differ at {difference}"),
+    }
+
+    Ok(())
+  }
+}
@@ -2,12 +2,15 @@ use clap::{
   Args,
   builder::{ArgPredicate, NonEmptyStringValueParser},
 };
-use std::{fs, io::Write, num::NonZero, path::PathBuf};
+use std::{fmt::Write as _, fs, io::Write, num::NonZero, path::PathBuf};
 use typed_arena::Arena;
 
-use crate::expr::{Allocator, ExprRef, ExprVisitor};
+use crate::{
+  expr::{Allocator, ExprRef, ExprVisitor, FREE_VARIABLE_PREFIX},
+  json,
+};
 
-use super::executor::Executor;
+use super::executor::{EvalOptions, Executor, TraceFormat};
 
 /// Encode an expression to Binary Lambda Calculus
 #[derive(Args)]
@@ -19,6 +22,10 @@ pub struct EncodeArgs {
   /// List of files to load
   files: Vec<PathBuf>,
 
+  /// Don't load the prelude, so only the definitions from the files exist
+  #[clap(long)]
+  no_prelude: bool,
+
   /// Evaluate the term first before encoding it
   #[clap(short, long)]
   evaluate: bool,
@@ -27,6 +34,14 @@ pub struct EncodeArgs {
   #[clap(short, long, requires = "evaluate")]
   steps: bool,
 
+  /// Format of the reduction steps printed by --steps
+  #[clap(long, value_enum, default_value_t, requires = "steps")]
+  trace_format: TraceFormat,
+
+  /// Apply eta reduction after evaluating, which usually makes the encoding shorter
+  #[clap(long, requires = "evaluate")]
+  eta: bool,
+
   /// Output as raw bytes instead
   #[clap(short, long, group = "format")]
   binary: bool,
@@ -35,11 +50,31 @@ pub struct EncodeArgs {
   #[clap(short, long, group = "format")]
   zero_width: bool,
 
+  /// Output as an S-expression like (lam x (app (var 1) (var 1))), which keeps the parameter names
+  #[clap(long, group = "format")]
+  sexpr: bool,
+
+  /// Output as a JSON tree, which keeps the parameter names
+  #[clap(long, group = "format")]
+  json: bool,
+
+  /// Output as a Graphviz DOT graph of the expression tree
+  #[clap(long, group = "format")]
+  dot: bool,
+
+  /// Output as a lambda expression with de Bruijn indices instead of parameter names, like λ.λ.(2 1)
+  #[clap(long, group = "format")]
+  de_bruijn: bool,
+
+  /// Only print the length of the Binary Lambda Calculus encoding, like "42 bits (6 bytes)"
+  #[clap(long, group = "format", conflicts_with_all = ["zero", "one", "no_trailing_newline", "trailing_newline"])]
+  count_only: bool,
+
   /// Character to output for a "0"
   #[clap(
     long,
     value_parser = NonEmptyStringValueParser::new(),
-    conflicts_with = "binary",
+    conflicts_with_all = ["binary", "zero_width", "sexpr", "json", "de_bruijn", "dot"],
     default_value = "0",
     default_value_if("zero_width", ArgPredicate::Equals("true".into()), Some("\u{ffa0}"))
   )]
@@ -49,11 +84,23 @@ pub struct EncodeArgs {
   #[clap(
     long,
     value_parser = NonEmptyStringValueParser::new(),
-    conflicts_with = "binary",
+    conflicts_with_all = ["binary", "zero_width", "sexpr", "json", "de_bruijn", "dot"],
     default_value = "1",
     default_value_if("zero_width", ArgPredicate::Equals("true".into()), Some("\u{3164}"))
   )]
   one: String,
+
+  /// Write the encoded output to this file instead of stdout
+  #[clap(short, long, value_name = "FILE")]
+  output: Option<PathBuf>,
+
+  /// Don't print a newline after the encoded text (already the default for --zero-width)
+  #[clap(long, conflicts_with = "binary", overrides_with = "trailing_newline")]
+  no_trailing_newline: bool,
+
+  /// Always print a newline after the encoded text, even with --zero-width
+  #[clap(long, conflicts_with = "binary", overrides_with = "no_trailing_newline")]
+  trailing_newline: bool,
 }
 
 impl EncodeArgs {
@@ -67,15 +114,14 @@ impl EncodeArgs {
     let executor = Executor::new();
 
     // Load the prelude
-    {
+    if !self.no_prelude {
       let prelude = text_data.alloc(crate::PRELUDE.to_string());
       executor.load_code(prelude.as_str(), Some("prelude"))?;
     }
 
     // Load, but don't evaluate the code files
     for file in self.files.iter() {
-      let file_data = text_data.alloc(fs::read_to_string(file)?);
-      executor.load_code(file_data.as_str(), file.to_str())?;
+      executor.load_file(file)?;
     }
 
     // Execute the term as code
@@ -89,22 +135,60 @@ impl EncodeArgs {
 
     // Possibly evaluate the expression
     if self.evaluate {
-      expr = executor.evaluate(&eval_allocator, expr, self.steps);
+      let mut options = EvalOptions::new(self.steps, self.trace_format);
+      options.eta = self.eta;
+      expr = executor.evaluate(&eval_allocator, expr, options);
     }
 
-    if self.binary {
+    let output = if self.count_only {
+      // Count the bits without building the encoding
+      let mut visitor = BitCountVisitor::new();
+      expr.visit(&mut visitor);
+      let bits = visitor.bits();
+      format!("{bits} bits ({} bytes)\n", bits.div_ceil(8)).into_bytes()
+    } else if self.binary {
       // Binary encode the expression
       let mut visitor = ByteVisitor::new();
       expr.visit(&mut visitor);
-
-      let bytes = visitor.into_bytes();
-      std::io::stdout().write_all(&bytes)?;
+      visitor.into_bytes()
     } else {
       // String encode the expression
-      expr.visit(&mut PrintVisitor::new(&self.zero, &self.one));
-      if !self.zero_width {
-        println!();
+      let mut text = if self.sexpr {
+        let mut visitor = SexprVisitor::new();
+        expr.visit(&mut visitor);
+        visitor.into_string()
+      } else if self.json {
+        json::expr_to_json(expr)
+      } else if self.dot {
+        let mut visitor = DotVisitor::new();
+        expr.visit(&mut visitor);
+        visitor.into_string()
+      } else if self.de_bruijn {
+        format!("{expr:+#}")
+      } else {
+        let mut visitor = PrintVisitor::new(&self.zero, &self.one);
+        expr.visit(&mut visitor);
+        visitor.into_string()
+      };
+
+      let print_newline = if self.trailing_newline {
+        true
+      } else if self.no_trailing_newline {
+        false
+      } else {
+        !self.zero_width
+      };
+      if print_newline {
+        text.push('\n');
       }
+
+      text.into_bytes()
+    };
+
+    // Bytes are written exactly as they are, with no newline translation
+    match self.output {
+      Some(path) => fs::write(&path, output).map_err(|e| format!("cannot write to {}: {e}", path.display()))?,
+      None => std::io::stdout().write_all(&output)?,
     }
 
     Ok(())
@@ -112,14 +196,23 @@ impl EncodeArgs {
 }
 
 /// Encode as a string
-struct PrintVisitor<'zero, 'one> {
+pub(super) struct PrintVisitor<'zero, 'one> {
   zero: &'zero str,
   one: &'one str,
+  output: String,
 }
 
 impl<'zero, 'one> PrintVisitor<'zero, 'one> {
   pub fn new(zero: &'zero str, one: &'one str) -> Self {
-    Self { zero, one }
+    Self {
+      zero,
+      one,
+      output: String::new(),
+    }
+  }
+
+  pub fn into_string(self) -> String {
+    self.output
   }
 }
 
@@ -128,20 +221,139 @@ impl<'eval> ExprVisitor<'eval> for PrintVisitor<'_, '_> {
 
   fn visit_term(&mut self, _: ExprRef<'eval>, de_bruijn_index: NonZero<u64>) -> Self::Output {
     for _ in 0..de_bruijn_index.get() {
-      print!("{}", self.one);
+      self.output.push_str(self.one);
     }
-    print!("{}", self.zero);
+    self.output.push_str(self.zero);
   }
 
   fn visit_lambda(&mut self, _: ExprRef<'eval>, body: ExprRef<'eval>, _: &'eval str) -> Self::Output {
-    print!("{}{}", self.zero, self.zero);
+    self.output.push_str(self.zero);
+    self.output.push_str(self.zero);
+    body.visit(self);
+  }
+
+  fn visit_eval(&mut self, _: ExprRef<'eval>, left: ExprRef<'eval>, right: ExprRef<'eval>) -> Self::Output {
+    self.output.push_str(self.zero);
+    self.output.push_str(self.one);
+    left.visit(self);
+    right.visit(self);
+  }
+}
+
+/// Encode as an S-expression, using `(lam name BODY)`, `(app LEFT RIGHT)` and `(var INDEX)`
+struct SexprVisitor {
+  output: String,
+}
+
+impl SexprVisitor {
+  pub fn new() -> Self {
+    Self { output: String::new() }
+  }
+
+  pub fn into_string(self) -> String {
+    self.output
+  }
+}
+
+impl<'eval> ExprVisitor<'eval> for SexprVisitor {
+  type Output = ();
+
+  fn visit_term(&mut self, _: ExprRef<'eval>, de_bruijn_index: NonZero<u64>) -> Self::Output {
+    write!(self.output, "(var {de_bruijn_index})").unwrap();
+  }
+
+  fn visit_lambda(&mut self, _: ExprRef<'eval>, body: ExprRef<'eval>, parameter_name: &'eval str) -> Self::Output {
+    write!(self.output, "(lam {parameter_name} ").unwrap();
     body.visit(self);
+    self.output.push(')');
   }
 
   fn visit_eval(&mut self, _: ExprRef<'eval>, left: ExprRef<'eval>, right: ExprRef<'eval>) -> Self::Output {
-    print!("{}{}", self.zero, self.one);
+    self.output.push_str("(app ");
     left.visit(self);
+    self.output.push(' ');
     right.visit(self);
+    self.output.push(')');
+  }
+}
+
+/// Encode as a Graphviz DOT graph, where lambdas are labeled `λname`, evals are labeled `@`,
+/// and terms are labeled with the name of their parameter
+struct DotVisitor<'eval> {
+  output: String,
+  next_id: u64,
+  lambda_parameters: Vec<(&'eval str, usize)>,
+}
+
+impl<'eval> DotVisitor<'eval> {
+  pub fn new() -> Self {
+    Self {
+      output: String::from("digraph {\n"),
+      next_id: 0,
+      lambda_parameters: Vec::new(),
+    }
+  }
+
+  pub fn into_string(mut self) -> String {
+    self.output.push('}');
+    self.output
+  }
+
+  /// Add a new node to the graph, returning its id
+  fn add_node(&mut self, label: &str) -> u64 {
+    let id = self.next_id;
+    self.next_id += 1;
+
+    let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+    writeln!(self.output, "  n{id} [label=\"{label}\"];").unwrap();
+    id
+  }
+
+  fn add_edge(&mut self, from: u64, to: u64, label: &str) {
+    writeln!(self.output, "  n{from} -> n{to} [label=\"{label}\"];").unwrap();
+  }
+}
+
+impl<'eval> ExprVisitor<'eval> for DotVisitor<'eval> {
+  type Output = u64;
+
+  fn visit_term(&mut self, _: ExprRef<'eval>, de_bruijn_index: NonZero<u64>) -> Self::Output {
+    // Shadowed parameters are marked the same way as when printing an expression
+    let depth = self.lambda_parameters.len() as u64;
+    let label = match depth.checked_sub(de_bruijn_index.get()) {
+      Some(index) => {
+        let (name, shadows) = self.lambda_parameters[index as usize];
+        format!("{name}{}", "′".repeat(shadows))
+      },
+      None => format!("{FREE_VARIABLE_PREFIX}{}", de_bruijn_index.get() - depth),
+    };
+
+    self.add_node(&label)
+  }
+
+  fn visit_lambda(&mut self, _: ExprRef<'eval>, body: ExprRef<'eval>, parameter_name: &'eval str) -> Self::Output {
+    let shadows = self
+      .lambda_parameters
+      .iter()
+      .filter(|(name, _)| *name == parameter_name)
+      .count();
+    let id = self.add_node(&format!("λ{parameter_name}{}", "′".repeat(shadows)));
+
+    self.lambda_parameters.push((parameter_name, shadows));
+    let body = body.visit(self);
+    self.lambda_parameters.pop();
+
+    self.add_edge(id, body, "body");
+    id
+  }
+
+  fn visit_eval(&mut self, _: ExprRef<'eval>, left: ExprRef<'eval>, right: ExprRef<'eval>) -> Self::Output {
+    let id = self.add_node("@");
+    let left = left.visit(self);
+    let right = right.visit(self);
+    self.add_edge(id, left, "left");
+    self.add_edge(id, right, "right");
+    id
   }
 }
 
@@ -201,3 +413,62 @@ impl<'eval> ExprVisitor<'eval> for ByteVisitor {
     right.visit(self);
   }
 }
+
+/// Count the number of bits in the Binary Lambda Calculus encoding
+struct BitCountVisitor {
+  bits: u64,
+}
+
+impl BitCountVisitor {
+  pub fn new() -> Self {
+    Self { bits: 0 }
+  }
+
+  pub fn bits(&self) -> u64 {
+    self.bits
+  }
+}
+
+impl<'eval> ExprVisitor<'eval> for BitCountVisitor {
+  type Output = ();
+
+  fn visit_term(&mut self, _: ExprRef<'eval>, de_bruijn_index: NonZero<u64>) -> Self::Output {
+    self.bits += de_bruijn_index.get() + 1;
+  }
+
+  fn visit_lambda(&mut self, _: ExprRef<'eval>, body: ExprRef<'eval>, _: &'eval str) -> Self::Output {
+    self.bits += 2;
+    body.visit(self);
+  }
+
+  fn visit_eval(&mut self, _: ExprRef<'eval>, left: ExprRef<'eval>, right: ExprRef<'eval>) -> Self::Output {
+    self.bits += 2;
+    left.visit(self);
+    right.visit(self);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn church_zero_encodes_as_000010() {
+    let executor = Executor::new();
+    let allocator = Allocator::new();
+    let zero = executor.load_expression(&allocator, r"\f.\x.x").unwrap();
+
+    let mut visitor = PrintVisitor::new("0", "1");
+    zero.visit(&mut visitor);
+    assert_eq!(visitor.into_string(), "000010");
+
+    let mut visitor = BitCountVisitor::new();
+    zero.visit(&mut visitor);
+    assert_eq!(visitor.bits(), 6);
+
+    // Padded with 0's to a full byte
+    let mut visitor = ByteVisitor::new();
+    zero.visit(&mut visitor);
+    assert_eq!(visitor.into_bytes(), [0b0000_1000]);
+  }
+}
@@ -0,0 +1,72 @@
+use clap::{Args, Subcommand};
+use crossterm::style::Stylize;
+use std::path::PathBuf;
+
+use super::executor::Executor;
+
+/// Save or load all definitions as a compact binary file
+#[derive(Args)]
+pub struct EnvArgs {
+  #[clap(subcommand)]
+  command: EnvCommand,
+}
+
+#[derive(Subcommand)]
+enum EnvCommand {
+  /// Load the prelude and code files, then save every definition to a binary file
+  Save {
+    /// Binary file to write
+    output: PathBuf,
+
+    /// List of files to load, in order
+    files: Vec<PathBuf>,
+
+    /// Don't load the prelude, so only the definitions from the files are saved
+    #[clap(long)]
+    no_prelude: bool,
+  },
+
+  /// Load a binary file and print every definition
+  Load {
+    /// Binary file to read
+    file: PathBuf,
+  },
+}
+
+impl EnvArgs {
+  pub fn execute(self) -> super::CommandResult {
+    let executor = Executor::new();
+
+    match self.command {
+      EnvCommand::Save {
+        output,
+        files,
+        no_prelude,
+      } => {
+        if !no_prelude {
+          executor.load_code(crate::PRELUDE, Some("prelude"))?;
+        }
+        for file in files.iter() {
+          executor.load_file(file)?;
+        }
+
+        executor.save_environment(&output)?;
+      },
+
+      EnvCommand::Load { file } => {
+        executor.load_environment(&file)?;
+
+        let all_globals = executor.all_globals().borrow();
+        let max_name_length = all_globals.keys().map(|name| (*name).len()).max().unwrap_or(1);
+        for (name, value) in all_globals.iter() {
+          println!(
+            "{} = {value:#}",
+            format!("{name: <width$}", width = max_name_length).white().bold(),
+          );
+        }
+      },
+    }
+
+    Ok(())
+  }
+}
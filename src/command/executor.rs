@@ -1,28 +1,59 @@
 use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::fmt;
+use std::fs;
 use std::num::NonZero;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use crate::expr::{Allocator, ExprRef, ExprVisitor, UnpackedExpr};
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use serde::Serialize;
+use typed_arena::Arena;
+
+use crate::environment;
+use crate::expr::{Allocator, ExprRef, PathStep, UnpackedExpr};
 use crate::lambda::{EvalExpressionParser as ExpressionParser, ProgramParser, StatementParser};
-use crate::symbol_table::SymbolTable;
+use crate::readable::as_pair;
+use crate::symbol_table::{
+  DEFAULT_MAX_NUMBER, IncludeContext, LambdaLocations, SourceLocation, SymbolTable, extend_numbers,
+};
 
 pub struct Executor<'s> {
   assign_allocator: Allocator,
+  text_data: Arena<String>,
   globals: RefCell<BTreeMap<&'s str, ExprRef<'s>>>,
   numbers: RefCell<Vec<ExprRef<'s>>>,
+  included_files: RefCell<HashSet<PathBuf>>,
+  lambda_locations: RefCell<Option<HashMap<ExprRef<'s>, SourceLocation>>>,
+  max_number: u64,
+  messages_to_stderr: bool,
+  json_messages: bool,
   program_parser: ProgramParser,
   statement_parser: StatementParser,
   expression_parser: ExpressionParser,
 }
 
+impl Default for Executor<'_> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl<'s> Executor<'s> {
   pub fn new() -> Self {
     Self {
       assign_allocator: Allocator::new(),
+      text_data: Arena::new(),
       globals: RefCell::new(BTreeMap::new()),
       numbers: RefCell::new(Vec::new()),
+      included_files: RefCell::new(HashSet::new()),
+      lambda_locations: RefCell::new(None),
+      max_number: DEFAULT_MAX_NUMBER,
+      messages_to_stderr: false,
+      json_messages: false,
       program_parser: ProgramParser::new(),
       statement_parser: StatementParser::new(),
       expression_parser: ExpressionParser::new(),
@@ -30,23 +61,131 @@ impl<'s> Executor<'s> {
   }
 
   #[inline]
-  #[allow(unused)]
   pub fn get_global(&self, name: &str) -> Option<ExprRef<'s>> {
     self.globals.borrow().get(name).cloned()
   }
 
+  /// Find the names of all globals that are structurally equal to the expression
+  pub fn find_equal_globals(&self, expr: ExprRef<'_>) -> Vec<&'s str> {
+    self
+      .globals
+      .borrow()
+      .iter()
+      .filter_map(|(name, value)| value.structurally_eq(expr).then_some(*name))
+      .collect()
+  }
+
   #[inline]
   pub fn all_globals(&self) -> &RefCell<BTreeMap<&'s str, ExprRef<'s>>> {
     &self.globals
   }
 
+  /// Remember where each lambda was written in any code loaded after this, so it can be reported by
+  /// `evaluate_with_profile()`. Only code loaded as a whole program is tracked.
+  pub fn record_lambda_locations(&self) {
+    self.lambda_locations.borrow_mut().get_or_insert_default();
+  }
+
+  /// Largest number literal that can be written in any code loaded after this
+  pub fn set_max_number(&mut self, max_number: u64) {
+    self.max_number = max_number;
+  }
+
+  /// Build every numeral from 0 up to and including `number` ahead of time.
+  /// Number literals in any code loaded later reuse these instead of building them on demand.
+  pub fn seed_numbers(&'s self, number: u64) {
+    extend_numbers(&self.assign_allocator, &mut self.numbers.borrow_mut(), number);
+  }
+
+  /// Print compiler errors and warnings to stderr instead of stdout
+  pub fn set_messages_to_stderr(&mut self, messages_to_stderr: bool) {
+    self.messages_to_stderr = messages_to_stderr;
+  }
+
+  /// Print compiler errors and warnings to stderr as one JSON object per line
+  pub fn set_json_messages(&mut self, json_messages: bool) {
+    self.json_messages = json_messages;
+  }
+
+  fn print_messages(&self, symbol_table: &SymbolTable) {
+    if self.json_messages {
+      symbol_table.eprint_json_messages();
+    } else if self.messages_to_stderr {
+      symbol_table.eprint_messages();
+    } else {
+      symbol_table.print_messages();
+    }
+  }
+
+  /// Evaluate both sides of every `assert` statement, and report an error for each one that doesn't match
+  fn check_assertions(symbol_table: &mut SymbolTable) {
+    symbol_table.check_assertions(|left, right| {
+      let eval_allocator = Allocator::new();
+      let left = Evaluator::new(&eval_allocator, EvalOptions::default()).evaluate(left);
+      let right = Evaluator::new(&eval_allocator, EvalOptions::default()).evaluate(right);
+      if left.structurally_eq(right) {
+        Ok(())
+      } else {
+        Err(format!("{left:#} is not equal to {right:#}"))
+      }
+    });
+  }
+
+  /// Save all globals and cached numbers to a compact binary file
+  pub fn save_environment(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut file = fs::File::create(path)?;
+    environment::write_environment(&mut file, &self.globals.borrow(), &self.numbers.borrow())
+  }
+
+  /// Load globals and numbers from a file written by `save_environment()`, skipping parsing entirely
+  pub fn load_environment(&'s self, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut file = fs::File::open(path)?;
+    let environment = environment::read_environment(&mut file, &self.assign_allocator, &self.text_data)?;
+
+    let mut globals = self.globals.borrow_mut();
+    for (name, expr) in environment.globals {
+      if globals.insert(name, expr).is_some() {
+        return Err(format!("{}: duplicate variable {name}", path.display()).into());
+      }
+    }
+
+    let mut numbers = self.numbers.borrow_mut();
+    if environment.numbers.len() > numbers.len() {
+      *numbers = environment.numbers;
+    }
+
+    Ok(())
+  }
+
+  /// Read and load a code file, returning any statements that might need to be evaluated.
+  /// Any `include` statements are relative to the directory of the file.
+  pub fn load_file(&'s self, path: &Path) -> Result<Vec<ExprRef<'s>>, Box<dyn Error>> {
+    let code = self.text_data.alloc(fs::read_to_string(path)?);
+
+    let full_path = fs::canonicalize(path)?;
+    self.included_files.borrow_mut().insert(full_path.clone());
+
+    self.load_program(code.as_str(), Some(&path.to_string_lossy()), Some(full_path))
+  }
+
   /// Load a code file and return any statements that might need to be evaluated.
   /// Name is just a helpful string for error handling.
   pub fn load_code(&'s self, code: &'s str, name: Option<&str>) -> Result<Vec<ExprRef<'s>>, Box<dyn Error>> {
+    self.load_program(code, name, None)
+  }
+
+  fn load_program(
+    &'s self,
+    code: &'s str,
+    name: Option<&str>,
+    full_path: Option<PathBuf>,
+  ) -> Result<Vec<ExprRef<'s>>, Box<dyn Error>> {
     let name_str = name.map(|n| format!("{n}: ")).unwrap_or_default();
 
     let mut globals = self.globals.borrow_mut();
     let mut numbers = self.numbers.borrow_mut();
+    let mut included_files = self.included_files.borrow_mut();
+    let mut lambda_locations = self.lambda_locations.borrow_mut();
 
     let mut symbol_table = SymbolTable::new(
       &self.assign_allocator,
@@ -54,14 +193,21 @@ impl<'s> Executor<'s> {
       &mut globals,
       &mut numbers,
     );
+    symbol_table.set_max_number(self.max_number);
     symbol_table.set_line_numbers(code);
+    let code = symbol_table.strip_block_comments(code, &self.text_data);
+    symbol_table.set_include_context(IncludeContext::new(&self.text_data, &mut included_files, full_path));
+    if let Some(lambda_locations) = lambda_locations.as_mut() {
+      symbol_table.set_lambda_locations(LambdaLocations::new(lambda_locations, name.map(str::to_string)));
+    }
 
     let results = self
       .program_parser
       .parse(&mut symbol_table, code)
       .map_err(|e| format!("{name_str}parsing error: {e}"))?;
 
-    symbol_table.print_messages();
+    Self::check_assertions(&mut symbol_table);
+    self.print_messages(&symbol_table);
     if symbol_table.has_errors() {
       return Err(format!("{name_str}failed to load code").into());
     }
@@ -81,13 +227,19 @@ impl<'s> Executor<'s> {
     let mut globals = self.globals.borrow_mut();
     let mut numbers = self.numbers.borrow_mut();
 
+    let mut included_files = self.included_files.borrow_mut();
+
     let mut symbol_table = SymbolTable::new(&self.assign_allocator, eval_allocator, &mut globals, &mut numbers);
+    symbol_table.set_max_number(self.max_number);
+    let code = symbol_table.strip_block_comments(code, &self.text_data);
+    symbol_table.set_include_context(IncludeContext::new(&self.text_data, &mut included_files, None));
     let result = self
       .statement_parser
       .parse(&mut symbol_table, code)
       .map_err(|e| format!("parsing error: {e}"))?;
 
-    symbol_table.print_messages();
+    Self::check_assertions(&mut symbol_table);
+    self.print_messages(&symbol_table);
     if symbol_table.has_errors() {
       return Err("failed to evaluate statement".into());
     }
@@ -108,12 +260,14 @@ impl<'s> Executor<'s> {
     let mut numbers = self.numbers.borrow_mut();
 
     let mut symbol_table = SymbolTable::new(&self.assign_allocator, eval_allocator, &mut globals, &mut numbers);
+    symbol_table.set_max_number(self.max_number);
+    let code = symbol_table.strip_block_comments(code, &self.text_data);
     let result = self
       .expression_parser
       .parse(&mut symbol_table, code)
       .map_err(|e| format!("parsing error: {e}"))?;
 
-    symbol_table.print_messages();
+    self.print_messages(&symbol_table);
     if symbol_table.has_errors() {
       return Err("failed to evaluate expression".into());
     }
@@ -126,276 +280,1591 @@ impl<'s> Executor<'s> {
     &self,
     eval_allocator: &'eval Allocator,
     expr: ExprRef<'eval>,
-    show_steps: bool,
+    options: EvalOptions,
   ) -> ExprRef<'eval>
   where
     's: 'eval,
   {
-    Evaluator::new(eval_allocator, show_steps).evaluate(expr)
+    Evaluator::new(eval_allocator, options).evaluate(expr)
+  }
+
+  /// Evaluate an expression, and also return how much work it took
+  pub fn evaluate_with_stats<'eval>(
+    &self,
+    eval_allocator: &'eval Allocator,
+    expr: ExprRef<'eval>,
+    options: EvalOptions,
+  ) -> (ExprRef<'eval>, EvalStats)
+  where
+    's: 'eval,
+  {
+    let mut evaluator = Evaluator::new(eval_allocator, options);
+    let result = evaluator.evaluate(expr);
+    let stats = EvalStats {
+      steps: evaluator.reductions,
+      size: result.size(),
+    };
+
+    (result, stats)
   }
 
-  /// Returns `None` if aborted with Ctrl+C
+  /// Evaluate an expression, and also count how many beta reductions applied each lambda from the source code.
+  ///
+  /// Lambdas are traced back to where they were written using the locations saved by `record_lambda_locations()`.
+  /// Reductions of lambdas that cannot be traced back to the code are counted under `None`.
+  pub fn evaluate_with_profile<'eval>(
+    &self,
+    eval_allocator: &'eval Allocator,
+    expr: ExprRef<'eval>,
+    options: EvalOptions,
+  ) -> (ExprRef<'eval>, HashMap<Option<SourceLocation>, u64>)
+  where
+    's: 'eval,
+  {
+    let mut evaluator = Evaluator::new(eval_allocator, options);
+    evaluator.profile = Some(Profile::default());
+    let result = evaluator.evaluate(expr);
+
+    let lambda_locations = self.lambda_locations.borrow();
+    let lambda_locations: Option<&HashMap<ExprRef<'eval>, SourceLocation>> = lambda_locations.as_ref();
+
+    let mut counts = HashMap::new();
+    for (lambda, count) in evaluator.profile.take().unwrap_or_default().applications {
+      let location = lambda_locations.and_then(|locations| locations.get(&lambda)).cloned();
+      *counts.entry(location).or_default() += count;
+    }
+
+    (result, counts)
+  }
+
+  /// Same as evaluate_with_stats(), but stops with an error if aborted with Ctrl+C
+  pub fn evaluate_with_abort_and_stats<'eval>(
+    &self,
+    eval_allocator: &'eval Allocator,
+    expr: ExprRef<'eval>,
+    options: EvalOptions,
+    abort: &AtomicBool,
+  ) -> Result<(ExprRef<'eval>, EvalStats), EvalError> {
+    let mut evaluator = Evaluator::new(eval_allocator, options);
+    let result = evaluator.evaluate_with_abort(expr, abort)?;
+    let stats = EvalStats {
+      steps: evaluator.reductions,
+      size: result.size(),
+    };
+
+    Ok((result, stats))
+  }
+
+  /// Returns `EvalError::Aborted` if aborted with Ctrl+C
   pub fn evaluate_with_abort<'eval>(
     &self,
     eval_allocator: &'eval Allocator,
     expr: ExprRef<'eval>,
-    show_steps: bool,
+    options: EvalOptions,
     abort: &AtomicBool,
+  ) -> Result<ExprRef<'eval>, EvalError> {
+    Evaluator::new(eval_allocator, options).evaluate_with_abort(expr, abort)
+  }
+
+  /// Evaluate an expression, stopping with an error if any of the limits are reached
+  pub fn evaluate_with_limits<'eval>(
+    &self,
+    eval_allocator: &'eval Allocator,
+    expr: ExprRef<'eval>,
+    options: EvalOptions,
+    limits: EvalLimits,
+  ) -> Result<ExprRef<'eval>, EvalError> {
+    Evaluator::new(eval_allocator, options).evaluate_with_limits(expr, limits)
+  }
+
+  /// Returns `None` if the evaluation takes longer than the duration
+  pub fn evaluate_with_timeout<'eval>(
+    &self,
+    eval_allocator: &'eval Allocator,
+    expr: ExprRef<'eval>,
+    options: EvalOptions,
+    duration: Duration,
   ) -> Option<ExprRef<'eval>> {
-    Evaluator::new(eval_allocator, show_steps).evaluate_with_abort(expr, abort)
+    let limits = EvalLimits {
+      timeout: Some(duration),
+      ..EvalLimits::default()
+    };
+
+    self.evaluate_with_limits(eval_allocator, expr, options, limits).ok()
+  }
+
+  /// Look up a named global and evaluate it, stopping with an error if any of the limits are reached
+  pub fn evaluate_global<'eval>(
+    &self,
+    eval_allocator: &'eval Allocator,
+    name: &str,
+    options: EvalOptions,
+    limits: EvalLimits,
+  ) -> Result<ExprRef<'eval>, EvalError>
+  where
+    's: 'eval,
+  {
+    let expr = self
+      .get_global(name)
+      .ok_or_else(|| EvalError::UnknownGlobal(name.to_string()))?;
+
+    Evaluator::new(eval_allocator, options).evaluate_with_limits(expr, limits)
+  }
+
+  /// Evaluate an expression, using a custom strategy to choose which redex to reduce at each step.
+  /// The normal form in the options is ignored, since the selector decides when to stop.
+  ///
+  /// Returns `EvalError::InvalidRedex` if the selector returns a path that doesn't lead to a redex.
+  pub fn evaluate_with_selector<'eval>(
+    &self,
+    eval_allocator: &'eval Allocator,
+    expr: ExprRef<'eval>,
+    options: EvalOptions,
+    selector: &mut dyn RedexSelector,
+  ) -> Result<ExprRef<'eval>, EvalError> {
+    Evaluator::with_selector(eval_allocator, options, Box::new(selector))
+      .evaluate_with_limits(expr, EvalLimits::default())
+  }
+
+  /// Lazily evaluate the first few elements of a list, which might be infinite.
+  ///
+  /// With normal order, only the list cells are reduced to head normal form, so the rest of the list is never
+  /// evaluated. Each element is then evaluated using the options. The limits apply separately to each cell and
+  /// each element, so an element that doesn't finish is reported without stopping the rest of the list.
+  ///
+  /// The result only counts as a list if it starts with a cons cell, since the empty list `\x y.y` is also
+  /// `false` and `0`. Anything else finishes evaluating from where the list check left off.
+  pub fn evaluate_list_prefix<'eval>(
+    &self,
+    eval_allocator: &'eval Allocator,
+    expr: ExprRef<'eval>,
+    options: EvalOptions,
+    count: u64,
+    limits: EvalLimits,
+  ) -> Result<ListEvaluation<'eval>, EvalError> {
+    // The other strategies always evaluate the arguments, so they can't leave the rest of the list unevaluated
+    let cell_options = match options.strategy {
+      ReductionStrategy::NormalOrder => EvalOptions {
+        normal_form: NormalForm::Head,
+        eta: false,
+        ..options
+      },
+      _ => EvalOptions { eta: false, ..options },
+    };
+    let evaluate_cell = |expr| Evaluator::new(eval_allocator, cell_options).evaluate_with_limits(expr, limits);
+    let evaluate_full = |expr| Evaluator::new(eval_allocator, options).evaluate_with_limits(expr, limits);
+
+    let first_cell = evaluate_cell(expr)?;
+    let Some((mut head, mut tail)) = as_pair(first_cell, eval_allocator) else {
+      return Ok(ListEvaluation::NotAList(evaluate_full(first_cell)?));
+    };
+
+    let mut elements = Vec::new();
+    loop {
+      if elements.len() as u64 == count {
+        return Ok(ListEvaluation::List(ListPrefix {
+          elements,
+          end: ListEnd::More,
+        }));
+      }
+      elements.push(evaluate_full(head));
+
+      let cell = evaluate_cell(tail)?;
+      if is_nil(cell) {
+        return Ok(ListEvaluation::List(ListPrefix {
+          elements,
+          end: ListEnd::Nil,
+        }));
+      }
+
+      (head, tail) = match as_pair(cell, eval_allocator) {
+        Some(pair) => pair,
+        None => {
+          return Ok(ListEvaluation::List(ListPrefix {
+            elements,
+            end: ListEnd::Improper(evaluate_full(cell)?),
+          }));
+        },
+      };
+    }
   }
 }
 
-struct Shift<'eval> {
-  eval_allocator: &'eval Allocator,
-  cutoff: u64,
-  offset: i64,
+/// Result of [Executor::evaluate_list_prefix]
+#[derive(Debug, Clone)]
+pub enum ListEvaluation<'eval> {
+  List(ListPrefix<'eval>),
+
+  /// Expression doesn't start with a cons cell, so this is its usual result instead
+  NotAList(ExprRef<'eval>),
+}
+
+/// First few elements of a list
+#[derive(Debug, Clone)]
+pub struct ListPrefix<'eval> {
+  /// Evaluated elements, or the error if one of them reached the limits
+  pub elements: Vec<Result<ExprRef<'eval>, EvalError>>,
+
+  /// What comes after the elements
+  pub end: ListEnd<'eval>,
+}
+
+/// Where a [ListPrefix] stopped
+#[derive(Debug, Clone)]
+pub enum ListEnd<'eval> {
+  /// Reached the empty list, so these are all of the elements
+  Nil,
+
+  /// List has more elements after these
+  More,
+
+  /// List ended with something other than a cons cell or the empty list, which is fully evaluated
+  Improper(ExprRef<'eval>),
+}
+
+/// The empty list `nil = \x y.y`
+fn is_nil(expr: ExprRef<'_>) -> bool {
+  use UnpackedExpr::*;
+
+  match expr.unpack() {
+    Lambda { body, .. } => match body.unpack() {
+      Lambda { body, .. } => matches!(body.unpack(), Term { de_bruijn_index } if de_bruijn_index.get() == 1),
+      _ => false,
+    },
+    _ => false,
+  }
+}
+
+/// Settings that control how an expression gets evaluated
+#[derive(Debug, Clone, Copy)]
+pub struct EvalOptions {
+  /// Print the individual reduction steps to stderr
+  pub show_steps: bool,
+
+  /// How to format the reduction steps
+  pub trace_format: TraceFormat,
+
+  /// How far to reduce the expression. Only used by the normal order strategy.
+  pub normal_form: NormalForm,
+
+  /// Order to reduce the redexes in
+  pub strategy: ReductionStrategy,
+
+  /// Stop with an error if the expression ever repeats, since the evaluation would loop forever.
+  /// Only recent expressions are remembered, and only evaluations that can fail check for cycles.
+  pub detect_cycles: bool,
+
+  /// Number of reduction steps between checks of the abort flag.
+  /// Smaller values stop sooner after an abort, larger values spend less time checking.
+  pub abort_check_interval: NonZero<u64>,
+
+  /// Remember the result of every reduced redex, so a redex shared between
+  /// several parts of the expression only gets substituted once.
+  /// Off by default: the cache grows with every step, and most reductions create new pointers that never hit it.
+  pub memoize: bool,
+
+  /// Print a dot to stderr after this many reduction steps, so long evaluations show they are still running.
+  /// Ignored while the individual steps are printed.
+  pub progress: Option<NonZero<u64>>,
+
+  /// Apply eta reduction once the beta reductions are finished, so `\x.(f x)` becomes `f`
+  pub eta: bool,
 }
 
-impl<'eval> Shift<'eval> {
-  pub fn new(eval_allocator: &'eval Allocator, cutoff: u64, offset: i64) -> Self {
+impl EvalOptions {
+  pub const DEFAULT_ABORT_CHECK_INTERVAL: NonZero<u64> = NonZero::new(100).unwrap();
+
+  pub fn new(show_steps: bool, trace_format: TraceFormat) -> Self {
     Self {
-      eval_allocator,
-      cutoff,
-      offset,
+      show_steps,
+      trace_format,
+      normal_form: NormalForm::Full,
+      strategy: ReductionStrategy::NormalOrder,
+      detect_cycles: false,
+      abort_check_interval: Self::DEFAULT_ABORT_CHECK_INTERVAL,
+      memoize: false,
+      progress: None,
+      eta: false,
     }
   }
 }
 
-impl<'eval> ExprVisitor<'eval> for Shift<'eval> {
-  type Output = ExprRef<'eval>;
+impl Default for EvalOptions {
+  fn default() -> Self {
+    Self::new(false, TraceFormat::default())
+  }
+}
 
-  fn visit_term(&mut self, expr: ExprRef<'eval>, de_bruijn_index: NonZero<u64>) -> Self::Output {
-    if de_bruijn_index.get() < self.cutoff {
-      expr // Optimization: avoid an extra allocation
-    } else {
-      let new_de_bruijn_index = NonZero::new((de_bruijn_index.get() as i64 + self.offset) as u64);
-      self.eval_allocator.new_term(new_de_bruijn_index.expect("index is 0"))
+/// Number of reduction steps between checks of the timeout
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// Stop evaluating an expression early if it takes too long
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvalLimits {
+  /// Maximum number of beta reductions
+  pub max_steps: Option<u64>,
+
+  /// Maximum wall-clock time
+  pub timeout: Option<Duration>,
+
+  /// Maximum number of nodes in the expression at any step.
+  /// Checking this walks the whole expression after every step, so it's slower than the other limits.
+  pub max_size: Option<u64>,
+}
+
+/// Evaluation could not finish
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+  /// No global exists with this name
+  UnknownGlobal(String),
+
+  /// Expression was not fully evaluated after this many reduction steps
+  StepLimit(u64),
+
+  /// Expression was not fully evaluated within this amount of time
+  Timeout(Duration),
+
+  /// Expression grew past this many nodes before it was fully evaluated
+  SizeLimit(u64),
+
+  /// Evaluation was aborted with Ctrl+C
+  Aborted,
+
+  /// Expression came back to a previous state, so it never finishes
+  Cycle,
+
+  /// Redex selector returned a path that doesn't lead to a redex in the expression
+  InvalidRedex(Vec<PathStep>),
+}
+
+impl fmt::Display for EvalError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UnknownGlobal(name) => write!(f, "unknown global: {name}"),
+      Self::StepLimit(steps) => write!(f, "evaluation did not finish within {steps} steps"),
+      Self::Timeout(duration) => write!(f, "evaluation did not finish within {duration:?}"),
+      Self::SizeLimit(size) => write!(f, "expression grew larger than {size} nodes"),
+      Self::Aborted => write!(f, "evaluation was aborted"),
+      Self::Cycle => write!(f, "cycle detected, expression does not normalize"),
+      Self::InvalidRedex(path) => write!(f, "redex selector returned a path that isn't a redex: {path:?}"),
     }
   }
+}
 
-  fn visit_lambda(&mut self, expr: ExprRef<'eval>, body: ExprRef<'eval>, parameter_name: &'eval str) -> Self::Output {
-    self.cutoff += 1;
-    let new_body = body.visit(self);
-    self.cutoff -= 1;
+impl Error for EvalError {}
 
-    if new_body == body {
-      expr // Optimization: avoid an extra allocation
-    } else {
-      self.eval_allocator.new_lambda(parameter_name, new_body)
+/// Information about a finished evaluation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalStats {
+  /// Number of beta reductions performed
+  pub steps: u64,
+
+  /// Number of nodes in the final expression
+  pub size: u64,
+}
+
+/// How far to reduce an expression before stopping
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NormalForm {
+  /// Reduce every redex, including inside lambda bodies and arguments
+  #[default]
+  Full,
+
+  /// Only reduce until the head of the expression is a variable: `\x1 ... xn.(x a1 ... am)`.
+  ///
+  /// Unlike weak head normal form, this does reduce under the leading lambdas.
+  /// But unlike full normal form, the arguments `a1 ... am` are left unevaluated.
+  Head,
+}
+
+/// Order to reduce the redexes in, which decides whether an evaluation finishes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum ReductionStrategy {
+  /// Leftmost, outermost redex first, which always finds the normal form if one exists
+  #[default]
+  #[cfg_attr(feature = "cli", value(name = "normal"))]
+  NormalOrder,
+
+  /// Leftmost, innermost redex first, so arguments are fully reduced before being substituted
+  #[cfg_attr(feature = "cli", value(name = "applicative"))]
+  ApplicativeOrder,
+
+  /// Applicative order that never reduces inside of a lambda
+  #[cfg_attr(feature = "cli", value(name = "cbv"))]
+  CallByValue,
+}
+
+/// Output format for the individual reduction steps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum TraceFormat {
+  /// Human-readable `step: expression` lines
+  #[default]
+  Text,
+
+  /// One JSON object per line
+  Json,
+
+  /// Comma-separated values with a header row
+  Csv,
+}
+
+/// Chooses which redex gets reduced next, which decides the evaluation strategy
+pub trait RedexSelector {
+  /// Find the path from the root of the expression to the next redex `(\x.body argument)`.
+  /// Returns `None` once the expression is fully evaluated.
+  fn select(&mut self, expr: ExprRef<'_>) -> Option<Vec<PathStep>>;
+}
+
+impl<T: RedexSelector + ?Sized> RedexSelector for &mut T {
+  fn select(&mut self, expr: ExprRef<'_>) -> Option<Vec<PathStep>> {
+    (**self).select(expr)
+  }
+}
+
+/// Always reduce the leftmost, outermost redex first, including inside lambda bodies.
+/// This reaches the full normal form whenever one exists.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NormalOrder;
+
+impl RedexSelector for NormalOrder {
+  fn select(&mut self, expr: ExprRef<'_>) -> Option<Vec<PathStep>> {
+    use UnpackedExpr::*;
+
+    // Depth-first search, where each entry remembers the step that leads to it from its parent
+    let mut path = Vec::new();
+    let mut to_search = vec![(expr, 0, None)];
+    while let Some((expr, depth, step)) = to_search.pop() {
+      path.truncate(depth);
+      path.extend(step);
+
+      match expr.unpack() {
+        Term { .. } => {},
+        Lambda { body, .. } => to_search.push((body, path.len(), Some(PathStep::Body))),
+        Eval { left, .. } if matches!(left.unpack(), Lambda { .. }) => return Some(path),
+        Eval { left, right } => {
+          // Search the left side first, then backtrack to the right side
+          to_search.push((right, path.len(), Some(PathStep::Right)));
+          to_search.push((left, path.len(), Some(PathStep::Left)));
+        },
+      }
     }
+
+    None
   }
+}
 
-  fn visit_eval(&mut self, expr: ExprRef<'eval>, left: ExprRef<'eval>, right: ExprRef<'eval>) -> Self::Output {
-    let new_left = left.visit(self);
-    let new_right = right.visit(self);
+/// Only reduce the head redex, going under any leading lambdas but never into the arguments.
+/// See [NormalForm::Head].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeadOrder;
 
-    if new_left == left && new_right == right {
-      expr // Optimization: avoid an extra allocation
-    } else {
-      self.eval_allocator.new_eval(new_left, new_right)
+impl RedexSelector for HeadOrder {
+  fn select(&mut self, mut expr: ExprRef<'_>) -> Option<Vec<PathStep>> {
+    use UnpackedExpr::*;
+
+    let mut path = Vec::new();
+    loop {
+      match expr.unpack() {
+        Term { .. } => return None,
+        Lambda { body, .. } => {
+          path.push(PathStep::Body);
+          expr = body;
+        },
+        Eval { left, .. } if matches!(left.unpack(), Lambda { .. }) => return Some(path),
+
+        // Walk down the left spine of the application to find the head
+        Eval { left, .. } => {
+          path.push(PathStep::Left);
+          expr = left;
+        },
+      }
     }
   }
 }
 
+/// Always reduce the leftmost, innermost redex first, so the arguments and lambda bodies of a redex
+/// are fully reduced before it. This may never finish on expressions that normal order can evaluate.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApplicativeOrder;
+
+impl RedexSelector for ApplicativeOrder {
+  fn select(&mut self, expr: ExprRef<'_>) -> Option<Vec<PathStep>> {
+    let mut path = Vec::new();
+    find_innermost_redex(expr, &mut path, true).then_some(path)
+  }
+}
+
+/// Same as applicative order, but never reduces inside of a lambda.
+/// This stops at weak normal form, where any lambda is a finished value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallByValue;
+
+impl RedexSelector for CallByValue {
+  fn select(&mut self, expr: ExprRef<'_>) -> Option<Vec<PathStep>> {
+    let mut path = Vec::new();
+    find_innermost_redex(expr, &mut path, false).then_some(path)
+  }
+}
+
+/// Extend the path to the leftmost, innermost redex. Returns false if there are no redexes.
+///
+/// This is the first redex in a post-order search, since the children of every node are searched before the node
+/// itself. The search uses an explicit stack, so very deep expressions can't overflow the call stack.
+fn find_innermost_redex(expr: ExprRef<'_>, path: &mut Vec<PathStep>, under_lambdas: bool) -> bool {
+  use UnpackedExpr::*;
+
+  // Each entry remembers the step that leads to it from its parent, and whether its children were already searched
+  let start = path.len();
+  let mut to_search = vec![(expr, start, None, false)];
+  while let Some((expr, depth, step, searched_children)) = to_search.pop() {
+    path.truncate(depth);
+    path.extend(step);
+
+    if searched_children {
+      // No redexes inside, so this is the innermost redex
+      if matches!(expr.unpack(), Eval { left, .. } if matches!(left.unpack(), Lambda { .. })) {
+        return true;
+      }
+      continue;
+    }
+
+    to_search.push((expr, depth, step, true));
+    match expr.unpack() {
+      Term { .. } => {},
+      Lambda { body, .. } if under_lambdas => to_search.push((body, path.len(), Some(PathStep::Body), false)),
+      Lambda { .. } => {},
+      Eval { left, right } => {
+        // Search the left side first, then the right side
+        to_search.push((right, path.len(), Some(PathStep::Right), false));
+        to_search.push((left, path.len(), Some(PathStep::Left), false));
+      },
+    }
+  }
+
+  path.truncate(start);
+  false
+}
+
+/// Test if the expression has no redexes left, without reducing or allocating anything
+#[cfg(feature = "cli")]
+pub fn is_normal_form(expr: ExprRef<'_>) -> bool {
+  use UnpackedExpr::*;
+
+  let mut to_search = vec![expr];
+  while let Some(expr) = to_search.pop() {
+    match expr.unpack() {
+      Term { .. } => {},
+      Lambda { body, .. } => to_search.push(body),
+      Eval { left, .. } if matches!(left.unpack(), Lambda { .. }) => return false,
+      Eval { left, right } => {
+        to_search.push(right);
+        to_search.push(left);
+      },
+    }
+  }
+
+  true
+}
+
+/// Add `offset` to every term that is free relative to `cutoff`.
+///
+/// Beta reduction never shifts a bound variable to 0, so the only way this fails is if a free variable
+/// grows past the largest index that can be stored. That would silently change which lambda the term
+/// references, so it panics instead.
+fn shift<'eval>(eval_allocator: &'eval Allocator, expr: ExprRef<'eval>, cutoff: u64, offset: i64) -> ExprRef<'eval> {
+  expr
+    .shift(eval_allocator, cutoff, offset)
+    .unwrap_or_else(|e| panic!("invalid shift during reduction: {e}"))
+}
+
+/// Substitutes a value for the variable bound by the lambda being reduced
 struct Replace<'eval> {
   eval_allocator: &'eval Allocator,
-  target: u64,
   default_expr: ExprRef<'eval>,
-  offsets: HashMap<u64, ExprRef<'eval>>,
+  offsets: BTreeMap<u64, ExprRef<'eval>>,
+  shift_is_identity: bool,
 }
 
 impl<'eval> Replace<'eval> {
   pub fn new(eval_allocator: &'eval Allocator, new_value: ExprRef<'eval>) -> Self {
     Self {
       eval_allocator,
-      target: 1,
       default_expr: new_value,
-      offsets: HashMap::from([(1, new_value)]),
+      offsets: BTreeMap::from([(1, new_value)]),
+      shift_is_identity: false,
     }
   }
 
-  fn get_offset_expr(&mut self, offset: u64) -> ExprRef<'eval> {
-    *self.offsets.entry(offset).or_insert_with(|| {
-      self
-        .default_expr
-        .visit(&mut Shift::new(self.eval_allocator, 1, (offset as i64) - 1))
+  /// Replace every term that references the outermost lambda of the body, shifting the value to match
+  /// the number of lambdas it ends up inside of
+  pub fn replace(&mut self, body: ExprRef<'eval>) -> ExprRef<'eval> {
+    let eval_allocator = self.eval_allocator;
+    body.map_terms(eval_allocator, |term, de_bruijn_index, depth| {
+      let target = depth + 1;
+      if de_bruijn_index.get() == target {
+        self.get_offset_expr(target)
+      } else {
+        term // Optimization: avoid an extra allocation
+      }
     })
   }
-}
 
-impl<'eval> ExprVisitor<'eval> for Replace<'eval> {
-  type Output = ExprRef<'eval>;
+  fn get_offset_expr(&mut self, offset: u64) -> ExprRef<'eval> {
+    if self.shift_is_identity {
+      return self.default_expr; // Shifting never changes an expression without free variables
+    }
+
+    if let Some(expr) = self.offsets.get(&offset) {
+      return *expr;
+    }
+
+    // Shifting by `a` and then by `b` is the same as shifting by `a + b`,
+    //  so start from the closest offset that has already been computed.
+    //  Offset 1 is always in the map, so there is always a smaller offset.
+    let (base_offset, base_expr) = self
+      .offsets
+      .range(..offset)
+      .next_back()
+      .map(|(o, e)| (*o, *e))
+      .expect("offset 1 is missing");
 
-  fn visit_term(&mut self, expr: ExprRef<'eval>, de_bruijn_index: NonZero<u64>) -> Self::Output {
-    if de_bruijn_index.get() == self.target {
-      self.get_offset_expr(self.target)
-    } else {
-      expr // Optimization: avoid an extra allocation
+    let new_expr = shift(self.eval_allocator, base_expr, 1, (offset - base_offset) as i64);
+    if new_expr == base_expr && base_expr == self.default_expr {
+      self.shift_is_identity = true;
     }
+
+    self.offsets.insert(offset, new_expr);
+    new_expr
   }
+}
+
+struct Evaluator<'eval, 'sel> {
+  eval_allocator: &'eval Allocator,
+  options: EvalOptions,
+  selector: Box<dyn RedexSelector + 'sel>,
+  something_changed: bool,
+  reductions: u64,
+  profile: Option<Profile<'eval>>,
+  history: Option<History<'eval>>,
+  memo: Option<HashMap<ExprRef<'eval>, ExprRef<'eval>>>,
+  printed_progress: bool,
 
-  fn visit_lambda(&mut self, expr: ExprRef<'eval>, body: ExprRef<'eval>, parameter_name: &'eval str) -> Self::Output {
-    self.target += 1;
-    let new_body = body.visit(self);
-    self.target -= 1;
+  /// Reused by every reduce_at() so each step doesn't allocate a new buffer
+  ancestors: Vec<ExprRef<'eval>>,
+}
 
-    if new_body == body {
-      expr // Optimization: avoid an extra allocation
-    } else {
-      self.eval_allocator.new_lambda(parameter_name, new_body)
+/// Number of recent expressions remembered when detecting cycles
+const CYCLE_HISTORY_LENGTH: usize = 10_000;
+
+/// Recent expressions seen during an evaluation, compared by structure instead of by pointer
+#[derive(Default)]
+struct History<'eval> {
+  seen: HashMap<u64, ExprRef<'eval>>,
+  order: VecDeque<u64>,
+}
+
+impl<'eval> History<'eval> {
+  /// Remember the expression, returning true if an identical one was already seen
+  fn is_repeated(&mut self, expr: ExprRef<'eval>) -> bool {
+    let hash = expr.structural_hash();
+    if let Some(previous) = self.seen.get(&hash)
+      && previous.structurally_eq(expr)
+    {
+      return true;
     }
+
+    if self.seen.insert(hash, expr).is_none() {
+      self.order.push_back(hash);
+    }
+    if self.order.len() > CYCLE_HISTORY_LENGTH
+      && let Some(oldest) = self.order.pop_front()
+    {
+      self.seen.remove(&oldest);
+    }
+
+    false
   }
+}
 
-  fn visit_eval(&mut self, expr: ExprRef<'eval>, left: ExprRef<'eval>, right: ExprRef<'eval>) -> Self::Output {
-    let new_left = left.visit(self);
-    let new_right = right.visit(self);
+/// Counts how many times each lambda gets applied during an evaluation
+#[derive(Default)]
+struct Profile<'eval> {
+  /// Lambdas rebuilt during evaluation mapped back to the original lambda they came from.
+  /// Only lambdas rebuilt at the top of a reduced redex or along the path to a redex are tracked.
+  origins: HashMap<ExprRef<'eval>, ExprRef<'eval>>,
+  applications: HashMap<ExprRef<'eval>, u64>,
+}
 
-    if new_left == left && new_right == right {
-      expr // Optimization: avoid an extra allocation
-    } else {
-      self.eval_allocator.new_eval(new_left, new_right)
+impl<'eval> Profile<'eval> {
+  fn origin(&self, lambda: ExprRef<'eval>) -> ExprRef<'eval> {
+    self.origins.get(&lambda).copied().unwrap_or(lambda)
+  }
+
+  fn rebuilt(&mut self, old_lambda: ExprRef<'eval>, new_lambda: ExprRef<'eval>) {
+    if old_lambda != new_lambda {
+      self.origins.insert(new_lambda, self.origin(old_lambda));
     }
   }
 }
 
-struct Evaluator<'eval> {
-  eval_allocator: &'eval Allocator,
-  show_steps: bool,
-  something_changed: bool,
+impl Drop for Evaluator<'_, '_> {
+  /// Finish the line of progress dots, even if the evaluation stopped with an error
+  fn drop(&mut self) {
+    if self.printed_progress {
+      eprintln!();
+    }
+  }
+}
+
+impl<'eval> Evaluator<'eval, 'static> {
+  /// Choose the redexes based on the strategy and normal form in the options
+  pub fn new(eval_allocator: &'eval Allocator, options: EvalOptions) -> Self {
+    let selector: Box<dyn RedexSelector> = match (options.strategy, options.normal_form) {
+      (ReductionStrategy::NormalOrder, NormalForm::Full) => Box::new(NormalOrder),
+      (ReductionStrategy::NormalOrder, NormalForm::Head) => Box::new(HeadOrder),
+      (ReductionStrategy::ApplicativeOrder, _) => Box::new(ApplicativeOrder),
+      (ReductionStrategy::CallByValue, _) => Box::new(CallByValue),
+    };
+
+    Evaluator::with_selector(eval_allocator, options, selector)
+  }
 }
 
-impl<'eval> Evaluator<'eval> {
-  pub fn new(eval_allocator: &'eval Allocator, show_steps: bool) -> Self {
+impl<'eval, 'sel> Evaluator<'eval, 'sel> {
+  pub fn with_selector(
+    eval_allocator: &'eval Allocator,
+    options: EvalOptions,
+    selector: Box<dyn RedexSelector + 'sel>,
+  ) -> Self {
     Self {
       eval_allocator,
-      show_steps,
+      options,
+      selector,
       something_changed: false,
+      reductions: 0,
+      profile: None,
+      history: options.detect_cycles.then(History::default),
+      memo: options.memoize.then(HashMap::new),
+      printed_progress: false,
+      ancestors: Vec::new(),
+    }
+  }
+
+  /// Print a dot to stderr every time the progress interval is reached
+  fn print_progress(&mut self, step: u64) {
+    if let Some(interval) = self.options.progress
+      && !self.options.show_steps
+      && step > 0
+      && step.is_multiple_of(interval.get())
+    {
+      eprint!(".");
+      self.printed_progress = true;
+    }
+  }
+
+  /// Print a single reduction step to stderr using the configured trace format
+  fn print_step(&self, step: u64, expr: ExprRef<'eval>, redex: Option<&[PathStep]>) {
+    eprintln!("{}", self.format_step(step, expr, redex));
+  }
+
+  /// Format a single reduction step, which is more than one line for the first step of a CSV trace.
+  /// The text format wraps the redex that gets reduced next in square brackets.
+  fn format_step(&self, step: u64, expr: ExprRef<'eval>, redex: Option<&[PathStep]>) -> String {
+    match self.options.trace_format {
+      TraceFormat::Text => match redex {
+        Some(path) => format!("{step}: {:#}", expr.display_highlighted(path)),
+        None => format!("{step}: {expr:#}"),
+      },
+      TraceFormat::Json => {
+        let expression = format!("{expr:#}");
+        let trace_step = TraceStep {
+          step,
+          size: expr.size(),
+          expression: &expression,
+        };
+        serde_json::to_string(&trace_step).expect("trace step can always be serialized")
+      },
+      TraceFormat::Csv => {
+        let header = if step == 0 { "step,size,expression\n" } else { "" };
+        let expression = format!("{expr:#}").replace('"', "\"\"");
+        format!("{header}{step},{},\"{expression}\"", expr.size())
+      },
     }
   }
 
-  /// Recursively evaluate the lambda expression
+  /// Evaluate the lambda expression until the selector runs out of redexes.
+  /// Only used with the built-in selectors, which always return a valid path.
   pub fn evaluate(&mut self, mut expr: ExprRef<'eval>) -> ExprRef<'eval> {
     for step in 0u64.. {
-      if self.show_steps {
-        eprintln!("{step}: {expr:#}");
+      let redex = self.selector.select(expr);
+      if self.options.show_steps {
+        self.print_step(step, expr, redex.as_deref());
       }
+      self.print_progress(step);
 
       self.something_changed = false;
-      expr = self.evaluate_strong(expr);
+      expr = self
+        .evaluate_step(expr, redex.as_deref())
+        .expect("built-in redex selectors always return a valid path");
 
       if !self.something_changed {
         break;
       }
     }
 
-    expr
+    self.finish(expr)
   }
 
   /// Same as evaluate(), but has an atomic boolean that can be used to abort early by setting to `true`
-  pub fn evaluate_with_abort(&mut self, mut expr: ExprRef<'eval>, abort: &AtomicBool) -> Option<ExprRef<'eval>> {
+  pub fn evaluate_with_abort(
+    &mut self,
+    mut expr: ExprRef<'eval>,
+    abort: &AtomicBool,
+  ) -> Result<ExprRef<'eval>, EvalError> {
     for step in 0u64.. {
-      if self.show_steps {
-        eprintln!("{step}: {expr:#}");
+      let redex = self.selector.select(expr);
+      if self.options.show_steps {
+        self.print_step(step, expr, redex.as_deref());
       }
+      self.print_progress(step);
 
-      if abort.load(Ordering::Relaxed) {
-        return None;
+      if step % self.options.abort_check_interval.get() == 0 && abort.load(Ordering::Relaxed) {
+        return Err(EvalError::Aborted);
       }
 
+      self.check_for_cycle(expr)?;
+
       self.something_changed = false;
-      expr = self.evaluate_strong(expr);
+      expr = self.evaluate_step(expr, redex.as_deref())?;
 
       if !self.something_changed {
         break;
       }
     }
 
-    Some(expr)
+    Ok(self.finish(expr))
   }
 
-  /// Attempts to evaluate the body of a lambda expression
-  fn evaluate_strong(&mut self, expr: ExprRef<'eval>) -> ExprRef<'eval> {
-    use UnpackedExpr::*;
+  /// Same as evaluate(), but returns an error once any of the limits are reached
+  pub fn evaluate_with_limits(
+    &mut self,
+    mut expr: ExprRef<'eval>,
+    limits: EvalLimits,
+  ) -> Result<ExprRef<'eval>, EvalError> {
+    let start_time = Instant::now();
+    for step in 0u64.. {
+      let redex = self.selector.select(expr);
+      if self.options.show_steps {
+        self.print_step(step, expr, redex.as_deref());
+      }
+      self.print_progress(step);
 
-    match expr.unpack() {
-      Term { .. } => expr,
-
-      Lambda { body, parameter_name } => {
-        let new_body = self.evaluate_strong(body);
-        if new_body == body {
-          expr // Optimization: avoid an extra allocation
-        } else {
-          self.eval_allocator.new_lambda(parameter_name, new_body)
-        }
-      },
+      // Reading the clock is slow compared to a reduction step
+      if let Some(timeout) = limits.timeout
+        && step % TIMEOUT_CHECK_INTERVAL == 0
+        && start_time.elapsed() > timeout
+      {
+        return Err(EvalError::Timeout(timeout));
+      }
 
-      Eval { left, right } => {
-        let new_left = self.evaluate_weak(left);
-        if new_left != left {
-          return self.eval_allocator.new_eval(new_left, right);
-        }
+      self.check_for_cycle(expr)?;
 
-        match new_left.unpack() {
-          Term { .. } | Eval { .. } => {
-            let new_right = self.evaluate_strong(right);
-            if new_left == left && new_right == right {
-              expr // Optimization: avoid an extra allocation
-            } else {
-              self.eval_allocator.new_eval(new_left, new_right)
-            }
-          },
+      self.something_changed = false;
+      expr = self.evaluate_step(expr, redex.as_deref())?;
 
-          Lambda { body, .. } => {
-            self.something_changed = true;
+      if !self.something_changed {
+        break;
+      }
 
-            let shifted_right = right.visit(&mut Shift::new(self.eval_allocator, 1, 1));
-            body
-              .visit(&mut Replace::new(self.eval_allocator, shifted_right))
-              .visit(&mut Shift::new(self.eval_allocator, 1, -1))
-            // No need to recurse ... next loop iteration will attempt the substitution
-          },
-        }
-      },
+      if let Some(max_steps) = limits.max_steps
+        && self.reductions > max_steps
+      {
+        return Err(EvalError::StepLimit(max_steps));
+      }
+
+      if let Some(max_size) = limits.max_size
+        && expr.size() > max_size
+      {
+        return Err(EvalError::SizeLimit(max_size));
+      }
+    }
+
+    Ok(self.finish(expr))
+  }
+
+  /// Apply any normalization that happens after the last beta reduction
+  fn finish(&self, expr: ExprRef<'eval>) -> ExprRef<'eval> {
+    if self.options.eta {
+      expr.eta_reduce(self.eval_allocator)
+    } else {
+      expr
+    }
+  }
+
+  fn check_for_cycle(&mut self, expr: ExprRef<'eval>) -> Result<(), EvalError> {
+    if let Some(history) = self.history.as_mut()
+      && history.is_repeated(expr)
+    {
+      return Err(EvalError::Cycle);
+    }
+
+    Ok(())
+  }
+
+  /// Reduce the redex at the path picked by the selector, if there is one
+  fn evaluate_step(&mut self, expr: ExprRef<'eval>, redex: Option<&[PathStep]>) -> Result<ExprRef<'eval>, EvalError> {
+    match redex {
+      None => Ok(expr),
+      Some(path) => self.reduce_at(expr, path),
     }
   }
 
-  /// Lambda expression is left as lazily evaluated
-  fn evaluate_weak(&mut self, expr: ExprRef<'eval>) -> ExprRef<'eval> {
+  /// Follow the path to the redex, then rebuild the expression around the reduced result.
+  /// The path is followed using a loop instead of recursion, so deep expressions can't overflow the stack.
+  /// Returns an error if the path doesn't match the expression or doesn't end at a redex.
+  fn reduce_at(&mut self, expr: ExprRef<'eval>, path: &[PathStep]) -> Result<ExprRef<'eval>, EvalError> {
     use UnpackedExpr::*;
 
-    match expr.unpack() {
-      Term { .. } => expr,
+    let invalid_redex = || EvalError::InvalidRedex(path.to_vec());
 
-      Lambda { .. } => expr, // Lazily evaluated
+    let mut ancestors = std::mem::take(&mut self.ancestors);
+    ancestors.clear();
+    let mut redex = expr;
+    for step in path {
+      ancestors.push(redex);
+      redex = match (step, redex.unpack()) {
+        (PathStep::Body, Lambda { body, .. }) => body,
+        (PathStep::Left, Eval { left, .. }) => left,
+        (PathStep::Right, Eval { right, .. }) => right,
+        _ => return Err(invalid_redex()),
+      };
+    }
 
-      Eval { left, right } => {
-        let new_left = self.evaluate_weak(left);
-        if new_left != left {
-          return self.eval_allocator.new_eval(new_left, right);
-        }
+    let Eval { left, right } = redex.unpack() else {
+      return Err(invalid_redex());
+    };
+    let Lambda { body, .. } = left.unpack() else {
+      return Err(invalid_redex());
+    };
 
-        match new_left.unpack() {
-          Term { .. } | Eval { .. } => {
-            let new_right = self.evaluate_strong(right);
-            if new_left == left && new_right == right {
-              expr // Optimization: avoid an extra allocation
-            } else {
-              self.eval_allocator.new_eval(new_left, new_right)
-            }
-          },
+    let mut result = self.memoized_beta_reduce(redex, body, right);
+    if let Some(profile) = self.profile.as_mut() {
+      *profile.applications.entry(profile.origin(left)).or_default() += 1;
+      if matches!((body.unpack(), result.unpack()), (Lambda { .. }, Lambda { .. })) {
+        profile.rebuilt(body, result);
+      }
+    }
+
+    // Rebuild from the redex back up to the root
+    for (parent, step) in ancestors.drain(..).zip(path).rev() {
+      result = match (step, parent.unpack()) {
+        (PathStep::Body, Lambda { parameter_name, .. }) => {
+          let new_lambda = self.eval_allocator.new_lambda(parameter_name, result);
+          if let Some(profile) = self.profile.as_mut() {
+            profile.rebuilt(parent, new_lambda);
+          }
+          new_lambda
+        },
+        (PathStep::Left, Eval { right, .. }) => self.eval_allocator.new_eval(result, right),
+        (PathStep::Right, Eval { left, .. }) => self.eval_allocator.new_eval(left, result),
+        _ => unreachable!("path was already followed"),
+      };
+    }
+
+    self.ancestors = ancestors;
+    Ok(result)
+  }
+
+  /// Same as beta_reduce(), but reuses the result if this exact redex was already reduced.
+  /// Expressions never change inside the allocator, so cached results are always valid.
+  fn memoized_beta_reduce(
+    &mut self,
+    redex: ExprRef<'eval>,
+    body: ExprRef<'eval>,
+    argument: ExprRef<'eval>,
+  ) -> ExprRef<'eval> {
+    let Some(memo) = self.memo.as_ref() else {
+      return self.beta_reduce(body, argument);
+    };
+
+    if let Some(result) = memo.get(&redex).copied() {
+      // Still counts as a step, otherwise evaluation would stop early
+      self.something_changed = true;
+      self.reductions += 1;
+      return result;
+    }
+
+    let result = self.beta_reduce(body, argument);
+    if let Some(memo) = self.memo.as_mut() {
+      memo.insert(redex, result);
+    }
+    result
+  }
+
+  /// Substitute the argument into the body of a lambda expression
+  fn beta_reduce(&mut self, body: ExprRef<'eval>, argument: ExprRef<'eval>) -> ExprRef<'eval> {
+    self.something_changed = true;
+    self.reductions += 1;
+
+    let shifted_argument = shift(self.eval_allocator, argument, 1, 1);
+    let replaced = Replace::new(self.eval_allocator, shifted_argument).replace(body);
+    shift(self.eval_allocator, replaced, 1, -1)
+  }
+}
+
+/// Single line of a JSON trace
+#[derive(Serialize)]
+struct TraceStep<'e> {
+  step: u64,
+  size: u64,
+  expression: &'e str,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::expr::DisplayOptions;
+  use std::cmp::Reverse;
+
+  /// Expressions paired with their expected normal form, which is only parsed and never evaluated
+  const NORMAL_FORMS: &[(&str, &str)] = &[
+    // Plain beta reduction
+    (r"(\x.x \y.y)", r"\y.y"),
+    (r"\x.((\y.y) x)", r"\x.x"),
+    (r"((\x y.x) \a.a \b.b)", r"\a.a"),
+    // Capture avoidance: the inner `y` must stay a different variable than the argument
+    (r"\y.((\x.\y.(x y)) y)", r"\a.\b.(a b)"),
+    (r"\y.((\x.\y.(x y)) \z.(z y))", r"\a.\b.(b a)"),
+    (r"\y.\z.((\x.\y.\z.(x y z)) (y z))", r"\a.\b.\c.\d.((a b) c d)"),
+    // Self-application that terminates
+    (r"(\x.(x x) \y.y)", r"\y.y"),
+    (r"(\x.(x x) \f.\x.x)", r"\x.x"),
+    (r"(\f.\x.(f (f x)) \f.\x.(f (f x)))", "4"),
+    // Normal order never evaluates an argument that gets thrown away
+    (r"((\x y.x) \z.z ((\x.(x x)) (\x.(x x))))", r"\z.z"),
+    // Prelude compositions
+    ("(succ 2)", "3"),
+    ("(add 2 3)", "5"),
+    ("(mul 3 4)", "12"),
+    ("(pow 2 3)", "8"),
+    ("(pred 5)", "4"),
+    ("(sub 5 2)", "3"),
+    ("(sub 2 5)", "0"),
+    ("(not true)", "false"),
+    ("(and true false)", "false"),
+    ("(eq? 3 3)", "true"),
+    ("(lss? 3 2)", "false"),
+    ("(left (pair 1 2))", "1"),
+    ("(head (tail (cons 1 (cons 2 nil))))", "2"),
+    ("(len (cons 1 (cons 2 (cons 3 nil))))", "3"),
+    ("(map succ (cons 1 (cons 2 nil)))", r"\p.((p 2) \q.((q 3) false))"),
+    (r"(Y (\r.\n.((zero? n) 1 (mul n (r (pred n))))) 3)", "6"),
+  ];
+
+  #[test]
+  fn evaluation_reaches_the_expected_normal_form() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+
+    for (input, expected_normal_form) in NORMAL_FORMS {
+      let eval_allocator = Allocator::new();
+      let expr = executor.load_expression(&eval_allocator, input).unwrap();
+      let expected = executor.load_expression(&eval_allocator, expected_normal_form).unwrap();
+
+      let result = executor.evaluate(&eval_allocator, expr, EvalOptions::default());
+      assert!(
+        result.structurally_eq(expected),
+        "{input} gave {result:#}, expected {expected_normal_form}"
+      );
+    }
+  }
+
+  #[test]
+  fn head_normal_form_leaves_the_arguments_unreduced() {
+    // The head redex is under the leading lambda, and the argument has a redex of its own
+    const CODE: &str = r"\x.((\y.y) x ((\z.z) x))";
+
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+    let evaluate = |normal_form| {
+      let expr = executor.load_expression(&eval_allocator, CODE).unwrap();
+      let options = EvalOptions {
+        normal_form,
+        ..EvalOptions::default()
+      };
+      executor.evaluate(&eval_allocator, expr, options).to_string()
+    };
 
-          Lambda { body, .. } => {
-            self.something_changed = true;
+    assert_eq!(evaluate(NormalForm::Head), r"\x.(x (\z.z x))");
+    assert_eq!(evaluate(NormalForm::Full), r"\x.(x x)");
+  }
+
+  #[test]
+  fn eta_reduction_only_removes_unused_parameters() {
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+    let evaluate = |code, eta| {
+      let expr = executor.load_expression(&eval_allocator, code).unwrap();
+      let options = EvalOptions {
+        eta,
+        ..EvalOptions::default()
+      };
+      executor.evaluate(&eval_allocator, expr, options).to_string()
+    };
+
+    assert_eq!(evaluate(r"\f.\x.(f x)", true), r"\f.f");
+    assert_eq!(evaluate(r"\f.\x.(f x)", false), r"\f.\x.(f x)");
+    assert_eq!(evaluate(r"\x.(x x)", true), r"\x.(x x)");
+
+    // Reducing the body can make the outer lambda reducible too
+    assert_eq!(evaluate(r"\f.\x.\y.((f x) y)", true), r"\f.f");
+    assert_eq!(evaluate(r"\f.\x.\y.((f y) y)", true), r"\f.\x.\y.((f y) y)");
+  }
+
+  #[test]
+  fn globals_are_evaluated_by_name() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+    executor.load_code("three = (succ 2)", None).unwrap();
+
+    let eval_allocator = Allocator::new();
+    let expected = executor.load_expression(&eval_allocator, "3").unwrap();
+    let result = executor
+      .evaluate_global(&eval_allocator, "three", EvalOptions::default(), EvalLimits::default())
+      .unwrap();
+    assert!(result.structurally_eq(expected), "three gave {result:#}");
+
+    let error = executor
+      .evaluate_global(&eval_allocator, "four", EvalOptions::default(), EvalLimits::default())
+      .unwrap_err();
+    assert!(matches!(error, EvalError::UnknownGlobal(name) if name == "four"));
+  }
+
+  #[test]
+  fn profile_counts_applications_by_source_location() {
+    const PROGRAM: &str = "id = \\x.x\nthrice = \\f x.(f (f (f x)))\n(thrice id \\y.y)";
+
+    let executor = Executor::new();
+    executor.record_lambda_locations();
+    let expr = executor.load_code(PROGRAM, Some("test")).unwrap()[0];
+
+    let eval_allocator = Allocator::new();
+    let (result, counts) = executor.evaluate_with_profile(&eval_allocator, expr, EvalOptions::default());
+    assert_eq!(result.to_string(), r"\y.y");
+
+    // `id` is applied three times, but each parameter of `thrice` only once. Locations point at the parameter names.
+    let mut counts: Vec<_> = counts
+      .into_iter()
+      .map(|(location, count)| (location.unwrap().to_string(), count))
+      .collect();
+    counts.sort_by_key(|(location, count)| (Reverse(*count), location.clone()));
+    assert_eq!(
+      counts,
+      [
+        ("test:1:7".to_string(), 3),
+        ("test:2:11".to_string(), 1),
+        ("test:2:13".to_string(), 1)
+      ]
+    );
+  }
+
+  #[test]
+  fn multiple_parameters_desugar_into_nested_lambdas() {
+    // Name of a global, the code assigned to it, and the nested lambdas it should desugar into
+    const LAMBDAS: &[(&str, &str, &str)] = &[
+      ("single", r"\x.x", r"\x.x"),
+      ("first", r"\x y.x", r"\x.\y.x"),
+      ("last", r"λa b c.c", r"\a.\b.\c.c"),
+      ("compose", r"\a b c.(a (b c))", r"\a.\b.\c.(a (b c))"),
+    ];
+    const PROGRAM: &str = "single = \\x.x\nfirst = \\x y.x\nlast = λa b c.c\ncompose = \\a b c.(a (b c))";
+
+    // Globals are built by the assign scopes, and anything else by the eval scopes
+    let executor = Executor::new();
+    executor.load_code(PROGRAM, None).unwrap();
+    for (name, code, expected) in LAMBDAS {
+      assert_eq!(executor.get_global(name).unwrap().to_string(), *expected, "{name}");
+
+      let eval_allocator = Allocator::new();
+      let expr = executor.load_expression(&eval_allocator, code).unwrap();
+      assert_eq!(expr.to_string(), *expected, "{code}");
+    }
+  }
+
+  #[test]
+  fn shadowed_parameters_print_as_code_that_loads_back() {
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, r"\x.\x.\x.(x x)").unwrap();
+
+    let ascii = DisplayOptions {
+      ascii: true,
+      ..DisplayOptions::default()
+    };
+    let printed = expr.display_with(&[], ascii).to_string();
+    assert_eq!(printed, r"\x.\x_1.\x_2.(x_2 x_2)");
+
+    // Printed names are all different, so nothing gets captured when the output is loaded again
+    let reader = Executor::new();
+    let loaded = reader.load_expression(&eval_allocator, &printed).unwrap();
+    assert!(loaded.structurally_eq(expr), "{loaded}");
+  }
+
+  #[test]
+  fn very_deep_applications_reduce_and_print() {
+    const DEPTH: usize = 100_000;
+
+    /// `\f.(f a a ... a)` nested DEPTH applications deep
+    fn chain<'a>(eval_allocator: &'a Allocator, argument: ExprRef<'a>) -> ExprRef<'a> {
+      let f = eval_allocator.new_term(NonZero::new(1).unwrap());
+      let body = (0..DEPTH).fold(f, |left, _| eval_allocator.new_eval(left, argument));
+      eval_allocator.new_lambda("f", body)
+    }
+
+    // `(\x.\f.(f x x ... x) \y.y)` substitutes into every level with a single reduction
+    let eval_allocator = Allocator::new();
+    let identity = eval_allocator.new_lambda("y", eval_allocator.new_term(NonZero::new(1).unwrap()));
+    let body = chain(&eval_allocator, eval_allocator.new_term(NonZero::new(2).unwrap()));
+    let expr = eval_allocator.new_eval(eval_allocator.new_lambda("x", body), identity);
 
-            let shifted_right = right.visit(&mut Shift::new(self.eval_allocator, 1, 1));
-            body
-              .visit(&mut Replace::new(self.eval_allocator, shifted_right))
-              .visit(&mut Shift::new(self.eval_allocator, 1, -1))
-            // No need to recurse ... next loop iteration will attempt the substitution
+    let executor = Executor::new();
+    let result = executor.evaluate(&eval_allocator, expr, EvalOptions::default());
+    assert!(result.structurally_eq(chain(&eval_allocator, identity)));
+
+    let printed = result.to_string();
+    assert!(printed.starts_with(&format!(r"\f.{}f \y.y) \y.y)", "(".repeat(DEPTH))));
+    assert!(printed.ends_with(r" \y.y) \y.y)"));
+  }
+
+  #[test]
+  fn take_prints_the_start_of_an_infinite_list() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+    executor
+      .load_code("nats = (Y (\\r.\\n.(cons n (r (succ n)))) 0)", None)
+      .unwrap();
+
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, "nats").unwrap();
+    let result = executor
+      .evaluate_list_prefix(&eval_allocator, expr, EvalOptions::default(), 3, EvalLimits::default())
+      .unwrap();
+
+    let ListEvaluation::List(prefix) = result else {
+      panic!("nats is a list");
+    };
+    assert!(matches!(prefix.end, ListEnd::More));
+    assert_eq!(prefix.elements.len(), 3);
+    for (element, number) in prefix.elements.into_iter().zip(["0", "1", "2"]) {
+      let expected = executor.load_expression(&eval_allocator, number).unwrap();
+      assert!(element.unwrap().structurally_eq(expected));
+    }
+  }
+
+  #[test]
+  fn take_only_treats_results_starting_with_a_cons_cell_as_lists() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+
+    let eval_allocator = Allocator::new();
+    for code in ["false", "0", "nil", "(succ 2)"] {
+      let expr = executor.load_expression(&eval_allocator, code).unwrap();
+      let result = executor
+        .evaluate_list_prefix(&eval_allocator, expr, EvalOptions::default(), 3, EvalLimits::default())
+        .unwrap();
+
+      let expected = executor.evaluate(&eval_allocator, expr, EvalOptions::default());
+      match result {
+        ListEvaluation::NotAList(result) => assert!(result.structurally_eq(expected), "{code}"),
+        ListEvaluation::List(_) => panic!("{code} is not a list"),
+      }
+    }
+
+    let expr = executor.load_expression(&eval_allocator, "(cons 0 nil)").unwrap();
+    let result = executor
+      .evaluate_list_prefix(&eval_allocator, expr, EvalOptions::default(), 3, EvalLimits::default())
+      .unwrap();
+    assert!(matches!(
+      result,
+      ListEvaluation::List(ListPrefix { end: ListEnd::Nil, .. })
+    ));
+  }
+
+  #[test]
+  fn take_limits_each_element_separately() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+
+    let eval_allocator = Allocator::new();
+    let code = "(cons 0 (cons (\\x.(x x) \\x.(x x)) (cons (\\x.(x x x) \\x.(x x x)) (cons 1 nil))))";
+    let expr = executor.load_expression(&eval_allocator, code).unwrap();
+    let limits = EvalLimits {
+      max_steps: Some(1000),
+      max_size: Some(1000),
+      ..EvalLimits::default()
+    };
+    let result = executor
+      .evaluate_list_prefix(&eval_allocator, expr, EvalOptions::default(), 10, limits)
+      .unwrap();
+
+    let ListEvaluation::List(prefix) = result else {
+      panic!("result is a list");
+    };
+    assert!(matches!(prefix.end, ListEnd::Nil));
+    assert!(prefix.elements[0].is_ok());
+    assert_eq!(prefix.elements[1], Err(EvalError::StepLimit(1000)));
+    assert_eq!(prefix.elements[2], Err(EvalError::SizeLimit(1000)));
+    assert!(prefix.elements[3].is_ok());
+  }
+
+  /// Picks the rightmost, outermost redex, which is different from every built-in selector
+  struct RightmostOrder;
+
+  impl RedexSelector for RightmostOrder {
+    fn select(&mut self, expr: ExprRef<'_>) -> Option<Vec<PathStep>> {
+      use UnpackedExpr::*;
+
+      let mut to_search = vec![(expr, Vec::new())];
+      while let Some((expr, path)) = to_search.pop() {
+        match expr.unpack() {
+          Term { .. } => {},
+          Lambda { body, .. } => to_search.push((body, [path, vec![PathStep::Body]].concat())),
+          Eval { left, .. } if matches!(left.unpack(), Lambda { .. }) => return Some(path),
+          Eval { left, right } => {
+            to_search.push((left, [path.clone(), vec![PathStep::Left]].concat()));
+            to_search.push((right, [path, vec![PathStep::Right]].concat()));
           },
         }
-      },
+      }
+
+      None
     }
   }
+
+  const FACTORIAL: &str = r"fact = (Y (\r.\n.((zero? n) 1 (mul n (r (pred n))))))";
+
+  /// Evaluate a call to `fact`, returning the result, the number of steps,
+  /// and how many of those steps reused the result of a memoized redex
+  fn evaluate_factorial<'s, 'eval>(
+    executor: &'s Executor<'s>,
+    eval_allocator: &'eval Allocator,
+    code: &'s str,
+    memoize: bool,
+  ) -> (ExprRef<'eval>, u64, u64)
+  where
+    's: 'eval,
+  {
+    let expr = executor.load_expression(eval_allocator, code).unwrap();
+
+    let options = EvalOptions {
+      memoize,
+      ..EvalOptions::default()
+    };
+    let mut evaluator = Evaluator::new(eval_allocator, options);
+    let result = evaluator.evaluate(expr);
+
+    // Every step that missed the cache added exactly one entry
+    let misses = evaluator
+      .memo
+      .as_ref()
+      .map_or(evaluator.reductions, |memo| memo.len() as u64);
+    (result, evaluator.reductions, evaluator.reductions - misses)
+  }
+
+  #[test]
+  fn memoization_keeps_the_result_and_step_count() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+    executor.load_code(FACTORIAL, None).unwrap();
+
+    let eval_allocator = Allocator::new();
+    let (plain, plain_steps, plain_hits) = evaluate_factorial(&executor, &eval_allocator, "(fact 4)", false);
+    let (memoized, memoized_steps, memoized_hits) = evaluate_factorial(&executor, &eval_allocator, "(fact 4)", true);
+
+    let expected = executor.load_expression(&eval_allocator, "24").unwrap();
+    assert!(plain.structurally_eq(expected));
+    assert!(memoized.structurally_eq(expected));
+    assert_eq!(plain_steps, memoized_steps);
+    assert_eq!(plain_hits, 0);
+    assert!(memoized_hits > 0);
+  }
+
+  /// Compare the time to compute a factorial with and without memoization.
+  /// The factorial of 8 runs out of memory, so this uses 6 instead. Run with
+  /// `cargo test --release -- --ignored --nocapture memoization_speedup`.
+  #[test]
+  #[ignore]
+  fn memoization_speedup_on_factorial() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+    executor.load_code(FACTORIAL, None).unwrap();
+
+    for memoize in [false, true] {
+      let eval_allocator = Allocator::new();
+      let start = Instant::now();
+      let (_, steps, hits) = evaluate_factorial(&executor, &eval_allocator, "(fact 6)", memoize);
+      println!(
+        "memoize: {memoize}, time: {:?}, steps: {steps}, cache hits: {hits} ({:.1}%)",
+        start.elapsed(),
+        100.0 * hits as f64 / steps as f64
+      );
+    }
+  }
+
+  /// Uses normal order, but sets the abort flag once it has picked this many redexes
+  struct AbortAfter<'a> {
+    remaining: u64,
+    abort: &'a AtomicBool,
+  }
+
+  impl RedexSelector for AbortAfter<'_> {
+    fn select(&mut self, expr: ExprRef<'_>) -> Option<Vec<PathStep>> {
+      match self.remaining.checked_sub(1) {
+        Some(remaining) => self.remaining = remaining,
+        None => self.abort.store(true, Ordering::Relaxed),
+      }
+      NormalOrder.select(expr)
+    }
+  }
+
+  /// Count the steps it takes to notice the abort flag that gets set after 5 steps of a divergent term
+  fn steps_before_abort(abort_check_interval: u64) -> u64 {
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+    let expr = executor
+      .load_expression(&eval_allocator, r"(\x.(x x) \x.(x x))")
+      .unwrap();
+
+    let abort = AtomicBool::new(false);
+    let options = EvalOptions {
+      abort_check_interval: NonZero::new(abort_check_interval).unwrap(),
+      ..EvalOptions::default()
+    };
+    let selector = AbortAfter {
+      remaining: 5,
+      abort: &abort,
+    };
+
+    let mut evaluator = Evaluator::with_selector(&eval_allocator, options, Box::new(selector));
+    assert_eq!(evaluator.evaluate_with_abort(expr, &abort), Err(EvalError::Aborted));
+    evaluator.reductions
+  }
+
+  #[test]
+  fn smaller_abort_check_interval_stops_sooner() {
+    assert_eq!(steps_before_abort(1), 5);
+    assert_eq!(steps_before_abort(8), 8);
+    assert_eq!(steps_before_abort(1000), 1000);
+  }
+
+  /// Always points at the root, even when it isn't a redex
+  struct RootOnly;
+
+  impl RedexSelector for RootOnly {
+    fn select(&mut self, _: ExprRef<'_>) -> Option<Vec<PathStep>> {
+      Some(vec![PathStep::Body])
+    }
+  }
+
+  #[test]
+  fn custom_selector_reaches_the_normal_form() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, "(add 2 (mul 2 3))").unwrap();
+    let expected = executor.load_expression(&eval_allocator, "8").unwrap();
+    let result = executor
+      .evaluate_with_selector(&eval_allocator, expr, EvalOptions::default(), &mut RightmostOrder)
+      .unwrap();
+    assert!(result.structurally_eq(expected), "{result}");
+  }
+
+  #[test]
+  fn custom_selector_with_an_invalid_path_is_an_error() {
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, "(\\x.x \\y.y)").unwrap();
+    let result = executor.evaluate_with_selector(&eval_allocator, expr, EvalOptions::default(), &mut RootOnly);
+    assert_eq!(result, Err(EvalError::InvalidRedex(vec![PathStep::Body])));
+  }
+
+  /// Body of the innermost of the two outer lambdas, `\v.\x.body`
+  fn inner_body<'s: 'eval, 'eval>(
+    executor: &'s Executor<'s>,
+    eval_allocator: &'eval Allocator,
+    code: &'s str,
+  ) -> ExprRef<'eval> {
+    let UnpackedExpr::Lambda { body: x, .. } = executor.load_expression(eval_allocator, code).unwrap().unpack() else {
+      panic!("{code} starts with a lambda");
+    };
+    let UnpackedExpr::Lambda { body, .. } = x.unpack() else {
+      panic!("{code} starts with two lambdas");
+    };
+    body
+  }
+
+  #[test]
+  fn replace_composes_shifts_of_the_value() {
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+
+    // `x` is substituted at depths 0, 1, 2 and 2 again, by a value with free variables
+    let body = inner_body(&executor, &eval_allocator, r"\v.\x.(x \a.(x \b.(x x)))");
+    let value = inner_body(&executor, &eval_allocator, r"\v.\x.(v \y.(v y))");
+
+    let mut replace = Replace::new(&eval_allocator, value);
+    let composed = replace.replace(body);
+
+    // Shifting the original value directly walks the whole value once for every term that gets replaced
+    let mut direct_walks = 0;
+    let direct = body.map_terms(&eval_allocator, |term, de_bruijn_index, depth| {
+      if de_bruijn_index.get() != depth + 1 {
+        return term;
+      }
+      direct_walks += 1;
+      value.shift(&eval_allocator, 1, depth as i64).unwrap()
+    });
+    assert!(composed.structurally_eq(direct), "{composed} vs {direct}");
+
+    // Composed shifts only walk the value once for each new depth, building on the closest shift so far
+    let composed_walks = replace.offsets.len() - 1;
+    assert_eq!((composed_walks, direct_walks), (2, 4));
+    assert!(composed_walks as u64 * value.size() < direct_walks * value.size());
+  }
+
+  /// Format every step of `(\x.x \y.y)` using the trace format
+  fn trace_steps(trace_format: TraceFormat) -> Vec<String> {
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, r"(\x.x \y.y)").unwrap();
+
+    let evaluator = Evaluator::new(&eval_allocator, EvalOptions::new(true, trace_format));
+    let reduced = executor.evaluate(&eval_allocator, expr, EvalOptions::default());
+    vec![
+      evaluator.format_step(0, expr, Some(&[])),
+      evaluator.format_step(1, reduced, None),
+    ]
+  }
+
+  #[test]
+  fn csv_trace_has_a_header_then_one_row_per_step() {
+    assert_eq!(
+      trace_steps(TraceFormat::Csv),
+      ["step,size,expression\n0,5,\"(λx.x λy.y)\"", "1,2,\"λy.y\""]
+    );
+  }
+
+  #[test]
+  fn json_trace_has_one_object_per_step() {
+    assert_eq!(
+      trace_steps(TraceFormat::Json),
+      [
+        r#"{"step":0,"size":5,"expression":"(λx.x λy.y)"}"#,
+        r#"{"step":1,"size":2,"expression":"λy.y"}"#
+      ]
+    );
+  }
 }
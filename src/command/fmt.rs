@@ -0,0 +1,50 @@
+use clap::Args;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::format::format_code;
+
+/// Reprint code files in the canonical style, keeping all comments
+#[derive(Args)]
+pub struct FmtArgs {
+  /// Overwrite the files with the formatted code instead of printing it
+  #[clap(short, long)]
+  write: bool,
+
+  /// Only check if the files are already formatted, and fail if any are not
+  #[clap(long, conflicts_with = "write")]
+  check: bool,
+
+  /// List of files to format
+  #[clap(required = true)]
+  files: Vec<PathBuf>,
+}
+
+impl FmtArgs {
+  pub fn execute(self) -> super::CommandResult {
+    let mut unformatted_files = 0;
+    for file in self.files.iter() {
+      let code = fs::read_to_string(file)?;
+      let formatted = format_code(&code).map_err(|e| format!("{}: {e}", file.display()))?;
+
+      if self.check {
+        if formatted != code {
+          println!("{} is not formatted", file.display());
+          unformatted_files += 1;
+        }
+      } else if self.write {
+        if formatted != code {
+          fs::write(file, formatted)?;
+        }
+      } else {
+        print!("{formatted}");
+      }
+    }
+
+    if unformatted_files > 0 {
+      return Err(format!("{unformatted_files} file(s) need formatting").into());
+    }
+
+    Ok(())
+  }
+}
@@ -1,12 +1,52 @@
+#[cfg(feature = "cli")]
 use std::error::Error;
 
+#[cfg(feature = "cli")]
+mod bench;
+#[cfg(feature = "cli")]
+mod classes;
+#[cfg(feature = "cli")]
+mod compile;
+#[cfg(feature = "cli")]
 mod decode;
+#[cfg(feature = "cli")]
+mod diff;
+#[cfg(feature = "cli")]
 mod encode;
-mod executor;
+#[cfg(feature = "cli")]
+mod env;
+pub(crate) mod executor;
+#[cfg(feature = "cli")]
+mod fmt;
+#[cfg(feature = "cli")]
+mod random;
+#[cfg(feature = "cli")]
+mod reduce;
+#[cfg(feature = "cli")]
 mod run;
 
+#[cfg(feature = "cli")]
+pub use bench::BenchArgs;
+#[cfg(feature = "cli")]
+pub use classes::EquivClassesArgs;
+#[cfg(feature = "cli")]
+pub use compile::CompileArgs;
+#[cfg(feature = "cli")]
 pub use decode::DecodeArgs;
+#[cfg(feature = "cli")]
+pub use diff::DiffArgs;
+#[cfg(feature = "cli")]
 pub use encode::EncodeArgs;
+#[cfg(feature = "cli")]
+pub use env::EnvArgs;
+#[cfg(feature = "cli")]
+pub use fmt::FmtArgs;
+#[cfg(feature = "cli")]
+pub use random::RandomArgs;
+#[cfg(feature = "cli")]
+pub use reduce::ReduceArgs;
+#[cfg(feature = "cli")]
 pub use run::RunArgs;
 
+#[cfg(feature = "cli")]
 pub type CommandResult = std::result::Result<(), Box<dyn Error>>;
@@ -0,0 +1,153 @@
+use clap::Args;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::num::NonZero;
+use typed_arena::Arena;
+
+use crate::expr::{Allocator, ExprRef};
+
+use super::encode::PrintVisitor;
+
+/// Generate a random lambda expression
+#[derive(Args)]
+pub struct RandomArgs {
+  /// Maximum number of nodes (terms, lambdas, and evals) in the expression
+  #[clap(long, default_value_t = 16, value_parser = clap::value_parser!(u64).range(2..))]
+  size: u64,
+
+  /// Seed for the random number generator, to make the output reproducible
+  #[clap(long)]
+  seed: Option<u64>,
+
+  /// Number of free variables the expression may reference (0 generates a closed term)
+  #[clap(long, default_value_t = 0)]
+  free: u64,
+
+  /// Output the Binary Lambda Calculus encoding instead of the expression
+  #[clap(short, long, conflicts_with = "free")]
+  encode: bool,
+}
+
+impl RandomArgs {
+  pub fn execute(self) -> super::CommandResult {
+    let rng = match self.seed {
+      Some(seed) => StdRng::seed_from_u64(seed),
+      None => StdRng::from_os_rng(),
+    };
+
+    let text_data = Arena::new();
+    let allocator = Allocator::new();
+
+    let mut generator = Generator::new(&text_data, &allocator, rng, self.free);
+    let expr = generator.generate(self.size, 0);
+    debug_assert!(self.free > 0 || expr.is_closed(), "generated term is not closed");
+
+    if self.encode {
+      let mut visitor = PrintVisitor::new("0", "1");
+      expr.visit(&mut visitor);
+      println!("{}", visitor.into_string());
+    } else {
+      println!("{expr}");
+    }
+
+    Ok(())
+  }
+}
+
+struct Generator<'alloc> {
+  text_data: &'alloc Arena<String>,
+  allocator: &'alloc Allocator,
+  rng: StdRng,
+  free_variables: u64,
+  variable_names: Vec<&'alloc str>,
+}
+
+impl<'alloc> Generator<'alloc> {
+  pub fn new(text_data: &'alloc Arena<String>, allocator: &'alloc Allocator, rng: StdRng, free_variables: u64) -> Self {
+    Self {
+      text_data,
+      allocator,
+      rng,
+      free_variables,
+      variable_names: Vec::new(),
+    }
+  }
+
+  fn get_parameter_name(&mut self, depth: u64) -> &'alloc str {
+    for i in self.variable_names.len()..=(depth as usize) {
+      let data = self.text_data.alloc(format!("x{}", i + 1));
+      self.variable_names.push(data.as_str());
+    }
+
+    self.variable_names[depth as usize]
+  }
+
+  /// Smallest expression that can be built at the given depth:
+  /// a single term if any variables are in scope, otherwise `\x.x`
+  fn min_size(&self, depth: u64) -> u64 {
+    if depth + self.free_variables > 0 { 1 } else { 2 }
+  }
+
+  /// Generate an expression of at most `max_size` nodes that only references in-scope variables
+  pub fn generate(&mut self, max_size: u64, depth: u64) -> ExprRef<'alloc> {
+    let in_scope = depth + self.free_variables;
+
+    // Figure out which kinds of nodes still fit in the remaining space
+    let can_term = in_scope > 0;
+    let can_lambda = max_size > self.min_size(depth + 1);
+    let can_eval = max_size > 2 * self.min_size(depth);
+
+    let choices = [can_term, can_lambda, can_eval];
+    let num_choices = choices.iter().filter(|c| **c).count();
+    let choice = choices
+      .iter()
+      .enumerate()
+      .filter_map(|(i, c)| c.then_some(i))
+      .nth(self.rng.random_range(0..num_choices))
+      .expect("no valid expression for size");
+
+    match choice {
+      0 => {
+        let index = self.rng.random_range(1..=in_scope);
+        self.allocator.new_term(NonZero::new(index).expect("index is 0"))
+      },
+
+      1 => {
+        let body = self.generate(max_size - 1, depth + 1);
+        let name = self.get_parameter_name(depth);
+        self.allocator.new_lambda(name, body)
+      },
+
+      _ => {
+        let min_size = self.min_size(depth);
+        let left_size = self.rng.random_range(min_size..=(max_size - 1 - min_size));
+        let left = self.generate(left_size, depth);
+        let right = self.generate(max_size - 1 - left_size, depth);
+        self.allocator.new_eval(left, right)
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn generate(seed: u64, size: u64) -> String {
+    let text_data = Arena::new();
+    let allocator = Allocator::new();
+    let mut generator = Generator::new(&text_data, &allocator, StdRng::seed_from_u64(seed), 0);
+
+    let expr = generator.generate(size, 0);
+    assert!(expr.is_closed(), "{expr} is not closed");
+    assert!(expr.size() <= size, "{expr} has more than {size} nodes");
+    expr.to_string()
+  }
+
+  #[test]
+  fn same_seed_generates_the_same_closed_term() {
+    for seed in 0..20 {
+      assert_eq!(generate(seed, 32), generate(seed, 32));
+    }
+  }
+}
@@ -0,0 +1,80 @@
+use clap::Args;
+use std::io::Read;
+use std::path::PathBuf;
+use typed_arena::Arena;
+
+use crate::expr::Allocator;
+
+use super::executor::{EvalOptions, Executor, NormalForm, ReductionStrategy, TraceFormat};
+
+/// Evaluate a single expression and print its normal form
+#[derive(Args)]
+pub struct ReduceArgs {
+  /// Expression to evaluate. Reads from stdin if omitted.
+  expression: Option<String>,
+
+  /// File to load after the prelude before evaluating the expression. Can be repeated, and files load in order.
+  #[clap(short, long = "file", value_name = "FILE")]
+  files: Vec<PathBuf>,
+
+  /// Don't load the prelude, so only the definitions from the files exist
+  #[clap(long)]
+  no_prelude: bool,
+
+  /// Print the individual reduction steps to stderr
+  #[clap(short, long)]
+  steps: bool,
+
+  /// Format of the reduction steps printed by --steps
+  #[clap(long, value_enum, default_value_t, requires = "steps")]
+  trace_format: TraceFormat,
+
+  /// Only reduce the expression to head normal form instead of full normal form
+  #[clap(long)]
+  hnf: bool,
+
+  /// Order to reduce the redexes in
+  #[clap(long, value_enum, default_value_t, conflicts_with = "hnf")]
+  strategy: ReductionStrategy,
+
+  /// After evaluating, also apply eta reduction so `\x.(f x)` becomes `f`
+  #[clap(long)]
+  eta: bool,
+}
+
+impl ReduceArgs {
+  pub fn execute(self) -> super::CommandResult {
+    let text_data = Arena::new();
+    let executor = Executor::new();
+    if !self.no_prelude {
+      executor.load_code(crate::PRELUDE, Some("prelude"))?;
+    }
+    for file in self.files.iter() {
+      executor.load_file(file)?;
+    }
+
+    let code = match self.expression {
+      Some(expression) => expression,
+      None => {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        input
+      },
+    };
+    let code = text_data.alloc(code.trim().to_string());
+
+    let mut options = EvalOptions::new(self.steps, self.trace_format);
+    if self.hnf {
+      options.normal_form = NormalForm::Head;
+    }
+    options.strategy = self.strategy;
+    options.eta = self.eta;
+
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, code.as_str())?;
+    let result = executor.evaluate(&eval_allocator, expr, options);
+    println!("{result:#}");
+
+    Ok(())
+  }
+}
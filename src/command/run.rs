@@ -1,61 +1,682 @@
-use crate::expr::Allocator;
-use clap::Args;
+use crate::expr::{Allocator, DisplayOptions, ExprRef};
+use crate::format::find_comments;
+use crate::readable::{KnownTerms, ReadableOptions, format_readable, format_readable_with_known_terms};
+use crate::symbol_table::{DEFAULT_MAX_NUMBER, SourceLocation};
+use clap::{ArgGroup, Args, ValueEnum};
+use crossterm::cursor::MoveTo;
+use crossterm::execute;
 use crossterm::style::Stylize;
-use rustyline::DefaultEditor;
+use crossterm::terminal::{Clear, ClearType};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
 use rustyline::config::Configurer;
 use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::{DefaultHistory, History};
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal};
+use std::mem;
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
 use typed_arena::Arena;
 
-use super::executor::Executor;
+use super::encode::PrintVisitor;
+use super::executor::{
+  EvalError, EvalLimits, EvalOptions, Executor, ListEnd, ListEvaluation, ListPrefix, NormalForm, ReductionStrategy,
+  TraceFormat, is_normal_form,
+};
 
 #[derive(Args)]
+#[clap(group(ArgGroup::new("eval").multiple(true)))]
 pub struct RunArgs {
   /// Enter interactive mode after compiling files
   #[clap(short, long)]
   interactive: bool,
 
   /// Print the individual reduction steps to stderr
-  #[clap(short, long)]
+  #[clap(short, long, group = "eval")]
   steps: bool,
 
+  /// Format of the reduction steps printed by --steps
+  #[clap(long, value_enum, default_value_t, requires = "steps")]
+  trace_format: TraceFormat,
+
+  /// Only reduce expressions to head normal form instead of full normal form
+  #[clap(long, group = "eval")]
+  hnf: bool,
+
+  /// Order to reduce the redexes in. Applicative order and call-by-value may never finish on some
+  /// expressions that normal order can evaluate.
+  #[clap(long, value_enum, default_value_t, conflicts_with = "hnf", group = "eval")]
+  strategy: ReductionStrategy,
+
+  /// Load definitions from a binary file created by `env save` instead of the prelude
+  #[clap(long, value_name = "FILE")]
+  env: Option<PathBuf>,
+
+  /// Don't load the prelude, so only the definitions from the code files exist
+  #[clap(long, conflicts_with = "env")]
+  no_prelude: bool,
+
+  /// Note when the result of an evaluation is equal to a named variable
+  #[clap(long, group = "eval")]
+  annotate: bool,
+
+  /// Print any part of a result that is equal to a named variable using the name instead, like S, K or I
+  #[clap(long, group = "eval")]
+  recognize: bool,
+
+  /// Print Church booleans `\x y.x` and `\x y.y` as `true` and `false`
+  #[clap(long, group = "eval")]
+  booleans: bool,
+
+  /// Print Church pairs `\f.((f a) b)` as `(a, b)`
+  #[clap(long, group = "eval")]
+  pairs: bool,
+
+  /// Print Church numerals `\f.\x.(f (f ... (f x)))` as plain numbers
+  #[clap(long, group = "eval")]
+  decode_numerals: bool,
+
+  /// Print right-fold lists `\c.\n.((c a) ((c b) n))` as `[a, b]`, which is a different encoding than the built-in lists
+  #[clap(long, group = "eval")]
+  church_lists: bool,
+
+  /// Print Scott numerals `\z s.z` and `\z s.(s n)` as `scott(n)`, for any n up to the limit
+  #[clap(
+    long,
+    value_name = "LIMIT",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "1000000",
+    group = "eval"
+  )]
+  scott: Option<u64>,
+
+  /// Leave out redundant parentheses when printing results, so `(((a b) c) d)` is printed as `a b c d`
+  #[clap(long)]
+  pretty: bool,
+
+  /// Print results using `\` for lambdas and `x_1` instead of `x′` for shadowed parameters,
+  /// so the only characters outside of ASCII are the ones written in the parameter names
+  #[clap(long)]
+  ascii: bool,
+
+  /// Print any part of a result nested deeper than N as `…`, which keeps huge results from overflowing the stack
+  #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+  max_depth: Option<u64>,
+
+  /// Lazily evaluate only the first N elements of any list, which allows printing infinite lists
+  #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), group = "eval")]
+  take: Option<u64>,
+
+  /// Stop evaluating any list cell or element from --take after N reduction steps
+  #[clap(long, value_name = "N", requires = "take")]
+  take_steps: Option<u64>,
+
+  /// Stop evaluating any list cell or element from --take once it grows past N nodes
+  #[clap(long, value_name = "N", requires = "take")]
+  take_size: Option<u64>,
+
+  /// Only parse the files and print the top-level expressions without evaluating them
+  #[clap(long, conflicts_with = "eval")]
+  no_eval: bool,
+
+  /// Truncate printed expressions to at most N characters
+  #[clap(long, value_name = "N")]
+  max_output_chars: Option<usize>,
+
+  /// Only evaluate the Nth top-level expression (starting from 1), counted across all files
+  #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+  only: Option<u64>,
+
+  /// Count how many beta reductions applied each lambda, then print the N most reduced source locations to stderr
+  #[clap(
+    long,
+    value_name = "N",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "10",
+    conflicts_with = "take",
+    group = "eval"
+  )]
+  profile: Option<usize>,
+
+  /// Print a dot to stderr every N reduction steps, so long evaluations show they are still running
+  #[clap(
+    long,
+    value_name = "N",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "100000",
+    value_parser = clap::value_parser!(u64).range(1..),
+    group = "eval"
+  )]
+  progress: Option<u64>,
+
+  /// Apply --term to each row of arguments in this file, and print the results
+  #[clap(long, value_name = "FILE", requires = "term", group = "eval")]
+  table: Option<PathBuf>,
+
+  /// Function to apply to each row of the --table file
+  #[clap(long, value_name = "EXPR", requires = "table")]
+  term: Option<String>,
+
+  /// After running the files, print the normal form of every named variable.
+  /// Any variable still being reduced after N steps is printed as `<diverges>`.
+  #[clap(
+    long,
+    value_name = "N",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "1000",
+    value_parser = clap::value_parser!(u64).range(1..),
+    conflicts_with_all = ["take", "profile"],
+    group = "eval"
+  )]
+  evaluate_all: Option<u64>,
+
+  /// Stop evaluating any expression that takes longer than this, like 5s, 1.5m or 500ms
+  #[clap(
+    long,
+    value_name = "DURATION",
+    value_parser = parse_duration,
+    conflicts_with_all = ["take", "profile"],
+    group = "eval"
+  )]
+  timeout: Option<Duration>,
+
+  /// Stop with an error if an expression comes back to an earlier state, since it would loop forever
+  #[clap(long, conflicts_with_all = ["take", "profile"], group = "eval")]
+  detect_cycles: bool,
+
+  /// Number of reduction steps between checks for Ctrl+C in the REPL.
+  /// Smaller values stop sooner, larger values evaluate slightly faster.
+  #[clap(
+    long,
+    value_name = "N",
+    value_parser = clap::value_parser!(u64).range(1..),
+    default_value_t = EvalOptions::DEFAULT_ABORT_CHECK_INTERVAL.get(),
+    group = "eval"
+  )]
+  abort_check_interval: u64,
+
+  /// Remember the result of every reduced redex, so shared redexes are only substituted once.
+  /// Uses more memory with every step, so it only helps terms that share a lot of redexes.
+  #[clap(long, group = "eval")]
+  memo: bool,
+
+  /// After evaluating, also apply eta reduction so `\x.(f x)` becomes `f`
+  #[clap(long, group = "eval")]
+  eta: bool,
+
+  /// Largest number literal allowed in the code, since every number is built as a chain of that many applications
+  #[clap(long, value_name = "N", default_value_t = DEFAULT_MAX_NUMBER)]
+  max_number: u64,
+
+  /// Build every number from 0 to N at startup, so number literals up to N are looked up instead of built
+  #[clap(long, value_name = "N")]
+  seed_numbers: Option<u64>,
+
+  /// Print compiler errors and warnings to stderr as one JSON object per line, with the level, message, line and column
+  #[clap(long)]
+  json_errors: bool,
+
+  /// Read one statement per line from stdin and print each result, without any of the REPL prompts.
+  /// Errors are printed to stderr, and the rest of the lines keep running.
+  #[clap(long, conflicts_with_all = ["interactive", "watch", "profile"])]
+  batch: bool,
+
+  /// Run the files again every time one of them changes, starting over with only the prelude each time
+  #[clap(long, requires = "files", conflicts_with = "interactive")]
+  watch: bool,
+
   /// List of files to run, in order
   files: Vec<PathBuf>,
 }
 
 impl RunArgs {
   pub fn execute(self) -> super::CommandResult {
+    if self.seed_numbers.is_some_and(|number| number > self.max_number) {
+      return Err(
+        format!(
+          "--seed-numbers cannot be larger than --max-number ({})",
+          self.max_number
+        )
+        .into(),
+      );
+    }
+
+    if self.watch {
+      return self.watch();
+    }
+
     let text_data = Arena::new();
-    let executor = Executor::new();
+    let executor = self.new_executor();
+    self.run_files(&text_data, &executor)?;
 
-    // Load the prelude
-    {
+    if self.batch {
+      return self.run_batch(&text_data, &executor);
+    }
+
+    // Drop into interactive mode if required
+    let should_enter_interactive_mode =
+      self.interactive || (self.files.is_empty() && self.table.is_none() && self.evaluate_all.is_none());
+    if !should_enter_interactive_mode {
+      return Ok(());
+    }
+
+    println!("Welcome to Rusty Lambda, a lambda calculus interpreter");
+    println!("Type \":help\" for more information");
+
+    let state = ReplState {
+      options: self.eval_options(),
+      output: self.output_options(),
+      loaded_files: self.files.clone(),
+      history: Vec::new(),
+      prelude: !self.no_prelude,
+    };
+    let mut exit = Repl::new(&text_data, &executor, state).run()?;
+
+    // Every definition lives in the allocator of the executor, so reloading starts over with a new executor.
+    // The REPL settings and history are moved over, and the old executor is dropped along with its definitions.
+    while let ReplExit::Reload(state) = exit {
+      let text_data = Arena::new();
+      let executor = self.new_executor();
+      self.seed_numbers(&executor);
+      if state.prelude {
+        self.load_prelude(&text_data, &executor)?;
+      }
+
+      let mut repl = Repl::new(&text_data, &executor, state);
+      repl.reload_files();
+      exit = repl.run()?;
+    }
+
+    Ok(())
+  }
+
+  /// Create an empty executor with the settings for loading code
+  fn new_executor<'s>(&self) -> Executor<'s> {
+    let mut executor = Executor::new();
+    executor.set_max_number(self.max_number);
+    executor.set_messages_to_stderr(self.batch);
+    executor.set_json_messages(self.json_errors);
+    executor
+  }
+
+  fn eval_options(&self) -> EvalOptions {
+    let mut options = EvalOptions::new(self.steps, self.trace_format);
+    if self.hnf {
+      options.normal_form = NormalForm::Head;
+    }
+    options.strategy = self.strategy;
+    options.detect_cycles = self.detect_cycles;
+    options.abort_check_interval = NonZero::new(self.abort_check_interval).expect("interval is zero");
+    options.memoize = self.memo;
+    options.progress = self.progress.and_then(NonZero::new);
+    options.eta = self.eta;
+    options
+  }
+
+  fn output_options(&self) -> OutputOptions {
+    OutputOptions {
+      annotate: self.annotate,
+      recognize: self.recognize,
+      max_chars: self.max_output_chars,
+      readable: ReadableOptions {
+        booleans: self.booleans,
+        pairs: self.pairs,
+        numerals: self.decode_numerals,
+        lists: self.church_lists,
+        scott_numerals: self.scott,
+        de_bruijn_indices: false,
+        minimal_parens: self.pretty,
+        max_depth: self.max_depth.and_then(NonZero::new),
+        ascii: self.ascii,
+      },
+    }
+  }
+
+  /// Build the numbers from `--seed-numbers` before any code is loaded
+  fn seed_numbers<'s>(&self, executor: &'s Executor<'s>) {
+    if let Some(number) = self.seed_numbers {
+      executor.seed_numbers(number);
+    }
+  }
+
+  /// Load the prelude, or a saved environment instead
+  fn load_prelude<'s>(&self, text_data: &'s Arena<String>, executor: &'s Executor<'s>) -> super::CommandResult {
+    if let Some(env) = self.env.as_ref() {
+      executor.load_environment(env)?;
+    } else {
       let prelude = text_data.alloc(crate::PRELUDE.to_string());
       executor.load_code(prelude.as_str(), Some("prelude"))?;
     }
 
+    Ok(())
+  }
+
+  /// Load the prelude, then run all of the code files and the table
+  fn run_files<'s>(&self, text_data: &'s Arena<String>, executor: &'s Executor<'s>) -> super::CommandResult {
+    let options = self.eval_options();
+    let output = self.output_options();
+
+    // Lambdas need to be tracked before any code is loaded
+    let mut profile = self.profile.map(|_| HashMap::new());
+    if profile.is_some() {
+      executor.record_lambda_locations();
+    }
+
+    self.seed_numbers(executor);
+
+    if !self.no_prelude {
+      self.load_prelude(text_data, executor)?;
+    }
+
     // Load and evaluate the code files
+    let mut expression_count = 0u64;
     for file in self.files.iter() {
-      let file_data = text_data.alloc(fs::read_to_string(file)?);
-
-      let to_evaluate = executor.load_code(file_data.as_str(), file.to_str())?;
+      let to_evaluate = executor.load_file(file)?;
       for expr in to_evaluate {
+        expression_count += 1;
+        if self.only.is_some_and(|n| n != expression_count) {
+          continue;
+        }
+
+        if self.no_eval {
+          let display = DisplayOptions {
+            minimal_parens: output.readable.minimal_parens,
+            max_depth: output.readable.max_depth,
+            ascii: output.readable.ascii,
+          };
+          println!(
+            "{}",
+            truncate(format!("{:#}", expr.display_with(&[], display)), output.max_chars)
+          );
+          continue;
+        }
+
         let eval_allocator = Allocator::new();
-        let result = executor.evaluate(&eval_allocator, expr, self.steps);
-        println!("{result:#}");
+        if let Some(count) = self.take {
+          let limits = EvalLimits {
+            max_steps: self.take_steps,
+            max_size: self.take_size,
+            ..EvalLimits::default()
+          };
+          match executor.evaluate_list_prefix(&eval_allocator, expr, options, count, limits) {
+            Ok(ListEvaluation::List(prefix)) => print_list_prefix(executor, prefix, output),
+            Ok(ListEvaluation::NotAList(result)) => print_result(executor, result, output, None),
+            Err(e) => print_eval_error(e),
+          }
+          continue;
+        }
+
+        match evaluate(executor, &eval_allocator, expr, options, self.timeout, profile.as_mut()) {
+          Ok(result) => print_result(executor, result, output, None),
+          Err(e) => print_eval_error(e),
+        }
       }
     }
 
-    // Drop into interactive mode if required
-    let should_enter_interactive_mode = self.interactive || self.files.is_empty();
-    if !should_enter_interactive_mode {
-      return Ok(());
+    if let Some(n) = self.only
+      && n > expression_count
+    {
+      return Err(format!("--only {n} is out of range: found {expression_count} top-level expression(s)").into());
+    }
+
+    // Apply the term to each row of arguments
+    if let (Some(table), Some(term)) = (self.table.as_ref(), self.term.as_ref()) {
+      // Comments are removed first, keeping their newlines so the line numbers still match
+      let rows = without_comments(&fs::read_to_string(table)?);
+      for (row, line_number) in rows.lines().zip(1..) {
+        let row = row.trim();
+        if row.is_empty() {
+          continue; // Skip blank lines and lines that only had comments
+        }
+
+        let code = text_data.alloc(format!("({term} {row})"));
+        let eval_allocator = Allocator::new();
+        let expr = executor
+          .load_expression(&eval_allocator, code.as_str())
+          .map_err(|e| format!("{} (line {line_number}): {e}", table.display()))?;
+
+        match evaluate(executor, &eval_allocator, expr, options, self.timeout, profile.as_mut()) {
+          Ok(result) => print_result(executor, result, output, None),
+          Err(e) => print_eval_error(e),
+        }
+      }
+    }
+
+    if let Some(max_steps) = self.evaluate_all {
+      self.evaluate_all(executor, max_steps);
+    }
+
+    if let (Some(top), Some(profile)) = (self.profile, profile) {
+      print_profile(profile, top);
+    }
+
+    Ok(())
+  }
+
+  /// Print `name = <normal form>` for every global, in alphabetical order
+  fn evaluate_all(&self, executor: &Executor<'_>, max_steps: u64) {
+    let options = self.eval_options();
+    let output = self.output_options();
+    let limits = EvalLimits {
+      max_steps: Some(max_steps),
+      timeout: self.timeout,
+      max_size: None,
+    };
+
+    // Copy the globals first, since the map can't stay borrowed while printing the results
+    let globals: Vec<_> = executor
+      .all_globals()
+      .borrow()
+      .iter()
+      .map(|(name, expr)| (*name, *expr))
+      .collect();
+
+    for (name, expr) in globals {
+      let eval_allocator = Allocator::new();
+      match executor.evaluate_with_limits(&eval_allocator, expr, options, limits) {
+        Ok(result) => println!(
+          "{name} = {}",
+          truncate(format_output(executor, result, output), output.max_chars)
+        ),
+        Err(EvalError::StepLimit(_) | EvalError::Timeout(_) | EvalError::Cycle) => println!("{name} = <diverges>"),
+        Err(e) => println!("{name} = {} {e}", "Error:".red()),
+      }
+    }
+  }
+
+  /// Evaluate each line of stdin as its own statement, printing one result per expression
+  fn run_batch<'s>(&self, text_data: &'s Arena<String>, executor: &'s Executor<'s>) -> super::CommandResult {
+    let options = self.eval_options();
+    let output = self.output_options();
+
+    for (line, line_number) in io::stdin().lines().zip(1..) {
+      let line = line?;
+      let line = line.trim();
+      if without_comments(line).trim().is_empty() {
+        continue; // Skip blank lines and comments
+      }
+
+      let code = text_data.alloc(line.to_string());
+      let eval_allocator = Allocator::new();
+      let expr = match executor.load_statement(&eval_allocator, code.as_str()) {
+        Ok(Some(expr)) => expr,
+        Ok(None) => continue,
+        Err(e) => {
+          eprintln!("{} {e} (line {line_number})", "Error:".red());
+          continue;
+        },
+      };
+
+      match evaluate(executor, &eval_allocator, expr, options, self.timeout, None) {
+        Ok(result) => print_result(executor, result, output, None),
+        Err(e) => eprintln!("{} {e} (line {line_number})", "Error:".red()),
+      }
     }
 
-    Repl::new(&text_data, &executor, self.steps).run()
+    Ok(())
   }
+
+  /// Run the files, then run them again from scratch every time one of them changes
+  fn watch(&self) -> super::CommandResult {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+
+    // Watch the directories instead of the files, since many editors save by replacing the file
+    let watched_files = self
+      .files
+      .iter()
+      .chain(self.table.as_ref())
+      .map(fs::canonicalize)
+      .collect::<Result<HashSet<_>, _>>()?;
+    let directories: HashSet<_> = watched_files.iter().filter_map(|file| file.parent()).collect();
+    for directory in directories {
+      watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    }
+
+    loop {
+      // Each run starts over with only the prelude defined
+      let text_data = Arena::new();
+      let executor = self.new_executor();
+      if let Err(e) = self.run_files(&text_data, &executor) {
+        println!("{} {e}", "Error:".red());
+      }
+
+      println!("{}", "Watching for changes, press Ctrl+C to exit".dark_grey());
+      wait_for_change(&receiver, &watched_files)?;
+    }
+  }
+}
+
+/// Block until one of the files changes
+fn wait_for_change(receiver: &Receiver<notify::Result<Event>>, files: &HashSet<PathBuf>) -> super::CommandResult {
+  loop {
+    let event = receiver.recv()??;
+    if is_file_changed(&event, files) {
+      break;
+    }
+  }
+
+  // Saving a file can trigger several events, so skip the rest of them
+  thread::sleep(Duration::from_millis(100));
+  while receiver.try_recv().is_ok() {}
+
+  Ok(())
+}
+
+fn is_file_changed(event: &Event, files: &HashSet<PathBuf>) -> bool {
+  matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+    && event.paths.iter().any(|path| files.contains(path))
+}
+
+/// Evaluate an expression, adding the reduction counts to the profile if one is being collected
+fn evaluate<'s, 'eval>(
+  executor: &Executor<'s>,
+  eval_allocator: &'eval Allocator,
+  expr: ExprRef<'eval>,
+  options: EvalOptions,
+  timeout: Option<Duration>,
+  profile: Option<&mut HashMap<Option<SourceLocation>, u64>>,
+) -> Result<ExprRef<'eval>, EvalError>
+where
+  's: 'eval,
+{
+  if timeout.is_some() || options.detect_cycles {
+    let limits = EvalLimits {
+      timeout,
+      ..EvalLimits::default()
+    };
+    return executor.evaluate_with_limits(eval_allocator, expr, options, limits);
+  }
+
+  let Some(profile) = profile else {
+    return Ok(executor.evaluate(eval_allocator, expr, options));
+  };
+
+  let (result, counts) = executor.evaluate_with_profile(eval_allocator, expr, options);
+  for (location, count) in counts {
+    *profile.entry(location).or_default() += count;
+  }
+
+  Ok(result)
+}
+
+/// Aborting with Ctrl+C isn't really an error
+fn print_eval_error(error: EvalError) {
+  match error {
+    EvalError::Aborted => println!("Interrupted"),
+    e => println!("{} {e}", "Error:".red()),
+  }
+}
+
+/// Parse a duration like `5s`, `1.5m` or `500ms`. Plain numbers are in seconds.
+fn parse_duration(input: &str) -> Result<Duration, String> {
+  let split = input
+    .find(|c: char| !c.is_ascii_digit() && c != '.')
+    .unwrap_or(input.len());
+  let (number, unit) = input.split_at(split);
+
+  let number: f64 = number.parse().map_err(|_| format!("invalid duration: {input}"))?;
+  let seconds = match unit {
+    "ms" => number / 1000.0,
+    "" | "s" => number,
+    "m" => number * 60.0,
+    "h" => number * 60.0 * 60.0,
+    _ => return Err(format!("unknown unit \"{unit}\", expected one of ms, s, m or h")),
+  };
+
+  Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+/// Print the source locations with the most reductions to stderr
+fn print_profile(profile: HashMap<Option<SourceLocation>, u64>, top: usize) {
+  let mut locations: Vec<_> = profile.into_iter().collect();
+  locations
+    .sort_by_cached_key(|(location, count)| (std::cmp::Reverse(*count), location.as_ref().map(ToString::to_string)));
+
+  let total: u64 = locations.iter().map(|(_, count)| count).sum();
+  eprintln!("{}", format!("Profile: {total} reductions").bold());
+
+  let width = locations.first().map_or(1, |(_, count)| count.to_string().len());
+  for (location, count) in locations.into_iter().take(top) {
+    match location {
+      Some(location) => eprintln!("  {count: >width$}  {location}"),
+      None => eprintln!("  {count: >width$}  (no source location)"),
+    }
+  }
+}
+
+/// Set up Ctrl+C to abort the current evaluation, returning the flag that gets set.
+///
+/// The handler can only be registered once per process, so every REPL shares the same flag.
+fn install_abort_handler() -> &'static AtomicBool {
+  static ABORT_EXECUTION: AtomicBool = AtomicBool::new(false);
+  static INSTALL_HANDLER: Once = Once::new();
+
+  INSTALL_HANDLER.call_once(|| {
+    if let Err(e) = ctrlc::set_handler(|| {
+      ABORT_EXECUTION.store(true, Ordering::Relaxed);
+    }) {
+      println!("{}: failed to set Ctrl+C handler", "Warning".yellow());
+      println!("{e}\n");
+    }
+  });
+
+  &ABORT_EXECUTION
 }
 
 struct Repl<'text, 'assign>
@@ -64,12 +685,89 @@ where
 {
   text_data: &'text Arena<String>,
   executor: &'assign Executor<'assign>,
-  show_steps: bool,
+  options: EvalOptions,
+  output: OutputOptions,
+  loaded_files: Vec<PathBuf>,
+  history: Vec<String>,
+  prelude: bool,
   abort: &'static AtomicBool,
 }
 
+/// Everything the REPL keeps when it is reloaded with a new executor
+struct ReplState {
+  options: EvalOptions,
+  output: OutputOptions,
+  /// Code files given on the command line or with `:load`, in the order they were first loaded
+  loaded_files: Vec<PathBuf>,
+  history: Vec<String>,
+  /// Load the prelude after reloading
+  prelude: bool,
+}
+
+/// Why the REPL stopped running
+enum ReplExit {
+  Exit,
+  Reload(ReplState),
+}
+
+type ReplEditor<'assign> = Editor<ReplHelper<'assign>, DefaultHistory>;
+
+/// Tab completion for the REPL: file paths after `:load`, and named variables everywhere else
+struct ReplHelper<'assign> {
+  executor: &'assign Executor<'assign>,
+  filename_completer: FilenameCompleter,
+}
+
+impl Completer for ReplHelper<'_> {
+  type Candidate = Pair;
+
+  fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+    let is_load_command = line
+      .split_once(' ')
+      .is_some_and(|(command, _)| matches!(command, ":l" | ":lo" | ":loa" | ":load"));
+    if is_load_command {
+      return self.filename_completer.complete(line, pos, ctx);
+    }
+
+    // Find the start of the identifier under the cursor
+    let start = line[..pos]
+      .rfind(|c: char| c.is_whitespace() || "\\λ.;,()[]{}\"".contains(c))
+      .map(|i| i + line[i..].chars().next().map_or(1, char::len_utf8))
+      .unwrap_or(0);
+    let prefix = &line[start..pos];
+    if prefix.is_empty() || prefix.starts_with(':') {
+      return Ok((start, Vec::new()));
+    }
+
+    let all_globals = self.executor.all_globals().borrow();
+    let candidates = all_globals
+      .range(prefix..)
+      .take_while(|(name, _)| name.starts_with(prefix))
+      .map(|(name, _)| Pair {
+        display: name.to_string(),
+        replacement: name.to_string(),
+      })
+      .collect();
+
+    Ok((start, candidates))
+  }
+}
+
+impl Hinter for ReplHelper<'_> {
+  type Hint = String;
+}
+
+impl Highlighter for ReplHelper<'_> {}
+
+impl Validator for ReplHelper<'_> {}
+
+impl Helper for ReplHelper<'_> {}
+
 enum RunLineAction {
   Continue,
+  Paste,
+  History(usize),
+  Reload,
   Exit,
 }
 
@@ -77,35 +775,38 @@ impl<'text, 'assign> Repl<'text, 'assign>
 where
   'text: 'assign,
 {
-  pub fn new(text_data: &'text Arena<String>, executor: &'assign Executor<'assign>, show_steps: bool) -> Self {
-    static ABORT_EXECUTION: AtomicBool = AtomicBool::new(false);
-
+  pub fn new(text_data: &'text Arena<String>, executor: &'assign Executor<'assign>, state: ReplState) -> Self {
     Self {
       text_data,
       executor,
-      show_steps,
-      abort: &ABORT_EXECUTION,
+      options: state.options,
+      output: state.output,
+      loaded_files: state.loaded_files,
+      history: state.history,
+      prelude: state.prelude,
+      abort: install_abort_handler(),
     }
   }
 
-  pub fn run(mut self) -> super::CommandResult {
-    // Initialize the Ctrl+C handler
-    if let Err(e) = ctrlc::set_handler(|| {
-      self.abort.store(true, Ordering::Relaxed);
-    }) {
-      println!("{}: failed to set Ctrl+C handler", "Warning".yellow());
-      println!("{e}\n");
-    }
-
+  pub fn run(mut self) -> Result<ReplExit, Box<dyn std::error::Error>> {
     // Set up REPL editor
-    let mut editor = DefaultEditor::new()?;
+    let mut editor: ReplEditor = Editor::new()?;
     editor.set_auto_add_history(true);
+    editor.set_helper(Some(ReplHelper {
+      executor: self.executor,
+      filename_completer: FilenameCompleter::new(),
+    }));
+    if self.history.is_empty() {
+      // Only the first REPL starts with an empty history, since reloading always keeps the ":reload" line
+      load_history_file(&mut editor);
+    }
+    for line in self.history.drain(..) {
+      editor.add_history_entry(line)?;
+    }
 
     // We only want to exit if Ctrl+C pressed twice in a row
     let mut ctrl_c_should_exit = false;
 
-    println!("Welcome to Rusty Lambda, a lambda calculus interpreter");
-    println!("Type \":help\" for more information");
     loop {
       let line = match editor.readline("> ") {
         Ok(line) => {
@@ -113,10 +814,10 @@ where
           line
         },
 
-        Err(ReadlineError::Eof) => return Ok(()),
+        Err(ReadlineError::Eof) => return Ok(save_history_file(&mut editor)),
         Err(ReadlineError::Interrupted) => {
           if ctrl_c_should_exit {
-            return Ok(());
+            return Ok(save_history_file(&mut editor));
           }
 
           ctrl_c_should_exit = true;
@@ -131,11 +832,93 @@ where
         continue; // Skip empty lines
       }
 
+      // Keep reading lines until the code is complete
+      let line = if !line.trim_start().starts_with(':') && is_incomplete(&line) {
+        match self.read_continuation(&mut editor, line)? {
+          Some(line) => line,
+          None => continue,
+        }
+      } else {
+        line
+      };
+
+      if without_comments(&line).trim().is_empty() {
+        continue; // Nothing to run besides comments
+      }
+
       match self.run_line(line) {
         RunLineAction::Continue => continue,
-        RunLineAction::Exit => return Ok(()),
+        RunLineAction::Paste => self.paste(&mut editor)?,
+        RunLineAction::History(count) => print_history(&editor, count),
+        RunLineAction::Reload => {
+          return Ok(ReplExit::Reload(ReplState {
+            options: self.options,
+            output: self.output,
+            loaded_files: self.loaded_files,
+            history: editor.history().iter().cloned().collect(),
+            prelude: self.prelude,
+          }));
+        },
+        RunLineAction::Exit => return Ok(save_history_file(&mut editor)),
+      }
+    }
+  }
+
+  /// Load and run all of the files again, after the REPL was reloaded with a new executor
+  fn reload_files(&mut self) {
+    for path in mem::take(&mut self.loaded_files) {
+      self.load_file(&path.to_string_lossy());
+    }
+  }
+
+  /// Add lines to the code until it is complete or Ctrl+D is pressed. Returns None if cancelled with Ctrl+C.
+  fn read_continuation(&self, editor: &mut ReplEditor, mut code: String) -> Result<Option<String>, ReadlineError> {
+    while is_incomplete(&code) {
+      match editor.readline("... ") {
+        Ok(line) => {
+          code.push('\n');
+          code.push_str(&line);
+        },
+
+        Err(ReadlineError::Eof) => break,
+        Err(ReadlineError::Interrupted) => return Ok(None),
+        Err(e) => return Err(e),
+      }
+    }
+
+    Ok(Some(code))
+  }
+
+  /// Read lines until a lone `;;` or Ctrl+D, then load them all as a single program
+  fn paste(&self, editor: &mut ReplEditor) -> super::CommandResult {
+    println!("Paste mode: enter {} or press Ctrl+D to finish", ";;".white().bold());
+
+    let mut code = String::new();
+    loop {
+      match editor.readline("| ") {
+        Ok(line) if line.trim() == ";;" => break,
+        Ok(line) => {
+          code.push_str(&line);
+          code.push('\n');
+        },
+
+        Err(ReadlineError::Eof) => break,
+        Err(ReadlineError::Interrupted) => {
+          println!("Paste cancelled");
+          return Ok(());
+        },
+
+        Err(e) => return Err(e.into()),
       }
     }
+
+    let code = self.text_data.alloc(code);
+    match self.executor.load_code(code.as_str(), None) {
+      Ok(to_evaluate) => self.evaluate_all(to_evaluate),
+      Err(e) => println!("{} {e}", "Error:".red()),
+    }
+
+    Ok(())
   }
 
   fn run_line(&mut self, line: String) -> RunLineAction {
@@ -145,12 +928,40 @@ where
       Some(":e" | ":ex" | ":exi" | ":exit") => return RunLineAction::Exit,
       Some(":q" | ":qu" | ":qui" | ":quit") => return RunLineAction::Exit,
       Some(":h" | ":he" | ":hel" | ":help") => self.print_help(),
+      Some(":paste") => return RunLineAction::Paste,
+      Some(":history") => {
+        if let Some(count) = parse_history_count(&line, command_parts.collect()) {
+          return RunLineAction::History(count);
+        }
+      },
+      Some(":reload") => return RunLineAction::Reload,
+      Some(":clear" | ":cls") => self.clear_screen(),
+      Some(":env") => self.print_env(&line, command_parts.collect()),
       Some(":s" | ":st" | ":ste" | ":step" | ":steps") => self.set_steps(&line, command_parts.collect()),
-      Some(":a" | ":al" | ":all") => self.print_all_globals(),
+      Some(":decode") => self.set_decoders(&line, command_parts.collect()),
+      Some(":def" | ":show") => self.print_global(&line, command_parts.collect()),
+      Some(":undef" | ":unset") => self.remove_global(&line, command_parts.collect()),
+      Some(":numerals") => self.set_numerals(&line, command_parts.collect()),
+      Some(":indices") => self.set_indices(&line, command_parts.collect()),
+      Some(":pretty") => self.set_pretty(&line, command_parts.collect()),
+      Some(":ascii") => self.set_ascii(&line, command_parts.collect()),
+      Some(":maxdepth") => self.set_max_depth(&line, command_parts.collect()),
+      Some(":recognize") => self.set_recognize(&line, command_parts.collect()),
+      Some(":prelude") => self.set_prelude(&line, command_parts.collect()),
+      Some(":strategy") => self.set_strategy(&line, command_parts.collect()),
+      Some(":a" | ":al" | ":all" | ":globals") => self.print_all_globals(&line, command_parts.collect()),
       Some(prefix @ (":p" | ":pr" | ":pri" | ":prin" | ":print")) => {
         self.print_expression(strip_prefix(&line, prefix).to_string())
       },
+      Some(prefix @ ":eq") => self.print_equal(strip_prefix(&line, prefix)),
+      Some(prefix @ ":size") => self.print_sizes(strip_prefix(&line, prefix).to_string()),
+      Some(prefix @ ":save") => self.save_globals(strip_prefix(&line, prefix)),
       Some(prefix @ (":l" | ":lo" | ":loa" | ":load")) => self.load_file(strip_prefix(&line, prefix)),
+      Some(prefix @ (":c" | ":co" | ":cou" | ":coun" | ":count")) => {
+        self.print_count(strip_prefix(&line, prefix).to_string())
+      },
+      Some(prefix @ ":nf?") => self.print_is_normal_form(strip_prefix(&line, prefix).to_string()),
+      Some(prefix @ ":hnf") => self.print_head_normal_form(strip_prefix(&line, prefix).to_string()),
 
       // Not a built-in command, so run the line as code
       None | Some(_) => self.run_line_as_code(line),
@@ -162,13 +973,91 @@ where
   fn print_help(&self) {
     static ALL_COMMANDS: &[(&str, &str)] = &[
       (":all", "Print all named variables"),
+      (":all names", "Print only the names of all named variables"),
+      (":all size", "Print all named variables, sorted by size"),
+      (
+        ":ascii on",
+        "Print results with only ASCII characters, using \\ for lambdas and x_1 for shadowed parameters",
+      ),
+      (":ascii off", "Print results using λ and x′"),
+      (":clear", "Clear the screen, keeping all named variables"),
+      (":cls", "Alias for :clear"),
+      (
+        ":count <expr>",
+        "Print the number of reduction steps and size of the result, but not the result",
+      ),
+      (":def <name>", "Print the definition of a named variable"),
+      (
+        ":def <name> raw",
+        "Also print the definition using de Bruijn indices, exactly as it was parsed",
+      ),
+      (
+        ":env [pattern]",
+        "Print the binary encoding of each named variable as NAME=bits, optionally only names matching a * pattern",
+      ),
+      (
+        ":decode <list>",
+        "Choose which encodings to print in a readable form, like 'bool,pair,numeral,list' or 'none'",
+      ),
+      (
+        ":eq <expr> ;; <expr>",
+        "Evaluate two expressions and test if they are equal, ignoring parameter names",
+      ),
       (":exit", "Exit the REPL"),
+      (":globals", "Alias for :all"),
       (":help", "Print this help message"),
+      (":history [n]", "Print the last n lines entered, or the last 20 lines"),
+      (":hnf <expr>", "Reduce an expression to head normal form"),
+      (":indices on", "Print de Bruijn indices instead of parameter names"),
+      (":indices off", "Print parameter names"),
       (":load <file>", "Load and run a code file"),
+      (":maxdepth <n>", "Print any part of a result nested deeper than n as …"),
+      (":maxdepth off", "Print results no matter how deeply they are nested"),
+      (
+        ":nf? <expr>",
+        "Test if an expression is already in normal form, without evaluating it",
+      ),
+      (":numerals on", "Print Church numerals as plain numbers"),
+      (":numerals off", "Print Church numerals as lambda expressions"),
+      (":paste", "Run several lines as one program, ending with ;; or Ctrl+D"),
+      (":prelude on", "Load the prelude the next time the REPL is reloaded"),
+      (
+        ":prelude off",
+        "Don't load the prelude the next time the REPL is reloaded",
+      ),
+      (
+        ":pretty on",
+        "Leave out redundant parentheses, so (((a b) c) d) is printed as a b c d",
+      ),
+      (":pretty off", "Print every application in parentheses"),
       (":print <expr>", "Print an expression without evaluating it"),
       (":quit", "Alias for :exit"),
+      (
+        ":reload",
+        "Start over with only the prelude (unless it's off), then run every loaded file again",
+      ),
+      (
+        ":recognize on",
+        "Print any part of a result that equals a named variable using its name",
+      ),
+      (":recognize off", "Print results as lambda expressions"),
+      (
+        ":save <file>",
+        "Save all named variables that aren't from the prelude to a code file",
+      ),
+      (
+        ":size <expr>",
+        "Print the number of terms, lambdas and evals in an expression, without evaluating it",
+      ),
+      (":show <name>", "Alias for :def"),
       (":steps on", "Print reduction steps to stderr"),
       (":steps off", "Don't print reduction steps"),
+      (
+        ":strategy <name>",
+        "Set the reduction strategy to normal, applicative or cbv (call-by-value)",
+      ),
+      (":undef <name>", "Remove a named variable"),
+      (":unset <name>", "Alias for :undef"),
     ];
 
     let max_name_length = ALL_COMMANDS.iter().map(|(name, _)| (*name).len()).max().unwrap_or(1);
@@ -184,16 +1073,16 @@ where
   fn set_steps(&mut self, line: &str, args: Vec<&str>) {
     match args.first().cloned() {
       None => {
-        if self.show_steps {
+        if self.options.show_steps {
           println!("Reduction steps are {}", "on".green());
         } else {
           println!("Reduction steps are {}", "off".red());
         }
       },
 
-      Some("on" | "1" | "true") if args.len() == 1 => self.show_steps = true,
+      Some("on" | "1" | "true") if args.len() == 1 => self.options.show_steps = true,
 
-      Some("off" | "0" | "false") if args.len() == 1 => self.show_steps = false,
+      Some("off" | "0" | "false") if args.len() == 1 => self.options.show_steps = false,
 
       Some(_) => {
         println!(
@@ -205,17 +1094,419 @@ where
     }
   }
 
-  fn print_all_globals(&self) {
-    let all_globals = self.executor.all_globals().borrow();
+  fn set_decoders(&mut self, line: &str, args: Vec<&str>) {
+    let readable = &mut self.output.readable;
+    let [decoders] = args[..] else {
+      if args.is_empty() {
+        let active: Vec<_> = [
+          (readable.booleans, "bool"),
+          (readable.pairs, "pair"),
+          (readable.numerals, "numeral"),
+          (readable.lists, "list"),
+        ]
+        .into_iter()
+        .filter_map(|(active, name)| active.then_some(name))
+        .collect();
 
-    let max_name_length = all_globals.keys().map(|name| (*name).len()).max().unwrap_or(1);
-    for (name, value) in all_globals.iter() {
-      println!(
-        "{} = {value:#}",
-        format!("{name: <width$}", width = max_name_length).white().bold(),
+        let active = if active.is_empty() {
+          "none".to_string()
+        } else {
+          active.join(",")
+        };
+        return println!("Active decoders: {}", active.white().bold());
+      }
+
+      return println!(
+        "Expecting '{}', given '{line}'",
+        ":decode bool,pair,numeral,list".white().bold()
       );
-    }
-  }
+    };
+
+    let (mut booleans, mut pairs, mut numerals, mut lists) = (false, false, false, false);
+    for decoder in decoders.split(',').filter(|decoder| !decoder.is_empty()) {
+      match decoder {
+        "bool" => booleans = true,
+        "pair" => pairs = true,
+        "numeral" => numerals = true,
+        "list" => lists = true,
+        "none" => {},
+        _ => {
+          return println!(
+            "Unknown decoder '{decoder}', expecting any of '{}', '{}', '{}', '{}' or '{}'",
+            "bool".white().bold(),
+            "pair".white().bold(),
+            "numeral".white().bold(),
+            "list".white().bold(),
+            "none".white().bold(),
+          );
+        },
+      }
+    }
+
+    readable.booleans = booleans;
+    readable.pairs = pairs;
+    readable.numerals = numerals;
+    readable.lists = lists;
+  }
+
+  fn set_numerals(&mut self, line: &str, args: Vec<&str>) {
+    match args.first().cloned() {
+      None => {
+        if self.output.readable.numerals {
+          println!("Numeral decoding is {}", "on".green());
+        } else {
+          println!("Numeral decoding is {}", "off".red());
+        }
+      },
+
+      Some("on" | "1" | "true") if args.len() == 1 => self.output.readable.numerals = true,
+
+      Some("off" | "0" | "false") if args.len() == 1 => self.output.readable.numerals = false,
+
+      Some(_) => {
+        println!(
+          "Expecting either '{}' or '{}', given '{line}'",
+          ":numerals on".white().bold(),
+          ":numerals off".white().bold(),
+        )
+      },
+    }
+  }
+
+  fn set_indices(&mut self, line: &str, args: Vec<&str>) {
+    match args.first().cloned() {
+      None => {
+        if self.output.readable.de_bruijn_indices {
+          println!("De Bruijn indices are {}", "on".green());
+        } else {
+          println!("De Bruijn indices are {}", "off".red());
+        }
+      },
+
+      Some("on" | "1" | "true") if args.len() == 1 => self.output.readable.de_bruijn_indices = true,
+
+      Some("off" | "0" | "false") if args.len() == 1 => self.output.readable.de_bruijn_indices = false,
+
+      Some(_) => {
+        println!(
+          "Expecting either '{}' or '{}', given '{line}'",
+          ":indices on".white().bold(),
+          ":indices off".white().bold(),
+        )
+      },
+    }
+  }
+
+  fn set_pretty(&mut self, line: &str, args: Vec<&str>) {
+    match args.first().cloned() {
+      None => {
+        if self.output.readable.minimal_parens {
+          println!("Pretty printing is {}", "on".green());
+        } else {
+          println!("Pretty printing is {}", "off".red());
+        }
+      },
+
+      Some("on" | "1" | "true") if args.len() == 1 => self.output.readable.minimal_parens = true,
+
+      Some("off" | "0" | "false") if args.len() == 1 => self.output.readable.minimal_parens = false,
+
+      Some(_) => {
+        println!(
+          "Expecting either '{}' or '{}', given '{line}'",
+          ":pretty on".white().bold(),
+          ":pretty off".white().bold(),
+        )
+      },
+    }
+  }
+
+  fn set_ascii(&mut self, line: &str, args: Vec<&str>) {
+    match args.first().cloned() {
+      None => {
+        if self.output.readable.ascii {
+          println!("ASCII output is {}", "on".green());
+        } else {
+          println!("ASCII output is {}", "off".red());
+        }
+      },
+
+      Some("on" | "1" | "true") if args.len() == 1 => self.output.readable.ascii = true,
+
+      Some("off" | "0" | "false") if args.len() == 1 => self.output.readable.ascii = false,
+
+      Some(_) => {
+        println!(
+          "Expecting either '{}' or '{}', given '{line}'",
+          ":ascii on".white().bold(),
+          ":ascii off".white().bold(),
+        )
+      },
+    }
+  }
+
+  fn set_recognize(&mut self, line: &str, args: Vec<&str>) {
+    match args.first().cloned() {
+      None => {
+        if self.output.recognize {
+          println!("Recognizing named variables is {}", "on".green());
+        } else {
+          println!("Recognizing named variables is {}", "off".red());
+        }
+      },
+
+      Some("on" | "1" | "true") if args.len() == 1 => self.output.recognize = true,
+
+      Some("off" | "0" | "false") if args.len() == 1 => self.output.recognize = false,
+
+      Some(_) => {
+        println!(
+          "Expecting either '{}' or '{}', given '{line}'",
+          ":recognize on".white().bold(),
+          ":recognize off".white().bold(),
+        )
+      },
+    }
+  }
+
+  fn set_prelude(&mut self, line: &str, args: Vec<&str>) {
+    match args.first().cloned() {
+      None => {
+        if self.prelude {
+          println!("Loading the prelude is {}", "on".green());
+        } else {
+          println!("Loading the prelude is {}", "off".red());
+        }
+      },
+
+      Some(setting @ ("on" | "1" | "true" | "off" | "0" | "false")) if args.len() == 1 => {
+        self.prelude = matches!(setting, "on" | "1" | "true");
+
+        // Definitions are substituted when parsed, so the prelude can't be added or removed in place
+        println!("Use {} to start over with the new setting", ":reload".white().bold());
+      },
+
+      Some(_) => {
+        println!(
+          "Expecting either '{}' or '{}', given '{line}'",
+          ":prelude on".white().bold(),
+          ":prelude off".white().bold(),
+        )
+      },
+    }
+  }
+
+  fn set_max_depth(&mut self, line: &str, args: Vec<&str>) {
+    match args[..] {
+      [] => match self.output.readable.max_depth {
+        Some(max_depth) => println!("Maximum print depth is {}", max_depth.to_string().white().bold()),
+        None => println!("Maximum print depth is {}", "off".red()),
+      },
+
+      ["off" | "0" | "false"] => self.output.readable.max_depth = None,
+
+      [depth] => match depth.parse::<NonZero<u64>>() {
+        Ok(depth) => self.output.readable.max_depth = Some(depth),
+        Err(_) => println!("Expecting a positive number or 'off', given '{depth}'"),
+      },
+
+      _ => println!(
+        "Expecting either '{}' or '{}', given '{line}'",
+        ":maxdepth <n>".white().bold(),
+        ":maxdepth off".white().bold(),
+      ),
+    }
+  }
+
+  fn set_strategy(&mut self, line: &str, args: Vec<&str>) {
+    match args[..] {
+      [] => println!(
+        "Reduction strategy is {}",
+        strategy_name(self.options.strategy).white().bold()
+      ),
+
+      [name] => match ReductionStrategy::from_str(name, true) {
+        Ok(strategy) => self.options.strategy = strategy,
+        Err(_) => println!(
+          "Unknown strategy '{name}', expecting one of {}",
+          ReductionStrategy::value_variants()
+            .iter()
+            .map(|strategy| format!("'{}'", strategy_name(*strategy)))
+            .collect::<Vec<_>>()
+            .join(", ")
+        ),
+      },
+
+      _ => println!("Expecting '{}', given '{line}'", ":strategy <name>".white().bold()),
+    }
+  }
+
+  fn print_all_globals(&self, line: &str, args: Vec<&str>) {
+    let mut names_only = false;
+    let mut sort_by_size = false;
+    for arg in args {
+      match arg {
+        "names" => names_only = true,
+        "size" => sort_by_size = true,
+        "name" => sort_by_size = false,
+        _ => {
+          return println!(
+            "Expecting any of '{}', '{}' or '{}', given '{line}'",
+            ":all names".white().bold(),
+            ":all size".white().bold(),
+            ":all name".white().bold(),
+          );
+        },
+      }
+    }
+
+    let all_globals = self.executor.all_globals().borrow();
+    let mut sorted: Vec<_> = all_globals.iter().collect();
+    if sort_by_size {
+      sorted.sort_by_key(|(_, value)| value.size()); // Stable, so equal sizes stay sorted by name
+    }
+
+    if names_only {
+      for (name, _) in sorted {
+        println!("{}", name.white().bold());
+      }
+      return;
+    }
+
+    let max_name_length = all_globals.keys().map(|name| (*name).len()).max().unwrap_or(1);
+    for (name, value) in sorted {
+      println!(
+        "{} = {}",
+        format!("{name: <width$}", width = max_name_length).white().bold(),
+        truncate(format!("{value:#}"), self.output.max_chars),
+      );
+    }
+  }
+
+  /// Clear the terminal and move the prompt back to the top. Does nothing if the output isn't a terminal.
+  fn clear_screen(&self) {
+    let mut stdout = io::stdout();
+    if !stdout.is_terminal() {
+      return;
+    }
+
+    if let Err(e) = execute!(stdout, Clear(ClearType::All), Clear(ClearType::Purge), MoveTo(0, 0)) {
+      println!("{} {e}", "Error:".red());
+    }
+  }
+
+  /// Print globals as `NAME=bits` lines, which can be used with `eval` in a shell or saved to a `.env` file
+  fn print_env(&self, line: &str, args: Vec<&str>) {
+    let pattern = match args[..] {
+      [] => "*",
+      [pattern] => pattern,
+      _ => return println!("Expecting '{}', given '{line}'", ":env [pattern]".white().bold()),
+    };
+
+    let mut skipped = 0;
+    for (name, value) in self.executor.all_globals().borrow().iter() {
+      if !matches_wildcard(pattern, name) {
+        continue;
+      }
+
+      if !is_shell_variable_name(name) {
+        skipped += 1;
+        continue;
+      }
+
+      let mut visitor = PrintVisitor::new("0", "1");
+      value.visit(&mut visitor);
+      println!("{name}={}", visitor.into_string());
+    }
+
+    if skipped > 0 {
+      println!("# Skipped {skipped} variable(s) whose names can't be used in a shell");
+    }
+  }
+
+  fn print_global(&self, line: &str, args: Vec<&str>) {
+    let (name, raw) = match args[..] {
+      [name] => (name, false),
+      [name, "raw"] => (name, true),
+      _ => {
+        return println!(
+          "Expecting '{}' or '{}', given '{line}'",
+          ":def <name>".white().bold(),
+          ":def <name> raw".white().bold(),
+        );
+      },
+    };
+
+    let Some(value) = self.executor.get_global(name) else {
+      return println!("{name} is not defined");
+    };
+
+    println!(
+      "{} = {}",
+      name.white().bold(),
+      truncate(format!("{value:#}"), self.output.max_chars)
+    );
+    if raw {
+      println!(
+        "{} = {}",
+        name.white().bold(),
+        truncate(format!("{value:+#}"), self.output.max_chars)
+      );
+    }
+  }
+
+  fn remove_global(&self, line: &str, args: Vec<&str>) {
+    let [name] = args[..] else {
+      return println!("Expecting '{}', given '{line}'", ":undef <name>".white().bold());
+    };
+
+    if self.executor.all_globals().borrow_mut().remove(name).is_none() {
+      return println!("no such global: {name}");
+    }
+
+    println!(
+      "Removed {}. Definitions that already use it keep the old value, since globals are substituted when parsed.",
+      name.white().bold()
+    );
+  }
+
+  fn save_globals(&self, filename: &str) {
+    if filename.is_empty() {
+      return println!("Expecting '{}'", ":save <file>".white().bold());
+    }
+
+    let result = (|| -> super::CommandResult {
+      // Skip the prelude definitions, so the file can be loaded without any duplicate variables
+      let prelude = Executor::new();
+      prelude.load_code(crate::PRELUDE, None)?;
+      let prelude_globals = prelude.all_globals().borrow();
+
+      let mut code = String::new();
+      code.push_str("; Saved from the REPL. Any other named variables used by a definition were\n");
+      code.push_str("; substituted when it was parsed, so every definition is fully expanded.\n");
+
+      let mut count = 0;
+      for (name, value) in self.executor.all_globals().borrow().iter() {
+        if prelude_globals
+          .get(name)
+          .is_some_and(|prelude_value| prelude_value.structurally_eq(*value))
+        {
+          continue;
+        }
+
+        code.push_str(&format!("{name} = {value:#}\n"));
+        count += 1;
+      }
+
+      fs::write(filename, code)?;
+      println!("Saved {count} definition(s) to {}", filename.white());
+      Ok(())
+    })();
+
+    if let Err(e) = result {
+      println!("{} {e}", "Error:".red());
+    }
+  }
 
   fn print_expression(&self, expr: String) {
     let line = self.text_data.alloc(expr);
@@ -226,27 +1517,121 @@ where
     }
   }
 
-  fn load_file(&self, filename: &str) {
-    let result = (|| -> super::CommandResult {
-      println!("Loading file: {}", filename.white());
+  fn print_sizes(&self, expr: String) {
+    let line = self.text_data.alloc(expr);
+    let eval_allocator = Allocator::new();
+    match self.executor.load_expression(&eval_allocator, line.as_str()) {
+      Ok(expr) => {
+        let sizes = expr.sizes();
+        println!(
+          "Terms: {}, Lambdas: {}, Evals: {}, Total: {}, Depth: {}",
+          sizes.terms,
+          sizes.lambdas,
+          sizes.evals,
+          sizes.terms + sizes.lambdas + sizes.evals,
+          sizes.depth
+        );
+      },
+      Err(e) => println!("{e}"),
+    }
+  }
 
-      let file_data = self.text_data.alloc(fs::read_to_string(filename)?);
-      let to_evaluate = self.executor.load_code(file_data.as_str(), Some(filename))?;
+  fn print_is_normal_form(&self, expr: String) {
+    let line = self.text_data.alloc(expr);
+    let eval_allocator = Allocator::new();
+    match self.executor.load_expression(&eval_allocator, line.as_str()) {
+      Ok(expr) if is_normal_form(expr) => println!("{}", "true".green()),
+      Ok(_) => println!("{}", "false".red()),
+      Err(e) => println!("{e}"),
+    }
+  }
 
-      println!("Running code...");
-      for expr in to_evaluate {
-        let eval_allocator = Allocator::new();
-        self.abort.store(false, Ordering::Relaxed);
+  fn print_equal(&self, line: &str) {
+    let Some((first, second)) = line.split_once(";;") else {
+      return println!("Expecting '{}'", ":eq <expr> ;; <expr>".white().bold());
+    };
 
-        let result = self
-          .executor
-          .evaluate_with_abort(&eval_allocator, expr, self.show_steps, self.abort);
+    let eval_allocator = Allocator::new();
+    let mut results = Vec::new();
+    for expr in [first, second] {
+      let code = self.text_data.alloc(expr.to_string());
+      let expr = match self.executor.load_expression(&eval_allocator, code.as_str()) {
+        Ok(expr) => expr,
+        Err(e) => return println!("{e}"),
+      };
 
-        match result {
-          None => println!("Interrupted"),
-          Some(result) => println!("{result:#}"),
-        }
+      self.abort.store(false, Ordering::Relaxed);
+      match self
+        .executor
+        .evaluate_with_abort(&eval_allocator, expr, self.options, self.abort)
+      {
+        Ok(result) => results.push(result),
+        Err(e) => return print_eval_error(e),
       }
+    }
+
+    match results[0].first_difference(results[1]) {
+      None => println!("{}", "true".green()),
+      Some(difference) => println!("{} (first difference at {difference})", "false".red()),
+    }
+  }
+
+  fn print_head_normal_form(&self, expr: String) {
+    let line = self.text_data.alloc(expr);
+    let eval_allocator = Allocator::new();
+    let expr = match self.executor.load_expression(&eval_allocator, line.as_str()) {
+      Ok(expr) => expr,
+      Err(e) => return println!("{e}"),
+    };
+
+    self.abort.store(false, Ordering::Relaxed);
+    let options = EvalOptions {
+      normal_form: NormalForm::Head,
+      strategy: ReductionStrategy::NormalOrder,
+      ..self.options
+    };
+
+    match self
+      .executor
+      .evaluate_with_abort_and_stats(&eval_allocator, expr, options, self.abort)
+    {
+      Ok((result, stats)) => print_result(self.executor, result, self.output, Some(stats.steps)),
+      Err(e) => print_eval_error(e),
+    }
+  }
+
+  fn print_count(&self, expr: String) {
+    let line = self.text_data.alloc(expr);
+    let eval_allocator = Allocator::new();
+    let expr = match self.executor.load_expression(&eval_allocator, line.as_str()) {
+      Ok(expr) => expr,
+      Err(e) => return println!("{e}"),
+    };
+
+    self.abort.store(false, Ordering::Relaxed);
+    match self
+      .executor
+      .evaluate_with_abort_and_stats(&eval_allocator, expr, self.options, self.abort)
+    {
+      Ok((_, stats)) => println!("Steps: {}, Size: {}", stats.steps, stats.size),
+      Err(e) => print_eval_error(e),
+    }
+  }
+
+  fn load_file(&mut self, filename: &str) {
+    // Remember the file for :reload, even if it has errors that still need to be fixed
+    let path = PathBuf::from(filename);
+    if path.is_file() && !self.loaded_files.contains(&path) {
+      self.loaded_files.push(path);
+    }
+
+    let result = (|| -> super::CommandResult {
+      println!("Loading file: {}", filename.white());
+
+      let to_evaluate = self.executor.load_file(Path::new(filename))?;
+
+      println!("Running code...");
+      self.evaluate_all(to_evaluate);
 
       Ok(())
     })();
@@ -256,22 +1641,49 @@ where
     }
   }
 
+  /// Evaluate and print each expression in order
+  fn evaluate_all(&self, to_evaluate: Vec<ExprRef<'assign>>) {
+    for expr in to_evaluate {
+      let eval_allocator = Allocator::new();
+      self.abort.store(false, Ordering::Relaxed);
+
+      let result = self
+        .executor
+        .evaluate_with_abort_and_stats(&eval_allocator, expr, self.options, self.abort);
+
+      match result {
+        Ok((result, stats)) => print_result(self.executor, result, self.output, Some(stats.steps)),
+        Err(e) => print_eval_error(e),
+      }
+    }
+  }
+
+  /// Run each statement on the line in order, like `a = 3 ;; (succ a) ;; a`
   fn run_line_as_code(&self, line: String) {
     let line = self.text_data.alloc(line);
+    for statement in split_statements(line.as_str()) {
+      if !without_comments(statement).trim().is_empty() {
+        self.run_statement(statement);
+      }
+    }
+  }
+
+  fn run_statement(&self, statement: &'text str) {
+    // Each statement gets its own allocator, so the memory from evaluating it is freed right after
     let eval_allocator = Allocator::new();
 
-    match self.executor.load_statement(&eval_allocator, line.as_str()) {
+    match self.executor.load_statement(&eval_allocator, statement) {
       Ok(None) => {},
       Ok(Some(expr)) => {
         self.abort.store(false, Ordering::Relaxed);
 
         let result = self
           .executor
-          .evaluate_with_abort(&eval_allocator, expr, self.show_steps, self.abort);
+          .evaluate_with_abort_and_stats(&eval_allocator, expr, self.options, self.abort);
 
         match result {
-          None => println!("Interrupted"),
-          Some(result) => println!("{result:#}"),
+          Ok((result, stats)) => print_result(self.executor, result, self.output, Some(stats.steps)),
+          Err(e) => print_eval_error(e),
         }
       },
 
@@ -280,7 +1692,412 @@ where
   }
 }
 
+/// Settings for printing the results of evaluations
+#[derive(Clone, Copy)]
+struct OutputOptions {
+  annotate: bool,
+  recognize: bool,
+  max_chars: Option<usize>,
+  readable: ReadableOptions,
+}
+
+/// Print the result of an evaluation, optionally noting any named variables with the same value
+/// and the number of reduction steps it took
+fn print_result(executor: &Executor<'_>, result: ExprRef<'_>, output: OutputOptions, steps: Option<u64>) {
+  let result = format_result(executor, result, output);
+  match steps {
+    Some(1) => println!("{result} {}", "(1 step)".dark_grey()),
+    Some(steps) => println!("{result} {}", format!("({steps} steps)").dark_grey()),
+    None => println!("{result}"),
+  }
+}
+
+/// Format the result of an evaluation, followed by `(= name)` for every named variable with the same value if annotating
+fn format_result(executor: &Executor<'_>, result: ExprRef<'_>, output: OutputOptions) -> String {
+  let equal_globals = if output.annotate {
+    executor.find_equal_globals(result)
+  } else {
+    Vec::new()
+  };
+
+  let result = truncate(format_output(executor, result, output), output.max_chars);
+  if equal_globals.is_empty() {
+    result
+  } else {
+    format!("{result} (= {})", equal_globals.join(", "))
+  }
+}
+
+/// Format an expression using the readable options, and the names of all globals if recognizing known terms
+fn format_output(executor: &Executor<'_>, expr: ExprRef<'_>, output: OutputOptions) -> String {
+  if !output.recognize {
+    return format_readable(expr, output.readable);
+  }
+
+  let globals = executor.all_globals().borrow();
+  format_readable_with_known_terms(expr, output.readable, &KnownTerms::new(&globals))
+}
+
+/// Print the elements of a list, ending with "..." if there are more elements.
+/// Elements that didn't finish within the limits are printed as `<diverges>` or `<too large>`.
+fn print_list_prefix(executor: &Executor<'_>, prefix: ListPrefix<'_>, output: OutputOptions) {
+  let elements: Vec<String> = prefix
+    .elements
+    .into_iter()
+    .map(|element| match element {
+      Ok(element) => format_output(executor, element, output),
+      Err(EvalError::SizeLimit(_)) => "<too large>".to_string(),
+      Err(_) => "<diverges>".to_string(),
+    })
+    .collect();
+
+  let list = match prefix.end {
+    ListEnd::Nil => format!("[{}]", elements.join(", ")),
+    ListEnd::More => format!("[{}, ...]", elements.join(", ")),
+    ListEnd::Improper(tail) => format!("[{} | {}]", elements.join(", "), format_output(executor, tail, output)),
+  };
+  println!("{}", truncate(list, output.max_chars));
+}
+
+/// Marks the end of a string cut short by [truncate]
+const TRUNCATED_SUFFIX: &str = "...";
+
+/// Cut a string down to at most `max_chars` characters, ending with "..." if anything was removed
+fn truncate(mut s: String, max_chars: Option<usize>) -> String {
+  let Some(max_chars) = max_chars else {
+    return s;
+  };
+
+  if s.chars().count() <= max_chars {
+    return s;
+  }
+
+  // Leave room for the ellipsis, which is also cut short if the limit is too small to fit all of it
+  let ellipsis = &TRUNCATED_SUFFIX[..max_chars.min(TRUNCATED_SUFFIX.len())];
+  let keep = max_chars - ellipsis.len();
+  if let Some((index, _)) = s.char_indices().nth(keep) {
+    s.truncate(index);
+  }
+  s.push_str(ellipsis);
+  s
+}
+
+/// Match a name against a pattern where `*` matches any sequence of characters
+fn matches_wildcard(pattern: &str, name: &str) -> bool {
+  match pattern.split_once('*') {
+    None => pattern == name,
+    Some((prefix, rest)) => name.strip_prefix(prefix).is_some_and(|name| {
+      name
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([name.len()])
+        .any(|i| matches_wildcard(rest, &name[i..]))
+    }),
+  }
+}
+
+/// Shell variables can only use ASCII letters, digits and underscores, and can't start with a digit
+fn is_shell_variable_name(name: &str) -> bool {
+  !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn strategy_name(strategy: ReductionStrategy) -> String {
+  strategy
+    .to_possible_value()
+    .map(|value| value.get_name().to_string())
+    .unwrap_or_default()
+}
+
+/// Number of lines printed by `:history` when no count is given
+const DEFAULT_HISTORY_COUNT: usize = 20;
+
+/// File in the home directory that keeps the REPL history between sessions
+const HISTORY_FILE_NAME: &str = ".rusty_lambda_history";
+
+/// Path of the history file, or None if the home directory is unknown
+fn history_file() -> Option<PathBuf> {
+  std::env::home_dir()
+    .filter(|home| !home.as_os_str().is_empty())
+    .map(|home| home.join(HISTORY_FILE_NAME))
+}
+
+/// Load the history from earlier sessions. The file doesn't exist until the first session is saved.
+fn load_history_file(editor: &mut ReplEditor) {
+  let Some(path) = history_file() else {
+    return;
+  };
+
+  match editor.load_history(&path) {
+    Ok(()) => {},
+    Err(ReadlineError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {},
+    Err(e) => println!(
+      "{} cannot load history from {}: {e}",
+      "Warning:".yellow(),
+      path.display()
+    ),
+  }
+}
+
+/// Save the history for the next session when the REPL exits
+fn save_history_file(editor: &mut ReplEditor) -> ReplExit {
+  if let Some(path) = history_file()
+    && let Err(e) = editor.save_history(&path)
+  {
+    println!("{} cannot save history to {}: {e}", "Warning:".yellow(), path.display());
+  }
+
+  ReplExit::Exit
+}
+
+fn parse_history_count(line: &str, args: Vec<&str>) -> Option<usize> {
+  let count = match args[..] {
+    [] => Some(DEFAULT_HISTORY_COUNT),
+    [count] => count.parse().ok(),
+    _ => None,
+  };
+
+  if count.is_none() {
+    println!("Expecting '{}', given '{line}'", ":history <n>".white().bold());
+  }
+  count
+}
+
+/// Print the most recent lines of the history, numbered from the start of the history
+fn print_history(editor: &ReplEditor, count: usize) {
+  let history = editor.history();
+  let start = history.len().saturating_sub(count);
+  let width = history.len().to_string().len();
+  for (i, line) in history.iter().enumerate().skip(start) {
+    println!("{}  {line}", format!("{: >width$}", i + 1).white().bold());
+  }
+}
+
+/// Code needs more lines if it has unclosed brackets, or ends with a lambda that has no body yet
+fn is_incomplete(code: &str) -> bool {
+  if bracket_depth(code) > 0 || find_comments(code).iter().any(|comment| comment.is_unclosed) {
+    return true;
+  }
+
+  let code = without_comments(code);
+  let last_line = code.lines().last().unwrap_or_default().trim_end();
+  last_line.ends_with(['\\', 'λ', '.'])
+}
+
+/// Remove every comment, but keep the line breaks inside of block comments
+fn without_comments(code: &str) -> String {
+  let mut result = String::with_capacity(code.len());
+  let mut index = 0;
+  for comment in find_comments(code) {
+    result.push_str(&code[index..comment.offset]);
+    result.extend(comment.text.chars().filter(|c| matches!(c, '\n' | '\r')));
+    index = comment.offset + comment.text.len();
+  }
+  result.push_str(&code[index..]);
+
+  result
+}
+
+/// Number of `(`, `[` and `{` that haven't been closed yet, ignoring comments and string literals.
+/// Negative if there are more closing brackets than opening brackets.
+fn bracket_depth(code: &str) -> i64 {
+  let mut depth = 0;
+  for line in without_comments(code).lines() {
+    let mut in_string = false;
+    for c in line.chars() {
+      match c {
+        '"' => in_string = !in_string,
+        _ if in_string => {},
+        '(' | '[' | '{' => depth += 1,
+        ')' | ']' | '}' => depth -= 1,
+        _ => {},
+      }
+    }
+  }
+
+  depth
+}
+
+/// Split a REPL line into the statements separated by `;;`, skipping over anything inside brackets,
+/// string literals and other comments. The `;;` is still the start of a comment in code files.
+fn split_statements(code: &str) -> Vec<&str> {
+  let mut statements = Vec::new();
+  let mut rest = code;
+  loop {
+    let separator = find_comments(rest)
+      .into_iter()
+      .find(|comment| comment.text.starts_with(";;") && bracket_depth(&rest[..comment.offset]) == 0);
+
+    let Some(separator) = separator else {
+      statements.push(rest);
+      return statements;
+    };
+
+    statements.push(&rest[..separator.offset]);
+    rest = &rest[separator.offset + 2..];
+  }
+}
+
 fn strip_prefix<'a>(input: &'a str, prefix: &str) -> &'a str {
   let s = input.trim();
   s.strip_prefix(prefix).unwrap_or(s).trim_start()
 }
+
+#[cfg(test)]
+mod tests {
+  use clap::Parser;
+  use clap::error::ErrorKind;
+
+  use super::*;
+
+  /// Parse the arguments of the run command the same way as the command line
+  fn parse_run_args(args: &[&str]) -> Result<RunArgs, clap::Error> {
+    #[derive(Parser)]
+    struct Cli {
+      #[clap(flatten)]
+      run: RunArgs,
+    }
+
+    Cli::try_parse_from(["lambda"].iter().chain(args)).map(|cli| cli.run)
+  }
+
+  #[test]
+  fn annotate_names_the_globals_equal_to_the_result() {
+    let executor = Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+    executor.load_code("five = 5", None).unwrap();
+
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, "(succ 4)").unwrap();
+    let result = executor.evaluate(&eval_allocator, expr, EvalOptions::default());
+
+    let output = parse_run_args(&["--annotate", "--decode-numerals"])
+      .unwrap()
+      .output_options();
+    assert_eq!(format_result(&executor, result, output), "5 (= five)");
+
+    // Without the flag, the result is printed by itself
+    let output = parse_run_args(&["--decode-numerals"]).unwrap().output_options();
+    assert_eq!(format_result(&executor, result, output), "5");
+  }
+
+  #[test]
+  fn ctrl_c_handler_is_only_installed_once() {
+    let args = parse_run_args(&[]).unwrap();
+    let text_data = Arena::new();
+    let executor = Executor::new();
+    let new_repl = || {
+      let state = ReplState {
+        options: args.eval_options(),
+        output: args.output_options(),
+        loaded_files: Vec::new(),
+        history: Vec::new(),
+        prelude: false,
+      };
+      Repl::new(&text_data, &executor, state)
+    };
+
+    // Reloading builds a new REPL, which shares the flag instead of registering another handler
+    let first = new_repl();
+    let second = new_repl();
+    assert!(std::ptr::eq(first.abort, second.abort));
+    assert!(matches!(ctrlc::set_handler(|| {}), Err(ctrlc::Error::MultipleHandlers)));
+  }
+
+  #[test]
+  fn watch_waits_for_a_change_to_a_watched_file() {
+    use notify::event::{AccessKind, CreateKind, DataChange, ModifyKind};
+
+    let watched = PathBuf::from("/code/main.txt");
+    let files = HashSet::from([watched.clone()]);
+    let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+    let (sender, receiver) = mpsc::channel();
+
+    // Only creating or modifying one of the files counts, so the first two events are skipped
+    let other_file = Event::new(modify).add_path("/code/other.txt".into());
+    let access = Event::new(EventKind::Access(AccessKind::Any)).add_path(watched.clone());
+    assert!(!is_file_changed(&other_file, &files));
+    assert!(!is_file_changed(&access, &files));
+    sender.send(Ok(other_file)).unwrap();
+    sender.send(Ok(access)).unwrap();
+
+    // A single save can create and then modify the file, which should only cause one reload
+    sender
+      .send(Ok(
+        Event::new(EventKind::Create(CreateKind::File)).add_path(watched.clone()),
+      ))
+      .unwrap();
+    sender.send(Ok(Event::new(modify).add_path(watched))).unwrap();
+    wait_for_change(&receiver, &files).unwrap();
+    assert!(receiver.try_recv().is_err());
+  }
+
+  #[test]
+  fn no_eval_rejects_every_eval_only_flag() {
+    let eval_only = [
+      &["--steps"][..],
+      &["--hnf"],
+      &["--strategy", "applicative"],
+      &["--annotate"],
+      &["--recognize"],
+      &["--booleans"],
+      &["--pairs"],
+      &["--decode-numerals"],
+      &["--church-lists"],
+      &["--scott"],
+      &["--take", "3"],
+      &["--profile"],
+      &["--progress"],
+      &["--table", "rows.txt", "--term", "succ"],
+      &["--evaluate-all"],
+      &["--timeout", "5s"],
+      &["--detect-cycles"],
+      &["--abort-check-interval", "10"],
+      &["--memo"],
+      &["--eta"],
+    ];
+
+    for flags in eval_only {
+      let args = [&["--no-eval"], flags, &["code.txt"]].concat();
+      let error = parse_run_args(&args)
+        .err()
+        .unwrap_or_else(|| panic!("{flags:?} should conflict"));
+      assert_eq!(error.kind(), ErrorKind::ArgumentConflict, "{flags:?}");
+      assert!(error.to_string().contains("--no-eval"), "{flags:?}: {error}");
+
+      // Every flag still works on its own
+      let args = [flags, &["code.txt"]].concat();
+      assert!(parse_run_args(&args).is_ok(), "{flags:?}");
+    }
+  }
+
+  #[test]
+  fn no_eval_accepts_printing_flags() {
+    assert!(parse_run_args(&["--no-eval", "code.txt"]).is_ok());
+    assert!(parse_run_args(&["--no-eval", "--pretty", "--ascii", "--max-depth", "3", "code.txt"]).is_ok());
+    assert!(parse_run_args(&["--no-eval", "--max-output-chars", "40", "--only", "2", "code.txt"]).is_ok());
+  }
+
+  #[test]
+  fn bracket_depth_ignores_comments_and_strings() {
+    assert_eq!(bracket_depth("(add [1 2] {3"), 2);
+    assert_eq!(bracket_depth("(succ 1))"), -1);
+    assert_eq!(bracket_depth("(f ; (((\n  x)"), 0);
+    assert_eq!(bracket_depth("(f {- ((( -} x"), 1);
+    assert_eq!(bracket_depth("include \"((.txt\""), 0);
+
+    assert!(is_incomplete("(add 1"));
+    assert!(is_incomplete(r"sq = \n."));
+    assert!(is_incomplete("(f {- unclosed"));
+    assert!(!is_incomplete("(add 1 2) ; (((\n"));
+    assert!(!is_incomplete(r"sq = \n.(mul n n)"));
+  }
+
+  #[test]
+  fn truncate_fits_the_ellipsis_inside_the_limit() {
+    assert_eq!(truncate("abcdefghij".to_string(), Some(8)), "abcde...");
+    assert_eq!(truncate("abcdefghij".to_string(), Some(10)), "abcdefghij");
+    assert_eq!(truncate("λλλλλλ".to_string(), Some(5)), "λλ...");
+    assert_eq!(truncate("abcdef".to_string(), Some(2)), "..");
+    assert_eq!(truncate("abcdef".to_string(), None), "abcdef");
+  }
+}
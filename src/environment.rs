@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{Read, Write};
+use std::num::NonZero;
+
+use typed_arena::Arena;
+
+use crate::expr::{Allocator, ExprRef, ExprVisitor, MAX_STR_LENGTH};
+
+/// Every environment file starts with these bytes
+const MAGIC: &[u8] = b"LAMBDAENV";
+
+/// Incremented whenever the file format changes
+const VERSION: u64 = 1;
+
+const TAG_LAMBDA: u8 = 0;
+const TAG_EVAL: u8 = 1;
+
+/// Globals and numbers read back from an environment file
+pub struct Environment<'a> {
+  pub globals: Vec<(&'a str, ExprRef<'a>)>,
+  pub numbers: Vec<ExprRef<'a>>,
+}
+
+/// Write all globals and the cached Church numerals to a compact binary format:
+///
+/// - Header: `LAMBDAENV` followed by the version number
+/// - String table: every parameter and global name, without duplicates
+/// - Node table: every lambda and eval node, with shared subtrees only stored once
+/// - Globals: pairs of (name, expression)
+/// - Numbers: list of expressions
+///
+/// All integers are stored as LEB128 variable-length integers. Expressions are stored as
+///  `(index << 1) | 1` for a term with a de Bruijn index, or `id << 1` for a node in the table.
+pub fn write_environment<'a>(
+  writer: &mut dyn Write,
+  globals: &BTreeMap<&'a str, ExprRef<'a>>,
+  numbers: &[ExprRef<'a>],
+) -> Result<(), Box<dyn Error>> {
+  let mut builder = TableBuilder::default();
+
+  let global_refs: Vec<_> = globals
+    .iter()
+    .map(|(name, expr)| (builder.add_string(name), expr.visit(&mut builder)))
+    .collect();
+  let number_refs: Vec<_> = numbers.iter().map(|expr| expr.visit(&mut builder)).collect();
+
+  let mut bytes = MAGIC.to_vec();
+  write_varint(&mut bytes, VERSION);
+
+  write_varint(&mut bytes, builder.strings.len() as u64);
+  for s in builder.strings.iter() {
+    write_varint(&mut bytes, s.len() as u64);
+    bytes.extend_from_slice(s.as_bytes());
+  }
+
+  write_varint(&mut bytes, builder.nodes.len() as u64);
+  for node in builder.nodes.iter() {
+    match *node {
+      Node::Lambda { name, body } => {
+        bytes.push(TAG_LAMBDA);
+        write_varint(&mut bytes, name);
+        write_varint(&mut bytes, body);
+      },
+      Node::Eval { left, right } => {
+        bytes.push(TAG_EVAL);
+        write_varint(&mut bytes, left);
+        write_varint(&mut bytes, right);
+      },
+    }
+  }
+
+  write_varint(&mut bytes, global_refs.len() as u64);
+  for (name, expr) in global_refs {
+    write_varint(&mut bytes, name);
+    write_varint(&mut bytes, expr);
+  }
+
+  write_varint(&mut bytes, number_refs.len() as u64);
+  for expr in number_refs {
+    write_varint(&mut bytes, expr);
+  }
+
+  writer.write_all(&bytes)?;
+  Ok(())
+}
+
+/// Read an environment written by [write_environment], allocating all expressions using the allocator
+pub fn read_environment<'a>(
+  reader: &mut dyn Read,
+  allocator: &'a Allocator,
+  text_data: &'a Arena<String>,
+) -> Result<Environment<'a>, Box<dyn Error>> {
+  let mut bytes = Vec::new();
+  reader.read_to_end(&mut bytes)?;
+
+  let mut input = bytes.strip_prefix(MAGIC).ok_or("not a lambda environment file")?;
+
+  let version = read_varint(&mut input)?;
+  if version != VERSION {
+    return Err(format!("unsupported environment file version {version} (expected {VERSION})").into());
+  }
+
+  let num_strings = read_varint(&mut input)?;
+  let mut strings = Vec::new();
+  for _ in 0..num_strings {
+    let len = read_varint(&mut input)? as usize;
+    if len > input.len() {
+      return Err(CORRUPT.into());
+    }
+
+    let (s, rest) = input.split_at(len);
+    let s = String::from_utf8(s.to_vec()).map_err(|_| CORRUPT)?;
+    strings.push(text_data.alloc(s).as_str());
+    input = rest;
+  }
+
+  let num_nodes = read_varint(&mut input)?;
+  let mut nodes = Vec::new();
+  for _ in 0..num_nodes {
+    let (tag, rest) = input.split_first().ok_or(CORRUPT)?;
+    input = rest;
+
+    let node = match *tag {
+      TAG_LAMBDA => {
+        let name = read_string(&mut input, &strings)?;
+        if name.is_empty() || name.len() as u64 > MAX_STR_LENGTH {
+          return Err(CORRUPT.into());
+        }
+
+        let body = read_expr(&mut input, allocator, &nodes)?;
+        allocator.new_lambda(name, body)
+      },
+      TAG_EVAL => {
+        let left = read_expr(&mut input, allocator, &nodes)?;
+        let right = read_expr(&mut input, allocator, &nodes)?;
+        allocator.new_eval(left, right)
+      },
+      _ => return Err(CORRUPT.into()),
+    };
+    nodes.push(node);
+  }
+
+  let num_globals = read_varint(&mut input)?;
+  let mut globals = Vec::new();
+  for _ in 0..num_globals {
+    let name = read_string(&mut input, &strings)?;
+    let expr = read_expr(&mut input, allocator, &nodes)?;
+    globals.push((name, expr));
+  }
+
+  let num_numbers = read_varint(&mut input)?;
+  let mut numbers = Vec::new();
+  for _ in 0..num_numbers {
+    numbers.push(read_expr(&mut input, allocator, &nodes)?);
+  }
+
+  if !input.is_empty() {
+    return Err(CORRUPT.into());
+  }
+
+  Ok(Environment { globals, numbers })
+}
+
+static CORRUPT: &str = "corrupt environment file";
+
+enum Node {
+  Lambda { name: u64, body: u64 },
+  Eval { left: u64, right: u64 },
+}
+
+/// Flattens expressions into the string and node tables
+#[derive(Default)]
+struct TableBuilder<'a> {
+  strings: Vec<&'a str>,
+  string_ids: HashMap<&'a str, u64>,
+  nodes: Vec<Node>,
+  node_ids: HashMap<ExprRef<'a>, u64>,
+}
+
+impl<'a> TableBuilder<'a> {
+  fn add_string(&mut self, s: &'a str) -> u64 {
+    *self.string_ids.entry(s).or_insert_with(|| {
+      self.strings.push(s);
+      (self.strings.len() - 1) as u64
+    })
+  }
+
+  fn add_node(&mut self, expr: ExprRef<'a>, node: Node) -> u64 {
+    self.nodes.push(node);
+    let id = (self.nodes.len() - 1) as u64;
+    self.node_ids.insert(expr, id);
+    id << 1
+  }
+}
+
+impl<'a> ExprVisitor<'a> for TableBuilder<'a> {
+  type Output = u64;
+
+  fn visit_term(&mut self, _: ExprRef<'a>, de_bruijn_index: NonZero<u64>) -> Self::Output {
+    (de_bruijn_index.get() << 1) | 1
+  }
+
+  fn visit_lambda(&mut self, expr: ExprRef<'a>, body: ExprRef<'a>, parameter_name: &'a str) -> Self::Output {
+    if let Some(id) = self.node_ids.get(&expr) {
+      return id << 1; // Shared subtree was already written
+    }
+
+    let body = body.visit(self);
+    let name = self.add_string(parameter_name);
+    self.add_node(expr, Node::Lambda { name, body })
+  }
+
+  fn visit_eval(&mut self, expr: ExprRef<'a>, left: ExprRef<'a>, right: ExprRef<'a>) -> Self::Output {
+    if let Some(id) = self.node_ids.get(&expr) {
+      return id << 1; // Shared subtree was already written
+    }
+
+    let left = left.visit(self);
+    let right = right.visit(self);
+    self.add_node(expr, Node::Eval { left, right })
+  }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+  loop {
+    let byte = (value & 0x7f) as u8;
+    value >>= 7;
+
+    if value == 0 {
+      bytes.push(byte);
+      return;
+    }
+    bytes.push(byte | 0x80);
+  }
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64, Box<dyn Error>> {
+  let mut value = 0u64;
+  for shift in (0..64).step_by(7) {
+    let (byte, rest) = input.split_first().ok_or(CORRUPT)?;
+    *input = rest;
+
+    value |= ((byte & 0x7f) as u64).checked_shl(shift).ok_or(CORRUPT)?;
+    if byte & 0x80 == 0 {
+      return Ok(value);
+    }
+  }
+
+  Err(CORRUPT.into())
+}
+
+fn read_string<'a>(input: &mut &[u8], strings: &[&'a str]) -> Result<&'a str, Box<dyn Error>> {
+  let id = read_varint(input)?;
+  strings.get(id as usize).cloned().ok_or_else(|| CORRUPT.into())
+}
+
+fn read_expr<'a>(
+  input: &mut &[u8],
+  allocator: &'a Allocator,
+  nodes: &[ExprRef<'a>],
+) -> Result<ExprRef<'a>, Box<dyn Error>> {
+  let value = read_varint(input)?;
+  if value & 1 == 1 {
+    let de_bruijn_index = NonZero::new(value >> 1).ok_or(CORRUPT)?;
+    Ok(allocator.new_term(de_bruijn_index))
+  } else {
+    // Nodes can only reference nodes that come before them
+    nodes.get((value >> 1) as usize).cloned().ok_or_else(|| CORRUPT.into())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{Executor, PRELUDE};
+
+  #[test]
+  fn saved_environment_loads_back_the_same_globals() {
+    let path = std::env::temp_dir().join(format!("rusty-lambda-{}-environment.env", std::process::id()));
+    let executor = Executor::new();
+    executor.load_code(PRELUDE, Some("prelude")).unwrap();
+    executor.load_code("shadow = \\x.\\x.(x 3)", None).unwrap();
+    executor.save_environment(&path).unwrap();
+
+    let loaded = Executor::new();
+    loaded.load_environment(&path).unwrap();
+
+    let globals = executor.all_globals().borrow();
+    let loaded_globals = loaded.all_globals().borrow();
+    assert_eq!(
+      globals.keys().collect::<Vec<_>>(),
+      loaded_globals.keys().collect::<Vec<_>>()
+    );
+    for (name, expr) in globals.iter() {
+      // Parameter names are saved too, so the globals print the same way
+      let loaded_expr = loaded_globals[name];
+      assert!(expr.structurally_eq(loaded_expr), "{name}");
+      assert_eq!(expr.to_string(), loaded_expr.to_string(), "{name}");
+    }
+
+    // A corrupted file is an error instead of a partial environment
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+    assert!(Executor::new().load_environment(&path).is_err());
+  }
+}
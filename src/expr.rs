@@ -1,4 +1,13 @@
-use std::{collections::HashMap, fmt, marker::PhantomData, num::NonZero, slice, str};
+use std::{
+  borrow::Cow,
+  collections::{HashMap, HashSet},
+  error::Error,
+  fmt,
+  hash::{DefaultHasher, Hash, Hasher},
+  marker::PhantomData,
+  num::NonZero,
+  slice, str,
+};
 use typed_arena::Arena;
 
 /// Visit a Lambda expression
@@ -12,13 +21,42 @@ pub trait ExprVisitor<'a> {
   fn visit_eval(&mut self, expr: ExprRef<'a>, left: ExprRef<'a>, right: ExprRef<'a>) -> Self::Output;
 }
 
+/// Single step in the path from the root of an expression to one of its subexpressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStep {
+  /// Go into the body of a lambda
+  Body,
+
+  /// Go into the left side of an evaluation
+  Left,
+
+  /// Go into the right side of an evaluation
+  Right,
+}
+
+impl fmt::Display for PathStep {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Body => write!(f, "body"),
+      Self::Left => write!(f, "left"),
+      Self::Right => write!(f, "right"),
+    }
+  }
+}
+
+/// Printed before the number of a free variable (a term that references outside the expression)
+pub const FREE_VARIABLE_PREFIX: &str = "?";
+
 const IS_TERM_BIT: u64 = 0x8000_0000_0000_0000;
-const TERM_MASK: u64 = 0x7fff_ffff_ffff_ffff;
+pub(crate) const TERM_MASK: u64 = 0x7fff_ffff_ffff_ffff;
 const POINTER_MASK: u64 = 0x0000_ffff_ffff_ffff;
 
 const STR_LENGTH_MASK: u64 = 0xffff_0000_0000_0000;
 const STR_LENGTH_SHIFT: u64 = 48;
-const MAX_STR_LENGTH: u64 = 0x7fff;
+pub(crate) const MAX_STR_LENGTH: u64 = 0x7fff;
+
+/// How many levels deep [ExprRef::map_terms] recurses before falling back to an explicit stack
+const MAX_RECURSION_DEPTH: u32 = 2048;
 
 /// Reference to a Lambda expression.
 ///
@@ -27,7 +65,8 @@ const MAX_STR_LENGTH: u64 = 0x7fff;
 ///
 /// Two ExprRefs are considered equal if they point to the same object in memory,
 /// not necessarily that they are isomorphic to each other. (reference equality)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Use [ExprRef::structurally_eq] to compare the structure instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ExprRef<'a>(NonZero<u64>, PhantomData<&'a CompactExpr>);
 
 pub enum UnpackedExpr<'a> {
@@ -72,99 +111,952 @@ impl<'a> ExprRef<'a> {
 
     self.visit(&mut UnpackVisitor)
   }
-}
 
-impl fmt::Display for ExprRef<'_> {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    struct Visitor<'f, 'ff, 's> {
-      f: &'f mut fmt::Formatter<'ff>,
-      lambda_parameters: Vec<(&'s str, u64)>,
-      shadowed_variables: HashMap<&'s str, u64>,
+  /// Combine the expression bottom-up into a single value, without needing to write an [ExprVisitor].
+  /// For example, `expr.fold(|_| 1, |_, body| 1 + body, |left, right| 1 + left + right)` is the same as [ExprRef::size].
+  ///
+  /// Unlike a visitor, this uses an explicit stack instead of recursion, so very deep expressions can't overflow the
+  /// call stack.
+  pub fn fold<T>(
+    self,
+    on_term: impl Fn(NonZero<u64>) -> T,
+    on_lambda: impl Fn(&'a str, T) -> T,
+    on_eval: impl Fn(T, T) -> T,
+  ) -> T {
+    enum Work<'a> {
+      Visit(ExprRef<'a>),
+      CombineLambda(&'a str),
+      CombineEval,
     }
 
-    impl<'s> ExprVisitor<'s> for Visitor<'_, '_, 's> {
-      type Output = fmt::Result;
+    // Children are visited left to right, leaving their values on the stack for the parent to combine
+    let mut work = vec![Work::Visit(self)];
+    let mut values = Vec::new();
+    while let Some(item) = work.pop() {
+      match item {
+        Work::Visit(expr) => match expr.unpack() {
+          UnpackedExpr::Term { de_bruijn_index } => values.push(on_term(de_bruijn_index)),
+          UnpackedExpr::Lambda { parameter_name, body } => {
+            work.push(Work::CombineLambda(parameter_name));
+            work.push(Work::Visit(body));
+          },
+          UnpackedExpr::Eval { left, right } => {
+            work.push(Work::CombineEval);
+            work.push(Work::Visit(right));
+            work.push(Work::Visit(left));
+          },
+        },
 
-      fn visit_term(&mut self, _: ExprRef<'s>, de_bruijn_index: NonZero<u64>) -> Self::Output {
-        if self.f.sign_plus() {
-          write!(self.f, "{}", de_bruijn_index)?;
-        } else if self.f.sign_minus() {
-          write!(self.f, "-{}", de_bruijn_index)?;
-        } else {
-          // Read the tern name from the vector of parameters
-          let term = self
-            .lambda_parameters
-            .get(self.lambda_parameters.len() - de_bruijn_index.get() as usize);
-
-          match term {
-            Some(term) => {
-              write!(self.f, "{}", term.0)?;
-
-              // Shadowed parameters
-              for _ in 0..term.1 {
-                write!(self.f, "′")?;
-              }
-            },
-            // Default print the de Bruijn index to avoid a crash
-            None => write!(self.f, "{}", de_bruijn_index)?,
-          }
+        Work::CombineLambda(parameter_name) => {
+          let body = values.pop().expect("missing lambda body");
+          values.push(on_lambda(parameter_name, body));
+        },
+
+        Work::CombineEval => {
+          let right = values.pop().expect("missing right side");
+          let left = values.pop().expect("missing left side");
+          values.push(on_eval(left, right));
+        },
+      }
+    }
+
+    values.pop().expect("missing value")
+  }
+
+  /// Count the total number of nodes (terms, lambdas, and evals) in the expression
+  pub fn size(self) -> u64 {
+    self.fold(|_| 1, |_, body| 1 + body, |left, right| 1 + left + right)
+  }
+
+  /// Count each kind of node in the expression, along with how deeply they are nested
+  pub fn sizes(self) -> ExprSizes {
+    self.fold(
+      |_| ExprSizes {
+        terms: 1,
+        lambdas: 0,
+        evals: 0,
+        depth: 1,
+      },
+      |_, body| ExprSizes {
+        lambdas: body.lambdas + 1,
+        depth: body.depth + 1,
+        ..body
+      },
+      |left, right| ExprSizes {
+        terms: left.terms + right.terms,
+        lambdas: left.lambdas + right.lambdas,
+        evals: left.evals + right.evals + 1,
+        depth: left.depth.max(right.depth) + 1,
+      },
+    )
+  }
+
+  /// Test if two expressions have the same structure, ignoring parameter names (alpha-equivalence).
+  ///
+  /// Unlike `==`, which only compares pointers, this walks both expressions, so two structurally equal
+  /// expressions can still be different objects in memory. The expressions don't even need to come from
+  /// the same [Allocator]. Both are walked using an explicit stack, so very deep expressions are fine.
+  pub fn structurally_eq(self, other: ExprRef<'_>) -> bool {
+    use UnpackedExpr::*;
+
+    let mut to_compare = vec![(self, other)];
+    while let Some((a, b)) = to_compare.pop() {
+      if a.0 == b.0 {
+        continue; // Optimization: same term or same pointer
+      }
+
+      match (a.unpack(), b.unpack()) {
+        (Term { de_bruijn_index: i }, Term { de_bruijn_index: j }) if i == j => {},
+        (Lambda { body: a, .. }, Lambda { body: b, .. }) => to_compare.push((a, b)),
+        (Eval { left: l1, right: r1 }, Eval { left: l2, right: r2 }) => {
+          to_compare.push((r1, r2));
+          to_compare.push((l1, l2));
+        },
+        _ => return false,
+      }
+    }
+
+    true
+  }
+
+  /// Find the first place where two expressions have a different structure, ignoring parameter names.
+  /// Returns None if they are [structurally equal](ExprRef::structurally_eq).
+  pub fn first_difference<'b>(self, other: ExprRef<'b>) -> Option<Difference<'a, 'b>> {
+    use UnpackedExpr::*;
+
+    // Each pair remembers how long the path was when it was pushed, so the path can be rewound to its parent
+    let mut path = Vec::new();
+    let mut to_compare = vec![(self, other, 0, None)];
+    while let Some((a, b, depth, step)) = to_compare.pop() {
+      path.truncate(depth);
+      path.extend(step);
+      if a.0 == b.0 {
+        continue; // Optimization: same term or same pointer
+      }
+
+      match (a.unpack(), b.unpack()) {
+        (Term { de_bruijn_index: i }, Term { de_bruijn_index: j }) if i == j => {},
+        (Lambda { body: a, .. }, Lambda { body: b, .. }) => to_compare.push((a, b, path.len(), Some(PathStep::Body))),
+        (Eval { left: l1, right: r1 }, Eval { left: l2, right: r2 }) => {
+          to_compare.push((r1, r2, path.len(), Some(PathStep::Right)));
+          to_compare.push((l1, l2, path.len(), Some(PathStep::Left)));
+        },
+        _ => {
+          return Some(Difference {
+            path,
+            left: a,
+            right: b,
+          });
+        },
+      }
+    }
+
+    None
+  }
+
+  /// Hash the structure of the expression, ignoring parameter names.
+  /// Expressions that are [structurally equal](ExprRef::structurally_eq) always have the same hash.
+  pub fn structural_hash(self) -> u64 {
+    self.fold(
+      |de_bruijn_index| hash_node((0u8, de_bruijn_index)),
+      |_, body| hash_node((1u8, body)),
+      |left, right| hash_node((2u8, left, right)),
+    )
+  }
+
+  /// Find the [structural hash](ExprRef::structural_hash) of every subexpression in a single pass
+  pub fn structural_hashes(self) -> HashMap<ExprRef<'a>, u64> {
+    struct HashesVisitor<'a> {
+      hashes: HashMap<ExprRef<'a>, u64>,
+    }
+
+    impl<'a> ExprVisitor<'a> for HashesVisitor<'a> {
+      type Output = u64;
+
+      fn visit_term(&mut self, expr: ExprRef<'a>, de_bruijn_index: NonZero<u64>) -> Self::Output {
+        let hash = hash_node((0u8, de_bruijn_index));
+        self.hashes.insert(expr, hash);
+        hash
+      }
+
+      fn visit_lambda(&mut self, expr: ExprRef<'a>, body: ExprRef<'a>, _: &'a str) -> Self::Output {
+        if let Some(hash) = self.hashes.get(&expr) {
+          return *hash; // Shared subexpression
         }
 
-        Ok(())
+        let hash = hash_node((1u8, body.visit(self)));
+        self.hashes.insert(expr, hash);
+        hash
+      }
+
+      fn visit_eval(&mut self, expr: ExprRef<'a>, left: ExprRef<'a>, right: ExprRef<'a>) -> Self::Output {
+        if let Some(hash) = self.hashes.get(&expr) {
+          return *hash; // Shared subexpression
+        }
+
+        let hash = hash_node((2u8, left.visit(self), right.visit(self)));
+        self.hashes.insert(expr, hash);
+        hash
+      }
+    }
+
+    let mut visitor = HashesVisitor { hashes: HashMap::new() };
+    self.visit(&mut visitor);
+    visitor.hashes
+  }
+
+  /// Test if the expression has no free variables (every term references an enclosing lambda)
+  pub fn is_closed(self) -> bool {
+    self.first_free_term().is_none()
+  }
+
+  /// Find the leftmost term that references outside of the expression.
+  /// Returns its de Bruijn index along with the number of lambdas surrounding it inside the expression.
+  pub fn first_free_term(self) -> Option<(NonZero<u64>, u64)> {
+    struct FreeTermVisitor {
+      depth: u64,
+    }
+
+    impl<'a> ExprVisitor<'a> for FreeTermVisitor {
+      type Output = Option<(NonZero<u64>, u64)>;
+
+      fn visit_term(&mut self, _: ExprRef<'a>, de_bruijn_index: NonZero<u64>) -> Self::Output {
+        (de_bruijn_index.get() > self.depth).then_some((de_bruijn_index, self.depth))
+      }
+
+      fn visit_lambda(&mut self, _: ExprRef<'a>, body: ExprRef<'a>, _: &'a str) -> Self::Output {
+        self.depth += 1;
+        let result = body.visit(self);
+        self.depth -= 1;
+        result
+      }
+
+      fn visit_eval(&mut self, _: ExprRef<'a>, left: ExprRef<'a>, right: ExprRef<'a>) -> Self::Output {
+        left.visit(self).or_else(|| right.visit(self))
       }
+    }
+
+    self.visit(&mut FreeTermVisitor { depth: 0 })
+  }
+
+  /// Print free variables using the names of the outer lambdas, listed from outermost to innermost.
+  /// Any variables that are still free are printed as usual.
+  pub fn display_in_context<'n>(self, context: &'n [&'a str]) -> DisplayInContext<'a, 'n> {
+    DisplayInContext { expr: self, context }
+  }
 
-      fn visit_lambda(&mut self, _: ExprRef<'s>, body: ExprRef<'s>, parameter_name: &'s str) -> Self::Output {
-        let count = self
-          .shadowed_variables
-          .entry(parameter_name)
-          .and_modify(|c| *c += 1)
-          .or_insert(0);
+  /// Same as display_in_context(), but only prints the parentheses that are needed to read the expression back
+  /// using the usual precedence: application is left-associative, and lambda bodies extend as far right as possible.
+  /// For example, `(((a b) c) d)` is printed as `a b c d` and `(\x.x (y z))` as `(\x.x) (y z)`.
+  pub fn display_minimal<'n>(self, context: &'n [&'a str]) -> DisplayWith<'a, 'n> {
+    let options = DisplayOptions {
+      minimal_parens: true,
+      ..DisplayOptions::default()
+    };
+    self.display_with(context, options)
+  }
 
-        if self.f.alternate() {
-          write!(self.f, "λ{}", parameter_name)?;
+  /// Same as display_in_context(), but with extra settings for how the expression gets printed
+  pub fn display_with<'n>(self, context: &'n [&'a str], options: DisplayOptions) -> DisplayWith<'a, 'n> {
+    DisplayWith {
+      expr: self,
+      context,
+      options,
+    }
+  }
+
+  /// Same as display_in_context(), but ignores the parameter names and generates
+  /// new ones from the depth of each lambda using [index_to_variable_name].
+  pub fn display_with_fresh_names<'n>(self, context: &'n [&'a str]) -> DisplayFreshNames<'a, 'n> {
+    DisplayFreshNames { expr: self, context }
+  }
+
+  /// Print the subexpression at the end of the path wrapped in square brackets, like `(λx.x [(λy.y z)])`.
+  /// Nothing is highlighted if the path doesn't match the expression.
+  pub fn display_highlighted<'p>(self, path: &'p [PathStep]) -> DisplayHighlighted<'a, 'p> {
+    DisplayHighlighted { expr: self, path }
+  }
+
+  /// Rebuild the expression with every term replaced by `map_term(term, de_bruijn_index, depth)`,
+  /// where the depth counts the lambdas around the term inside of the expression.
+  ///
+  /// Any node whose children didn't change is reused instead of allocating a copy. Shallow expressions are
+  /// walked recursively, which is the fast path taken by almost every beta reduction. Anything nested deeper
+  /// than [MAX_RECURSION_DEPTH] switches to an explicit stack, so very deep expressions can't overflow the call stack.
+  pub fn map_terms(
+    self,
+    allocator: &'a Allocator,
+    mut map_term: impl FnMut(ExprRef<'a>, NonZero<u64>, u64) -> ExprRef<'a>,
+  ) -> ExprRef<'a> {
+    self.map_terms_recursive(allocator, &mut map_term, 0, 0)
+  }
+
+  fn map_terms_recursive<F>(self, allocator: &'a Allocator, map_term: &mut F, depth: u64, recursion: u32) -> ExprRef<'a>
+  where
+    F: FnMut(ExprRef<'a>, NonZero<u64>, u64) -> ExprRef<'a>,
+  {
+    if recursion >= MAX_RECURSION_DEPTH {
+      return self.map_terms_with_stack(allocator, map_term, depth);
+    }
+
+    match self.unpack() {
+      UnpackedExpr::Term { de_bruijn_index } => map_term(self, de_bruijn_index, depth),
+      UnpackedExpr::Lambda { parameter_name, body } => {
+        let new_body = body.map_terms_recursive(allocator, map_term, depth + 1, recursion + 1);
+        if new_body == body {
+          self // Optimization: avoid an extra allocation
         } else {
-          write!(self.f, "\\{}", parameter_name)?;
+          allocator.new_lambda(parameter_name, new_body)
         }
+      },
+      UnpackedExpr::Eval { left, right } => {
+        let new_left = left.map_terms_recursive(allocator, map_term, depth, recursion + 1);
+        let new_right = right.map_terms_recursive(allocator, map_term, depth, recursion + 1);
+        if new_left == left && new_right == right {
+          self // Optimization: avoid an extra allocation
+        } else {
+          allocator.new_eval(new_left, new_right)
+        }
+      },
+    }
+  }
+
+  /// Same as map_terms_recursive(), but walks the expression using an explicit stack
+  fn map_terms_with_stack<F>(self, allocator: &'a Allocator, map_term: &mut F, depth: u64) -> ExprRef<'a>
+  where
+    F: FnMut(ExprRef<'a>, NonZero<u64>, u64) -> ExprRef<'a>,
+  {
+    enum Work<'a> {
+      Visit(ExprRef<'a>, u64),
+      BuildLambda(ExprRef<'a>, &'a str, ExprRef<'a>),
+      BuildEval(ExprRef<'a>, ExprRef<'a>, ExprRef<'a>),
+    }
+
+    // Children are visited left to right, leaving their results on the stack for the parent to pick up
+    let mut work = vec![Work::Visit(self, depth)];
+    let mut results = Vec::new();
+    while let Some(item) = work.pop() {
+      match item {
+        Work::Visit(expr, depth) => match expr.unpack() {
+          UnpackedExpr::Term { de_bruijn_index } => results.push(map_term(expr, de_bruijn_index, depth)),
+          UnpackedExpr::Lambda { parameter_name, body } => {
+            work.push(Work::BuildLambda(expr, parameter_name, body));
+            work.push(Work::Visit(body, depth + 1));
+          },
+          UnpackedExpr::Eval { left, right } => {
+            work.push(Work::BuildEval(expr, left, right));
+            work.push(Work::Visit(right, depth));
+            work.push(Work::Visit(left, depth));
+          },
+        },
+
+        Work::BuildLambda(expr, parameter_name, body) => {
+          let new_body = results.pop().expect("missing lambda body");
+          if new_body == body {
+            results.push(expr); // Optimization: avoid an extra allocation
+          } else {
+            results.push(allocator.new_lambda(parameter_name, new_body));
+          }
+        },
+
+        Work::BuildEval(expr, left, right) => {
+          let new_right = results.pop().expect("missing right side");
+          let new_left = results.pop().expect("missing left side");
+          if new_left == left && new_right == right {
+            results.push(expr); // Optimization: avoid an extra allocation
+          } else {
+            results.push(allocator.new_eval(new_left, new_right));
+          }
+        },
+      }
+    }
+
+    results.pop().expect("missing result")
+  }
+
+  /// Add `offset` to the de Bruijn index of every term that is free relative to `cutoff`.
+  ///
+  /// A term is free if its index is at least `cutoff` plus the number of lambdas surrounding it inside the expression,
+  /// so a cutoff of 1 shifts every free variable of the whole expression. Returns an error instead if any shifted
+  /// index would be 0 or less, or too large to store.
+  pub fn shift(self, allocator: &'a Allocator, cutoff: u64, offset: i64) -> Result<ExprRef<'a>, ShiftError> {
+    let mut error = None;
+    let result = self.map_terms(allocator, |term, de_bruijn_index, depth| {
+      if de_bruijn_index.get() < cutoff + depth {
+        return term;
+      }
+
+      let new_de_bruijn_index = de_bruijn_index
+        .get()
+        .checked_add_signed(offset)
+        .filter(|index| *index <= TERM_MASK)
+        .and_then(NonZero::new);
+
+      match new_de_bruijn_index {
+        Some(index) => allocator.new_term(index),
+        None => {
+          error.get_or_insert(ShiftError {
+            de_bruijn_index: de_bruijn_index.get(),
+            offset,
+          });
+          term
+        },
+      }
+    });
+
+    match error {
+      Some(error) => Err(error),
+      None => Ok(result),
+    }
+  }
+
+  /// Test if any term references the variable with de Bruijn index `de_bruijn_index` from outside the expression
+  pub fn references(self, de_bruijn_index: u64) -> bool {
+    let mut to_search = vec![(self, de_bruijn_index)];
+    while let Some((expr, index)) = to_search.pop() {
+      match expr.unpack() {
+        UnpackedExpr::Term { de_bruijn_index } if de_bruijn_index.get() == index => return true,
+        UnpackedExpr::Term { .. } => {},
+        UnpackedExpr::Lambda { body, .. } => to_search.push((body, index + 1)),
+        UnpackedExpr::Eval { left, right } => {
+          to_search.push((right, index));
+          to_search.push((left, index));
+        },
+      }
+    }
+
+    false
+  }
+
+  /// Apply eta reduction everywhere in the expression, from the innermost lambdas outward.
+  ///
+  /// A lambda `\x.(f x)` collapses to `f` whenever `f` doesn't reference `x`.
+  /// Uses an explicit stack instead of recursion, just like [ExprRef::map_terms].
+  pub fn eta_reduce(self, allocator: &'a Allocator) -> ExprRef<'a> {
+    enum Work<'a> {
+      Visit(ExprRef<'a>),
+      BuildLambda(ExprRef<'a>, &'a str, ExprRef<'a>),
+      BuildEval(ExprRef<'a>, ExprRef<'a>, ExprRef<'a>),
+    }
+
+    let mut work = vec![Work::Visit(self)];
+    let mut results = Vec::new();
+    while let Some(item) = work.pop() {
+      match item {
+        Work::Visit(expr) => match expr.unpack() {
+          UnpackedExpr::Term { .. } => results.push(expr),
+          UnpackedExpr::Lambda { parameter_name, body } => {
+            work.push(Work::BuildLambda(expr, parameter_name, body));
+            work.push(Work::Visit(body));
+          },
+          UnpackedExpr::Eval { left, right } => {
+            work.push(Work::BuildEval(expr, left, right));
+            work.push(Work::Visit(right));
+            work.push(Work::Visit(left));
+          },
+        },
+
+        Work::BuildLambda(expr, parameter_name, body) => {
+          let new_body = results.pop().expect("missing lambda body");
+          if let UnpackedExpr::Eval { left, right } = new_body.unpack()
+            && let UnpackedExpr::Term { de_bruijn_index } = right.unpack()
+            && de_bruijn_index.get() == 1
+            && !left.references(1)
+          {
+            // Every free term in `left` has an index of at least 2, so shifting down can never fail
+            let reduced = left
+              .shift(allocator, 1, -1)
+              .expect("Eta reduction should never shift below index 1");
+            results.push(reduced);
+          } else if new_body == body {
+            results.push(expr); // Optimization: avoid an extra allocation
+          } else {
+            results.push(allocator.new_lambda(parameter_name, new_body));
+          }
+        },
+
+        Work::BuildEval(expr, left, right) => {
+          let new_right = results.pop().expect("missing right side");
+          let new_left = results.pop().expect("missing left side");
+          if new_left == left && new_right == right {
+            results.push(expr); // Optimization: avoid an extra allocation
+          } else {
+            results.push(allocator.new_eval(new_left, new_right));
+          }
+        },
+      }
+    }
+
+    results.pop().expect("missing result")
+  }
+}
+
+/// Number of each kind of node in an expression, from [ExprRef::sizes]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExprSizes {
+  pub terms: u64,
+  pub lambdas: u64,
+  pub evals: u64,
+
+  /// Number of nodes on the longest path from the root to a term
+  pub depth: u64,
+}
+
+/// Shifting a term would have made its de Bruijn index invalid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShiftError {
+  pub de_bruijn_index: u64,
+  pub offset: i64,
+}
+
+impl fmt::Display for ShiftError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "cannot shift de Bruijn index {} by {}: index must be between 1 and {TERM_MASK}",
+      self.de_bruijn_index, self.offset
+    )
+  }
+}
+
+impl Error for ShiftError {}
+
+/// De Bruijn index of a term is too large to be stored in an [ExprRef]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError {
+  pub de_bruijn_index: u64,
+}
+
+impl fmt::Display for AllocError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "de Bruijn index {} is too large: index must be between 1 and {TERM_MASK}",
+      self.de_bruijn_index
+    )
+  }
+}
+
+impl Error for AllocError {}
+
+/// Where two expressions first have a different structure. Created by [ExprRef::first_difference].
+pub struct Difference<'a, 'b> {
+  /// Steps taken from the root to get to the difference
+  pub path: Vec<PathStep>,
+
+  /// Subexpression of the first expression at the path
+  pub left: ExprRef<'a>,
+
+  /// Subexpression of the second expression at the path
+  pub right: ExprRef<'b>,
+}
+
+impl fmt::Display for Difference<'_, '_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "root")?;
+    for step in self.path.iter() {
+      write!(f, ".{step}")?;
+    }
+    write!(f, ": {:#} vs {:#}", self.left, self.right)
+  }
+}
+
+/// Use `{:#}` to print lambdas with `λ` instead of a backslash, and `{:+}` to print the
+/// de Bruijn indices instead of the parameter names.
+impl fmt::Display for ExprRef<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    format_expr(*self, f, &[], None, DisplayOptions::default())
+  }
+}
+
+/// Display an expression inside of some outer lambdas that are not part of the expression.
+/// Created by [ExprRef::display_in_context].
+pub struct DisplayInContext<'a, 'n> {
+  expr: ExprRef<'a>,
+  context: &'n [&'a str],
+}
+
+impl fmt::Display for DisplayInContext<'_, '_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    format_expr(self.expr, f, self.context, None, DisplayOptions::default())
+  }
+}
+
+/// Settings for printing an expression using [ExprRef::display_with]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisplayOptions {
+  /// Leave out redundant parentheses, so `(((a b) c) d)` is printed as `a b c d`
+  pub minimal_parens: bool,
+
+  /// Print anything nested deeper than this as `…`, counting the root as depth 1
+  pub max_depth: Option<NonZero<u64>>,
+
+  /// Don't add any characters outside of ASCII: lambdas use `\` even with `{:#}`, shadowed parameters
+  /// are printed as `x_1` instead of `x′`, and anything too deep is printed as `...`
+  pub ascii: bool,
+}
+
+/// Display an expression inside of some outer lambdas using the display options.
+/// Created by [ExprRef::display_with] and [ExprRef::display_minimal].
+pub struct DisplayWith<'a, 'n> {
+  expr: ExprRef<'a>,
+  context: &'n [&'a str],
+  options: DisplayOptions,
+}
+
+impl fmt::Display for DisplayWith<'_, '_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    format_expr(self.expr, f, self.context, None, self.options)
+  }
+}
+
+/// Display an expression with one of its subexpressions highlighted.
+/// Created by [ExprRef::display_highlighted].
+pub struct DisplayHighlighted<'a, 'p> {
+  expr: ExprRef<'a>,
+  path: &'p [PathStep],
+}
 
-        for _ in 0..*count {
-          write!(self.f, "′")?;
+impl fmt::Display for DisplayHighlighted<'_, '_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    format_expr(self.expr, f, &[], Some(self.path), DisplayOptions::default())
+  }
+}
+
+/// Display an expression with generated parameter names.
+/// Created by [ExprRef::display_with_fresh_names].
+pub struct DisplayFreshNames<'a, 'n> {
+  expr: ExprRef<'a>,
+  context: &'n [&'a str],
+}
+
+impl fmt::Display for DisplayFreshNames<'_, '_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    struct RenameVisitor<'b> {
+      allocator: &'b Allocator,
+      text_data: &'b Arena<String>,
+      names: Vec<&'b str>,
+      depth: u64,
+    }
+
+    impl<'a, 'b> ExprVisitor<'a> for RenameVisitor<'b> {
+      type Output = ExprRef<'b>;
+
+      fn visit_term(&mut self, _: ExprRef<'a>, de_bruijn_index: NonZero<u64>) -> Self::Output {
+        self.allocator.new_term(de_bruijn_index)
+      }
+
+      fn visit_lambda(&mut self, _: ExprRef<'a>, body: ExprRef<'a>, _: &'a str) -> Self::Output {
+        if self.names.len() as u64 <= self.depth {
+          let name = self.text_data.alloc(index_to_variable_name(self.depth));
+          self.names.push(name.as_str());
         }
-        write!(self.f, ".")?;
-
-        self.lambda_parameters.push((parameter_name, *count));
-        body.visit(self)?;
-        self.lambda_parameters.pop();
-
-        let result = self
-          .shadowed_variables
-          .entry(parameter_name)
-          .and_modify(|c| {
-            if *c > 0 {
-              *c -= 1
+        let name = self.names[self.depth as usize];
+
+        self.depth += 1;
+        let body = body.visit(self);
+        self.depth -= 1;
+
+        self.allocator.new_lambda(name, body)
+      }
+
+      fn visit_eval(&mut self, _: ExprRef<'a>, left: ExprRef<'a>, right: ExprRef<'a>) -> Self::Output {
+        let left = left.visit(self);
+        let right = right.visit(self);
+        self.allocator.new_eval(left, right)
+      }
+    }
+
+    // Rebuild the expression with the new names, since the names are stored inside of the lambdas
+    let text_data = Arena::new();
+    let allocator = Allocator::new();
+    let expr = self.expr.visit(&mut RenameVisitor {
+      allocator: &allocator,
+      text_data: &text_data,
+      names: Vec::new(),
+      depth: 0,
+    });
+
+    format_expr(expr, f, self.context, None, DisplayOptions::default())
+  }
+}
+
+/// Combine the parts of a node into a single hash, used by [ExprRef::structural_hash]
+fn hash_node(value: impl Hash) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  value.hash(&mut hasher);
+  hasher.finish()
+}
+
+/// Letters used by [index_to_variable_name], starting with `x` like most hand-written code
+const VARIABLE_LETTERS: &[u8; 26] = b"xyzabcdefghijklmnopqrstuvw";
+
+/// Name a variable using bijective base-26, like spreadsheet columns, so every index gets a different name:
+/// `0 = x, 1 = y, 2 = z, 3 = a, ..., 25 = w, 26 = xx, 27 = xy, ..., 701 = ww, 702 = xxx, ...`
+pub fn index_to_variable_name(index: u64) -> String {
+  let mut letters = Vec::new();
+  let mut n = index as u128 + 1;
+  while n > 0 {
+    n -= 1;
+    letters.push(VARIABLE_LETTERS[(n % 26) as usize]);
+    n /= 26;
+  }
+
+  letters.reverse();
+  String::from_utf8(letters).expect("letters are ASCII")
+}
+
+/// Context lists the names of any outer lambdas, from outermost to innermost.
+///
+/// The expression is printed using an explicit stack instead of recursion, so very deep expressions
+/// can't overflow the call stack.
+fn format_expr<'s>(
+  expr: ExprRef<'s>,
+  f: &mut fmt::Formatter<'_>,
+  context: &[&'s str],
+  highlight: Option<&[PathStep]>,
+  options: DisplayOptions,
+) -> fmt::Result {
+  /// Settings that change while printing, which are restored after leaving a child node
+  #[derive(Clone, Copy)]
+  struct State<'p> {
+    // Rest of the path to the highlighted node, or None if it isn't inside the current node
+    highlight: Option<&'p [PathStep]>,
+
+    // At end means nothing else gets printed after the current node before the closing parenthesis
+    at_end: bool,
+
+    // Depth of the current node, counting the root as depth 1
+    depth: u64,
+  }
+
+  enum Work<'s, 'p> {
+    Node(ExprRef<'s>),
+    Child(ExprRef<'s>, PathStep),
+    Operand {
+      child: ExprRef<'s>,
+      step: PathStep,
+      parens: bool,
+      at_end: bool,
+    },
+    Text(&'static str),
+    PopParameter,
+    Restore(State<'p>),
+  }
+
+  struct Printer<'f, 'ff, 's, 'p> {
+    f: &'f mut fmt::Formatter<'ff>,
+    // Printed name of every lambda in scope, which are all different so the output can be parsed back
+    lambda_parameters: Vec<Cow<'s, str>>,
+    names_in_scope: HashSet<Cow<'s, str>>,
+    ascii: bool,
+
+    // Only print the parentheses that are needed, where a lambda can go without them if nothing comes after it
+    minimal_parens: bool,
+
+    // Anything nested deeper than the maximum is printed as `…`
+    max_depth: Option<NonZero<u64>>,
+
+    state: State<'p>,
+    work: Vec<Work<'s, 'p>>,
+  }
+
+  impl<'s, 'p> Printer<'_, '_, 's, 'p> {
+    /// Mark a shadowed parameter with primes (or `_1`, `_2`, ... in ASCII) until it doesn't match any other
+    /// variable in scope, including a parameter that was already written like `x′`
+    fn unique_name(&self, name: &'s str) -> Cow<'s, str> {
+      if !self.names_in_scope.contains(name) {
+        return Cow::Borrowed(name);
+      }
+
+      (1..)
+        .map(|count| {
+          if self.ascii {
+            format!("{name}_{count}")
+          } else {
+            format!("{name}{}", "′".repeat(count))
+          }
+        })
+        .find(|candidate| !self.names_in_scope.contains(candidate.as_str()))
+        .map(Cow::Owned)
+        .expect("ran out of names")
+    }
+
+    fn lambda_symbol(&self) -> &'static str {
+      if self.f.alternate() && !self.ascii { "λ" } else { "\\" }
+    }
+
+    /// Test if the children of the current node are too deep to print
+    fn hides_children(&self) -> bool {
+      self
+        .max_depth
+        .is_some_and(|max_depth| self.state.depth >= max_depth.get())
+    }
+
+    fn print(&mut self) -> fmt::Result {
+      while let Some(item) = self.work.pop() {
+        match item {
+          Work::Node(expr) => self.print_node(expr)?,
+          Work::Child(child, step) => self.print_child(child, step)?,
+          Work::Operand {
+            child,
+            step,
+            parens,
+            at_end,
+          } => self.print_operand(child, step, parens, at_end)?,
+          Work::Text(text) => write!(self.f, "{text}")?,
+          Work::PopParameter => {
+            if let Some(name) = self.lambda_parameters.pop() {
+              self.names_in_scope.remove(&name);
             }
-          })
-          .or_default();
-        if *result == 0 {
-          self.shadowed_variables.remove(parameter_name);
+          },
+          Work::Restore(state) => self.state = state,
         }
+      }
+
+      Ok(())
+    }
 
-        Ok(())
+    /// Print a child node, following the highlighted path if it goes that way
+    fn print_child(&mut self, child: ExprRef<'s>, step: PathStep) -> fmt::Result {
+      if self.hides_children() {
+        return write!(self.f, "{}", if self.ascii { "..." } else { "…" });
       }
 
-      fn visit_eval(&mut self, _: ExprRef<'s>, left: ExprRef<'s>, right: ExprRef<'s>) -> Self::Output {
+      self.work.push(Work::Restore(self.state));
+      self.state.depth += 1;
+      self.state.highlight = match self.state.highlight.and_then(<[PathStep]>::split_first) {
+        Some((next, rest)) if *next == step => Some(rest),
+        _ => None,
+      };
+
+      if self.state.highlight == Some(&[]) {
+        write!(self.f, "[")?;
+        self.work.push(Work::Text("]"));
+      }
+      self.work.push(Work::Node(child));
+      Ok(())
+    }
+
+    /// Print one side of an application, wrapped in parentheses if needed
+    fn print_operand(&mut self, child: ExprRef<'s>, step: PathStep, parens: bool, at_end: bool) -> fmt::Result {
+      self.work.push(Work::Restore(self.state));
+      self.state.at_end = at_end || parens;
+      if parens {
         write!(self.f, "(")?;
-        left.visit(self)?;
-        write!(self.f, " ")?;
-        right.visit(self)?;
-        write!(self.f, ")")
+        self.work.push(Work::Text(")"));
       }
+      self.work.push(Work::Child(child, step));
+      Ok(())
     }
 
-    self.visit(&mut Visitor {
-      f,
-      lambda_parameters: Vec::new(),
-      shadowed_variables: HashMap::new(),
-    })
+    fn print_node(&mut self, expr: ExprRef<'s>) -> fmt::Result {
+      match expr.unpack() {
+        UnpackedExpr::Term { de_bruijn_index } => self.print_term(de_bruijn_index),
+        UnpackedExpr::Lambda { parameter_name, body } => self.print_lambda(parameter_name, body),
+        UnpackedExpr::Eval { left, right } => self.print_eval(left, right),
+      }
+    }
+
+    fn print_term(&mut self, de_bruijn_index: NonZero<u64>) -> fmt::Result {
+      if self.f.sign_plus() {
+        return write!(self.f, "{}", de_bruijn_index);
+      }
+      if self.f.sign_minus() {
+        return write!(self.f, "-{}", de_bruijn_index);
+      }
+
+      // Read the tern name from the vector of parameters
+      let depth = self.lambda_parameters.len() as u64;
+      let term = depth
+        .checked_sub(de_bruijn_index.get())
+        .and_then(|index| self.lambda_parameters.get(index as usize));
+
+      match term {
+        Some(term) => write!(self.f, "{term}"),
+        // Free variables are numbered starting from 1 outside the outermost lambda
+        None => write!(self.f, "{FREE_VARIABLE_PREFIX}{}", de_bruijn_index.get() - depth),
+      }
+    }
+
+    fn print_lambda(&mut self, parameter_name: &'s str, body: ExprRef<'s>) -> fmt::Result {
+      // Parameter names aren't needed when the terms are printed as indices
+      if self.f.sign_plus() || self.f.sign_minus() {
+        write!(self.f, "{}.", self.lambda_symbol())?;
+        self.work.push(Work::Child(body, PathStep::Body));
+        return Ok(());
+      }
+
+      let name = self.unique_name(parameter_name);
+      write!(self.f, "{}{name}.", self.lambda_symbol())?;
+
+      self.names_in_scope.insert(name.clone());
+      self.lambda_parameters.push(name);
+      self.work.push(Work::PopParameter);
+      self.work.push(Work::Child(body, PathStep::Body));
+      Ok(())
+    }
+
+    fn print_eval(&mut self, left: ExprRef<'s>, right: ExprRef<'s>) -> fmt::Result {
+      // Work is a stack, so everything is pushed in reverse order
+      if !self.minimal_parens {
+        write!(self.f, "(")?;
+        self.work.push(Work::Text(")"));
+        self.work.push(Work::Child(right, PathStep::Right));
+        self.work.push(Work::Text(" "));
+        self.work.push(Work::Child(left, PathStep::Left));
+        return Ok(());
+      }
+
+      // Applications are left-associative, so only the right side needs parentheses around another application
+      let hidden = self.hides_children();
+      let left_parens = !hidden && matches!(left.unpack(), UnpackedExpr::Lambda { .. });
+      let right_parens = !hidden
+        && match right.unpack() {
+          UnpackedExpr::Term { .. } => false,
+          UnpackedExpr::Lambda { .. } => !self.state.at_end,
+          UnpackedExpr::Eval { .. } => true,
+        };
+
+      self.work.push(Work::Operand {
+        child: right,
+        step: PathStep::Right,
+        parens: right_parens,
+        at_end: self.state.at_end,
+      });
+      self.work.push(Work::Text(" "));
+      self.work.push(Work::Operand {
+        child: left,
+        step: PathStep::Left,
+        parens: left_parens,
+        at_end: false,
+      });
+      Ok(())
+    }
+  }
+
+  let mut printer = Printer {
+    f,
+    lambda_parameters: context.iter().map(|name| Cow::Borrowed(*name)).collect(),
+    names_in_scope: context.iter().map(|name| Cow::Borrowed(*name)).collect(),
+    ascii: options.ascii,
+    minimal_parens: options.minimal_parens,
+    max_depth: options.max_depth,
+    state: State {
+      highlight,
+      at_end: true,
+      depth: 1,
+    },
+    work: vec![Work::Node(expr)],
+  };
+
+  // The root itself is highlighted by an empty path
+  if highlight == Some(&[]) {
+    write!(printer.f, "[")?;
+    printer.print()?;
+    write!(printer.f, "]")
+  } else {
+    printer.print()
   }
 }
 
@@ -228,6 +1120,10 @@ impl CompactExpr {
 }
 
 /// Handles allocation of Lambda expressions
+///
+/// Every lambda and eval gets its own allocation, even if an identical one already exists.
+/// Several maps are keyed by [ExprRef] (like the source locations of lambdas and the results of reduced redexes),
+/// so two expressions from different places must never share a pointer.
 #[derive(Default)]
 pub struct Allocator {
   arena: Arena<CompactExpr>,
@@ -238,6 +1134,16 @@ impl Allocator {
     Self { arena: Arena::new() }
   }
 
+  fn alloc(&self, expr: CompactExpr) -> NonZero<u64> {
+    let ptr = self.arena.alloc(expr) as *const CompactExpr as u64;
+    debug_assert!(ptr & STR_LENGTH_MASK == 0, "Expression pointer has high bits set to 0");
+
+    // Safety: newly allocated pointer is never 0
+    unsafe { NonZero::new_unchecked(ptr) }
+  }
+
+  /// The index must be at most [TERM_MASK], which is only checked in debug builds.
+  /// Use [Allocator::try_new_term] for indexes that haven't been validated yet.
   #[allow(clippy::needless_lifetimes)]
   pub fn new_term<'a>(&'a self, de_bruijn_index: NonZero<u64>) -> ExprRef<'a> {
     debug_assert!(de_bruijn_index.get() <= TERM_MASK, "Term index is too large");
@@ -247,25 +1153,194 @@ impl Allocator {
     ExprRef(term, PhantomData)
   }
 
+  /// Same as new_term(), but returns an error if the index is too large to store
+  #[allow(clippy::needless_lifetimes)]
+  pub fn try_new_term<'a>(&'a self, de_bruijn_index: NonZero<u64>) -> Result<ExprRef<'a>, AllocError> {
+    if de_bruijn_index.get() > TERM_MASK {
+      return Err(AllocError {
+        de_bruijn_index: de_bruijn_index.get(),
+      });
+    }
+
+    Ok(self.new_term(de_bruijn_index))
+  }
+
   /// The parameter name must be 32,767 characters or less
   pub fn new_lambda<'a>(&'a self, param_name: &'a str, body: ExprRef<'a>) -> ExprRef<'a> {
-    let lambda = self.arena.alloc(CompactExpr::new_lambda(param_name, body));
-    let lambda_ptr = lambda as *const CompactExpr as u64;
-    debug_assert!(
-      lambda_ptr & STR_LENGTH_MASK == 0,
-      "Lambda pointer has high bits set to 0"
+    ExprRef(self.alloc(CompactExpr::new_lambda(param_name, body)), PhantomData)
+  }
+
+  pub fn new_eval<'a>(&'a self, left: ExprRef<'a>, right: ExprRef<'a>) -> ExprRef<'a> {
+    ExprRef(self.alloc(CompactExpr::new_eval(left, right)), PhantomData)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn variable_names_use_bijective_base_26() {
+    assert_eq!(index_to_variable_name(0), "x");
+    assert_eq!(index_to_variable_name(2), "z");
+    assert_eq!(index_to_variable_name(3), "a");
+    assert_eq!(index_to_variable_name(25), "w");
+    assert_eq!(index_to_variable_name(26), "xx");
+    assert_eq!(index_to_variable_name(27), "xy");
+    assert_eq!(index_to_variable_name(51), "xw");
+    assert_eq!(index_to_variable_name(52), "yx");
+    assert_eq!(index_to_variable_name(700), "wv");
+    assert_eq!(index_to_variable_name(701), "ww");
+    assert_eq!(index_to_variable_name(702), "xxx");
+  }
+
+  #[test]
+  fn fresh_names_replace_the_parameter_names() {
+    let allocator = Allocator::new();
+    let a = allocator.new_term(NonZero::new(2).unwrap());
+    let b = allocator.new_term(NonZero::new(1).unwrap());
+    let expr = allocator.new_lambda("a", allocator.new_lambda("b", allocator.new_eval(a, b)));
+
+    assert_eq!(expr.display_with_fresh_names(&[]).to_string(), r"\x.\y.(x y)");
+  }
+
+  #[test]
+  fn first_difference_rewinds_the_path_past_equal_subexpressions() {
+    let allocator = Allocator::new();
+    let x = allocator.new_term(NonZero::new(1).unwrap());
+    let identity = |name| allocator.new_lambda(name, x);
+
+    // ((\q.q) \z.z) vs ((\q.q) \z.\y.y)
+    let a = allocator.new_eval(identity("q"), identity("z"));
+    let b = allocator.new_eval(identity("q"), allocator.new_lambda("z", identity("y")));
+    let difference = a.first_difference(b).unwrap();
+    assert_eq!(difference.path, [PathStep::Right, PathStep::Body]);
+    assert_eq!(difference.to_string(), r"root.right.body: ?1 vs λy.y");
+
+    assert!(
+      a.first_difference(allocator.new_eval(identity("r"), identity("s")))
+        .is_none()
     );
+  }
 
-    // Safety: newly allocated pointer is never 0
-    ExprRef(unsafe { NonZero::new_unchecked(lambda_ptr) }, PhantomData)
+  #[test]
+  fn free_variables_print_with_a_prefix() {
+    let allocator = Allocator::new();
+    let bound = allocator.new_term(NonZero::new(1).unwrap());
+    let free = allocator.new_term(NonZero::new(2).unwrap());
+    let expr = allocator.new_lambda("x", allocator.new_eval(bound, free));
+
+    assert_eq!(expr.to_string(), r"\x.(x ?1)");
+    assert_eq!(free.to_string(), "?2");
   }
 
-  pub fn new_eval<'a>(&'a self, left: ExprRef<'a>, right: ExprRef<'a>) -> ExprRef<'a> {
-    let eval = self.arena.alloc(CompactExpr::new_eval(left, right));
-    let eval_ptr = eval as *const CompactExpr as u64;
-    debug_assert!(eval_ptr & STR_LENGTH_MASK == 0, "Eval pointer has high bits set to 0");
+  #[test]
+  fn sizes_match_the_size_from_fold() {
+    let allocator = Allocator::new();
+    let x = allocator.new_term(NonZero::new(1).unwrap());
+    let y = allocator.new_term(NonZero::new(2).unwrap());
+    let expr = allocator.new_lambda(
+      "x",
+      allocator.new_lambda("y", allocator.new_eval(allocator.new_eval(x, y), x)),
+    );
 
-    // Safety: newly allocated pointer is never 0
-    ExprRef(unsafe { NonZero::new_unchecked(eval_ptr) }, PhantomData)
+    let sizes = expr.sizes();
+    assert_eq!(
+      sizes,
+      ExprSizes {
+        terms: 3,
+        lambdas: 2,
+        evals: 2,
+        depth: 5
+      }
+    );
+    assert_eq!(sizes.terms + sizes.lambdas + sizes.evals, expr.size());
+
+    // Deeper than any recursive visitor could go
+    let mut deep = x;
+    for _ in 0..1_000_000 {
+      deep = allocator.new_lambda("x", deep);
+    }
+    assert_eq!(deep.sizes().depth, 1_000_001);
+    assert_eq!(deep.size(), 1_000_001);
+  }
+
+  #[test]
+  fn shift_only_moves_the_free_terms() {
+    let allocator = Allocator::new();
+    let term = |index| allocator.new_term(NonZero::new(index).unwrap());
+    let lambda = |index| allocator.new_lambda("x", allocator.new_eval(term(1), term(index)));
+
+    // The bound x never changes, but the free variable does in either direction
+    assert!(lambda(3).shift(&allocator, 1, 2).unwrap().structurally_eq(lambda(5)));
+    assert!(lambda(3).shift(&allocator, 1, -1).unwrap().structurally_eq(lambda(2)));
+
+    // Terms below the cutoff are left alone, even if they are free
+    assert!(lambda(3).shift(&allocator, 3, 1).unwrap().structurally_eq(lambda(3)));
+
+    // Shifting below index 1 is an error, even under a lambda
+    let error = lambda(3).shift(&allocator, 1, -3).unwrap_err();
+    assert_eq!(
+      error,
+      ShiftError {
+        de_bruijn_index: 3,
+        offset: -3
+      }
+    );
+
+    // Indexes built outside of shift() are checked too
+    assert!(allocator.try_new_term(NonZero::new(TERM_MASK).unwrap()).is_ok());
+    assert!(allocator.try_new_term(NonZero::new(TERM_MASK + 1).unwrap()).is_err());
+  }
+
+  #[test]
+  fn shift_rejects_indexes_outside_the_valid_range() {
+    let allocator = Allocator::new();
+    let largest = allocator.new_term(NonZero::new(TERM_MASK).unwrap());
+    let almost_largest = allocator.new_term(NonZero::new(TERM_MASK - 1).unwrap());
+    let first = allocator.new_term(NonZero::new(1).unwrap());
+
+    let shifted = almost_largest.shift(&allocator, 1, 1).unwrap();
+    assert!(shifted.structurally_eq(largest));
+
+    // Overflow past the largest index that fits in an ExprRef
+    let error = largest.shift(&allocator, 1, 1).unwrap_err();
+    assert_eq!(
+      error,
+      ShiftError {
+        de_bruijn_index: TERM_MASK,
+        offset: 1
+      }
+    );
+    let error = first.shift(&allocator, 1, i64::MAX).unwrap_err();
+    assert_eq!(error.de_bruijn_index, 1);
+
+    // Underflow to an index of 0, which doesn't reference any lambda
+    let error = first.shift(&allocator, 1, -1).unwrap_err();
+    assert_eq!(
+      error,
+      ShiftError {
+        de_bruijn_index: 1,
+        offset: -1
+      }
+    );
+    let error = largest.shift(&allocator, 1, -(TERM_MASK as i64)).unwrap_err();
+    assert_eq!(error.de_bruijn_index, TERM_MASK);
+
+    // Bound terms are never shifted, no matter how large the offset is
+    let lambda = allocator.new_lambda("x", first);
+    assert_eq!(lambda.shift(&allocator, 1, -1), Ok(lambda));
+  }
+
+  #[test]
+  fn identical_expressions_get_different_pointers() {
+    let allocator = Allocator::new();
+    let x = allocator.new_term(NonZero::new(1).unwrap());
+
+    let first = allocator.new_lambda("x", x);
+    let second = allocator.new_lambda("x", x);
+    assert_ne!(first, second);
+    assert!(first.structurally_eq(second));
+    assert_ne!(allocator.new_eval(first, x), allocator.new_eval(first, x));
   }
 }
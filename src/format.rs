@@ -0,0 +1,435 @@
+#[cfg(feature = "cli")]
+use crate::symbol_table::LineNumber;
+#[cfg(feature = "cli")]
+use crate::syntax::ProgramParser;
+#[cfg(feature = "cli")]
+use std::fmt::Write;
+
+/// Code exactly as written in the source file, before any names are resolved
+#[cfg(feature = "cli")]
+#[derive(Debug, Clone)]
+pub enum Syntax<'input> {
+  Paren(Box<Syntax<'input>>),
+  Term(&'input str),
+  Lambda(Vec<&'input str>, Box<Syntax<'input>>),
+  Apply(Vec<Syntax<'input>>),
+  Pipe(Vec<Vec<Syntax<'input>>>),
+  Number(&'input str),
+  List(Vec<Syntax<'input>>),
+  Tuple(Vec<Syntax<'input>>),
+  Call(&'input str, Vec<Vec<Syntax<'input>>>),
+  Let(Vec<(&'input str, Syntax<'input>)>, Box<Syntax<'input>>),
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Clone)]
+pub enum Statement<'input> {
+  Definition(&'input str, Syntax<'input>),
+  Expression(Syntax<'input>),
+  Include(&'input str),
+  Assert(Syntax<'input>, Syntax<'input>),
+}
+
+/// Statement along with the byte range it covers in the source file
+#[cfg(feature = "cli")]
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+  pub start: usize,
+  pub statement: T,
+  pub end: usize,
+}
+
+/// Reprint code in the canonical style, keeping all comments.
+///
+/// - Each statement is printed on its own line, with single spaces between tokens
+/// - Redundant parentheses are removed
+/// - The `=` signs of consecutive definitions are aligned
+/// - Runs of blank lines are collapsed into a single blank line
+/// - Comments inside a statement are moved to the line above it
+///
+/// Formatting already formatted code returns it unchanged.
+#[cfg(feature = "cli")]
+pub fn format_code(code: &str) -> Result<String, String> {
+  let blanked = blank_block_comments(code);
+  let parsed_code = blanked.as_deref().unwrap_or(code);
+  let statements = ProgramParser::new().parse(parsed_code).map_err(|e| {
+    e.map_location(|offset| lookup_line_number(code, offset))
+      .map_token(|t| t.1)
+      .to_string()
+  })?;
+
+  // Merge the statements and comments back into the order they were written
+  let mut items = Vec::new();
+  let mut comments = find_comments(code)
+    .into_iter()
+    .map(|comment| (comment.offset, comment.text))
+    .peekable();
+  let mut statements = statements.into_iter().peekable();
+  while let Some(statement) = statements.next() {
+    while let Some((offset, comment)) = comments.next_if(|(offset, _)| *offset < statement.end) {
+      if offset < statement.start {
+        items.push(Spanned {
+          start: offset,
+          statement: Item::Comment(comment),
+          end: offset + comment.len(),
+        });
+      } else {
+        // Comment is inside the statement, so move it above
+        items.push(Spanned {
+          start: statement.start,
+          statement: Item::Comment(comment),
+          end: statement.start,
+        });
+      }
+    }
+
+    // Keep comments at the end of the same line attached to the statement
+    let mut end = statement.end;
+    let next_start = statements.peek().map_or(code.len(), |next| next.start);
+    let trailing_comment = comments
+      .next_if(|(offset, _)| *offset < next_start && !code[statement.end..*offset].contains(['\n', '\r']))
+      .map(|(offset, comment)| {
+        end = offset + comment.len();
+        comment
+      });
+
+    items.push(Spanned {
+      start: statement.start,
+      statement: Item::Statement(statement.statement, trailing_comment),
+      end,
+    });
+  }
+  items.extend(comments.map(|(offset, comment)| Spanned {
+    start: offset,
+    statement: Item::Comment(comment),
+    end: offset + comment.len(),
+  }));
+
+  // Split into blocks separated by blank lines
+  let mut blocks: Vec<Vec<Item>> = Vec::new();
+  let mut previous_end = None;
+  for item in items {
+    let is_new_block = match previous_end {
+      None => true,
+      Some(previous_end) => count_line_breaks(&code[previous_end..item.start]) > 1,
+    };
+    if is_new_block {
+      blocks.push(Vec::new());
+    }
+
+    previous_end = Some(item.end);
+    blocks.last_mut().unwrap().push(item.statement);
+  }
+
+  let mut output = String::new();
+  for (i, block) in blocks.iter().enumerate() {
+    if i > 0 {
+      output.push('\n');
+    }
+    write_block(&mut output, block);
+  }
+
+  Ok(output)
+}
+
+#[cfg(feature = "cli")]
+enum Item<'input> {
+  Comment(&'input str),
+  Statement(Statement<'input>, Option<&'input str>),
+}
+
+/// Write a group of lines, aligning the `=` of consecutive definitions
+#[cfg(feature = "cli")]
+fn write_block(output: &mut String, block: &[Item<'_>]) {
+  for group in block.chunk_by(|a, b| is_definition(a) == is_definition(b)) {
+    let name_width = group
+      .iter()
+      .map(|item| match item {
+        Item::Statement(Statement::Definition(name, _), _) => name.chars().count(),
+        _ => 0,
+      })
+      .max()
+      .unwrap_or(0);
+
+    for item in group {
+      match item {
+        Item::Comment(comment) => output.push_str(comment),
+        Item::Statement(statement, trailing_comment) => {
+          match statement {
+            Statement::Definition(name, expr) => {
+              write!(output, "{name: <name_width$} = ").unwrap();
+              write_syntax(output, expr, false);
+            },
+            Statement::Expression(expr) => write_syntax(output, expr, false),
+            Statement::Include(path) => write!(output, "include \"{path}\"").unwrap(),
+            Statement::Assert(left, right) => {
+              output.push_str("assert ");
+              write_syntax(output, left, false);
+              output.push_str(" == ");
+              write_syntax(output, right, false);
+            },
+          }
+
+          if let Some(comment) = trailing_comment {
+            write!(output, " {comment}").unwrap();
+          }
+        },
+      }
+      output.push('\n');
+    }
+  }
+}
+
+#[cfg(feature = "cli")]
+fn is_definition(item: &Item<'_>) -> bool {
+  matches!(item, Item::Statement(Statement::Definition(..), _))
+}
+
+/// Lambdas and lets only keep their parentheses when written next to other expressions, like `(f (\x.x) y)`.
+/// All other parentheses around a single expression are redundant.
+#[cfg(feature = "cli")]
+fn write_syntax(output: &mut String, syntax: &Syntax<'_>, in_sequence: bool) {
+  match syntax {
+    Syntax::Paren(inner) => {
+      let mut inner = inner.as_ref();
+      while let Syntax::Paren(next) = inner {
+        inner = next.as_ref();
+      }
+
+      if in_sequence && matches!(inner, Syntax::Lambda(..) | Syntax::Let(..)) {
+        output.push('(');
+        write_syntax(output, inner, false);
+        output.push(')');
+      } else {
+        write_syntax(output, inner, in_sequence);
+      }
+    },
+
+    Syntax::Term(name) | Syntax::Number(name) => output.push_str(name),
+
+    Syntax::Lambda(names, body) => {
+      write!(output, "\\{}.", names.join(" ")).unwrap();
+      write_syntax(output, body, false);
+    },
+
+    Syntax::Apply(items) => {
+      output.push('(');
+      write_sequence(output, items);
+      output.push(')');
+    },
+
+    Syntax::Pipe(stages) => {
+      output.push('(');
+      for (i, stage) in stages.iter().enumerate() {
+        if i > 0 {
+          output.push_str(" |> ");
+        }
+        write_sequence(output, stage);
+      }
+      output.push(')');
+    },
+
+    Syntax::List(items) => {
+      output.push('[');
+      write_sequence(output, items);
+      output.push(']');
+    },
+
+    Syntax::Tuple(items) => {
+      output.push('{');
+      write_sequence(output, items);
+      output.push('}');
+    },
+
+    Syntax::Call(name, args) => {
+      write!(output, "{name}(").unwrap();
+      for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+          output.push_str(", ");
+        }
+        write_sequence(output, arg);
+      }
+      output.push(')');
+    },
+
+    Syntax::Let(bindings, body) => {
+      output.push_str("let ");
+      for (i, (name, value)) in bindings.iter().enumerate() {
+        if i > 0 {
+          output.push_str(", ");
+        }
+        write!(output, "{name} = ").unwrap();
+        write_syntax(output, value, false);
+      }
+      output.push_str(" in ");
+      write_syntax(output, body, false);
+    },
+  }
+}
+
+#[cfg(feature = "cli")]
+fn write_sequence(output: &mut String, items: &[Syntax<'_>]) {
+  for (i, item) in items.iter().enumerate() {
+    if i > 0 {
+      output.push(' ');
+    }
+    write_syntax(output, item, true);
+  }
+}
+
+/// Comment in the code, found by [find_comments]
+#[derive(Debug, Clone, Copy)]
+pub struct Comment<'a> {
+  /// Byte offset where the comment starts
+  pub offset: usize,
+
+  /// Text of the comment, including the `;`, `#` or `{- -}` markers
+  pub text: &'a str,
+
+  /// Either a `{- block comment -}`, or a line comment that starts with `;` or `#`
+  pub is_block: bool,
+
+  /// Block comment that reaches the end of the code without being closed
+  pub is_unclosed: bool,
+}
+
+/// Replace every block comment with spaces, keeping the line breaks so the byte offsets and line numbers stay the same.
+/// The lexer skips line comments by itself, but it can't match nested block comments.
+/// Returns None if there are no block comments.
+pub fn blank_block_comments(code: &str) -> Option<String> {
+  let comments: Vec<_> = find_comments(code).into_iter().filter(|c| c.is_block).collect();
+  if comments.is_empty() {
+    return None;
+  }
+
+  let mut blanked = String::with_capacity(code.len());
+  let mut index = 0;
+  for comment in comments {
+    blanked.push_str(&code[index..comment.offset]);
+    for c in comment.text.chars() {
+      match c {
+        '\n' | '\r' => blanked.push(c),
+        c => blanked.extend(std::iter::repeat_n(' ', c.len_utf8())),
+      }
+    }
+    index = comment.offset + comment.text.len();
+  }
+  blanked.push_str(&code[index..]);
+
+  Some(blanked)
+}
+
+/// Find every `; comment`, `# comment` and `{- block comment -}` in the code, skipping over string literals and identifiers.
+/// Block comments can be nested, and an unclosed block comment continues to the end of the code.
+pub fn find_comments(code: &str) -> Vec<Comment<'_>> {
+  const SPECIAL_CHARACTERS: &[char] = &['\\', 'λ', '.', ';', ',', '(', ')', '[', ']', '{', '}'];
+  let token_end = |start: usize| {
+    code[start..]
+      .find(|c: char| c.is_whitespace() || SPECIAL_CHARACTERS.contains(&c))
+      .map_or(code.len(), |length| start + length)
+  };
+
+  let mut comments = Vec::new();
+  let mut index = 0;
+  while let Some(c) = code[index..].chars().next() {
+    index = match c {
+      ';' | '#' => {
+        let end = code[index..]
+          .find(['\n', '\r'])
+          .map_or(code.len(), |length| index + length);
+        comments.push(Comment {
+          offset: index,
+          text: code[index..end].trim_end(),
+          is_block: false,
+          is_unclosed: false,
+        });
+        end
+      },
+
+      '{' if code[index..].starts_with("{-") => {
+        let (end, is_unclosed) = block_comment_end(code, index);
+        comments.push(Comment {
+          offset: index,
+          text: &code[index..end],
+          is_block: true,
+          is_unclosed,
+        });
+        end
+      },
+
+      // The lexer picks the longest match between a string literal and an identifier
+      '"' => {
+        let string_end = code[index + 1..]
+          .find(['"', '\n', '\r'])
+          .filter(|length| code[index + 1 + length..].starts_with('"'))
+          .map(|length| index + length + 2);
+
+        token_end(index).max(string_end.unwrap_or(0))
+      },
+
+      c if c.is_whitespace() || SPECIAL_CHARACTERS.contains(&c) => index + c.len_utf8(),
+      _ => token_end(index),
+    };
+  }
+
+  comments
+}
+
+/// Find the end of the block comment starting at `start`, counting any nested comments inside of it.
+/// Also returns true if the comment is never closed.
+fn block_comment_end(code: &str, start: usize) -> (usize, bool) {
+  let mut depth = 0;
+  let mut index = start;
+  loop {
+    if code[index..].starts_with("{-") {
+      depth += 1;
+      index += 2;
+    } else if code[index..].starts_with("-}") {
+      depth -= 1;
+      index += 2;
+      if depth == 0 {
+        return (index, false);
+      }
+    } else {
+      match code[index..].chars().next() {
+        Some(c) => index += c.len_utf8(),
+        None => return (index, true),
+      }
+    }
+  }
+}
+
+#[cfg(feature = "cli")]
+fn count_line_breaks(text: &str) -> usize {
+  text.matches('\n').count()
+}
+
+#[cfg(feature = "cli")]
+fn lookup_line_number(code: &str, offset: usize) -> LineNumber {
+  let before = &code[..offset];
+  let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+  let column = before[line_start..].chars().count() + 1;
+  LineNumber::new_with_column(before.matches('\n').count() + 1, column)
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+  use super::*;
+
+  /// Code in every shape the formatter changes, and the canonical form it should be printed in
+  const UNFORMATTED: &str = "id   =  \\x.x\nlonger-name = \\x y.x\n\n\n; comment\n((id)   id) # trailing\n{- block -} (longer-name\n  id id)\nadd(1,2)\n";
+  const FORMATTED: &str = "id          = \\x.x\nlonger-name = \\x y.x\n\n; comment\n(id id) # trailing\n{- block -}\n(longer-name id id)\nadd(1, 2)\n";
+
+  #[test]
+  fn code_is_printed_in_the_canonical_style() {
+    assert_eq!(format_code(UNFORMATTED).unwrap(), FORMATTED);
+  }
+
+  #[test]
+  fn formatting_twice_changes_nothing() {
+    for code in [UNFORMATTED, crate::PRELUDE] {
+      let once = format_code(code).unwrap();
+      assert_eq!(format_code(&once).unwrap(), once);
+    }
+  }
+}
@@ -0,0 +1,378 @@
+use std::fmt::{self, Write};
+use std::{error::Error, num::NonZero};
+use typed_arena::Arena;
+
+use crate::expr::{Allocator, ExprRef, MAX_STR_LENGTH, TERM_MASK, UnpackedExpr};
+
+/// Convert an expression into a JSON tree that mirrors `UnpackedExpr`, with a `type` field of `term`, `lambda`
+/// or `eval`:
+///
+/// ```json
+/// {"type":"lambda","parameter_name":"x","body":{"type":"term","de_bruijn_index":1}}
+/// ```
+///
+/// Expressions like large Church numerals are nested very deeply, so this uses an explicit stack instead of recursion.
+pub fn expr_to_json(expr: ExprRef<'_>) -> String {
+  enum Work<'a> {
+    Expr(ExprRef<'a>),
+    Text(&'static str),
+  }
+
+  let mut json = String::new();
+  let mut work = vec![Work::Expr(expr)];
+  while let Some(item) = work.pop() {
+    let expr = match item {
+      Work::Expr(expr) => expr,
+      Work::Text(text) => {
+        json.push_str(text);
+        continue;
+      },
+    };
+
+    match expr.unpack() {
+      UnpackedExpr::Term { de_bruijn_index } => {
+        write!(json, r#"{{"type":"term","de_bruijn_index":{de_bruijn_index}}}"#).expect("string can always be written");
+      },
+      UnpackedExpr::Lambda { parameter_name, body } => {
+        let parameter_name = serde_json::to_string(parameter_name).expect("string can always be serialized");
+        write!(json, r#"{{"type":"lambda","parameter_name":{parameter_name},"body":"#)
+          .expect("string can always be written");
+        work.push(Work::Text("}"));
+        work.push(Work::Expr(body));
+      },
+      UnpackedExpr::Eval { left, right } => {
+        json.push_str(r#"{"type":"eval","left":"#);
+        work.push(Work::Text("}"));
+        work.push(Work::Expr(right));
+        work.push(Work::Text(r#","right":"#));
+        work.push(Work::Expr(left));
+      },
+    }
+  }
+
+  json
+}
+
+/// Parse an expression from the JSON tree written by [expr_to_json], allocating the parameter names in the text arena.
+/// The fields of each object can be in any order.
+///
+/// Objects that are still being read wait on an explicit stack instead of recursing,
+/// so there is no limit on how deeply the JSON can be nested.
+pub fn expr_from_json<'a>(
+  allocator: &'a Allocator,
+  text_data: &'a Arena<String>,
+  json: &str,
+) -> Result<ExprRef<'a>, Box<dyn Error>> {
+  /// What the next token has to be
+  #[derive(Clone, Copy)]
+  enum Expect {
+    Value,
+    KeyOrEnd,
+    Key,
+    Colon,
+    CommaOrEnd,
+  }
+
+  let mut tokens = JsonTokens { json, offset: 0 };
+  let mut objects: Vec<JsonObject<'a>> = Vec::new();
+  let mut expect = Expect::Value;
+  let expr = loop {
+    let (offset, token) = tokens.next()?.ok_or("unexpected end of JSON")?;
+    match (expect, token.as_bytes()[0]) {
+      (Expect::Value, b'{') => {
+        objects.push(JsonObject::default());
+        expect = Expect::KeyOrEnd;
+      },
+      (Expect::Value, _) if !objects.is_empty() => {
+        objects.last_mut().expect("object exists").set_value(token)?;
+        expect = Expect::CommaOrEnd;
+      },
+      (Expect::KeyOrEnd | Expect::Key, b'"') => {
+        objects.last_mut().expect("object exists").set_key(token)?;
+        expect = Expect::Colon;
+      },
+      (Expect::Colon, b':') => expect = Expect::Value,
+      (Expect::CommaOrEnd, b',') => expect = Expect::Key,
+      (Expect::KeyOrEnd | Expect::CommaOrEnd, b'}') => {
+        let expr = objects.pop().expect("object exists").build(allocator, text_data)?;
+        match objects.last_mut() {
+          Some(parent) => {
+            parent.set_child(expr)?;
+            expect = Expect::CommaOrEnd;
+          },
+          None => break expr,
+        }
+      },
+      _ => return Err(format!("unexpected {token} at byte {offset} of the JSON").into()),
+    }
+  };
+
+  match tokens.next()? {
+    None => Ok(expr),
+    Some((offset, token)) => Err(format!("unexpected {token} at byte {offset} after the end of the JSON").into()),
+  }
+}
+
+/// Splits JSON into strings, numbers, literals like `true`, and single punctuation characters.
+/// Strings are returned with their quotes and escapes, since serde_json decodes them once they're needed.
+struct JsonTokens<'j> {
+  json: &'j str,
+  offset: usize,
+}
+
+impl<'j> JsonTokens<'j> {
+  /// Returns the byte offset of the token along with its text, or None at the end of the input
+  fn next(&mut self) -> Result<Option<(usize, &'j str)>, Box<dyn Error>> {
+    let rest = &self.json[self.offset..];
+    let start = self.offset + (rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len());
+    let rest = &self.json[start..];
+
+    let length = match rest.as_bytes().first() {
+      None => return Ok(None),
+      Some(b'"') => {
+        let mut escaped = false;
+        let end = rest[1..].find(|c| match c {
+          _ if escaped => {
+            escaped = false;
+            false
+          },
+          '\\' => {
+            escaped = true;
+            false
+          },
+          c => c == '"',
+        });
+        end.ok_or_else(|| format!("string at byte {start} of the JSON is never closed"))? + 2
+      },
+      Some(b'-' | b'0'..=b'9' | b'a'..=b'z') => rest
+        .find(|c: char| !matches!(c, '-' | '+' | '.' | '0'..='9' | 'a'..='z' | 'A'..='Z'))
+        .unwrap_or(rest.len()),
+      Some(_) => rest.chars().next().expect("input is not empty").len_utf8(),
+    };
+
+    self.offset = start + length;
+    Ok(Some((start, &rest[..length])))
+  }
+}
+
+/// Field of a JSON object, which is read before its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonKey {
+  Type,
+  DeBruijnIndex,
+  ParameterName,
+  Body,
+  Left,
+  Right,
+}
+
+impl fmt::Display for JsonKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Self::Type => "type",
+      Self::DeBruijnIndex => "de_bruijn_index",
+      Self::ParameterName => "parameter_name",
+      Self::Body => "body",
+      Self::Left => "left",
+      Self::Right => "right",
+    };
+    write!(f, "{name}")
+  }
+}
+
+/// Object that is still being read, with every field found so far
+#[derive(Default)]
+struct JsonObject<'a> {
+  key: Option<JsonKey>,
+  expr_type: Option<String>,
+  de_bruijn_index: Option<u64>,
+  parameter_name: Option<String>,
+  body: Option<ExprRef<'a>>,
+  left: Option<ExprRef<'a>>,
+  right: Option<ExprRef<'a>>,
+}
+
+impl<'a> JsonObject<'a> {
+  fn set_key(&mut self, token: &str) -> Result<(), Box<dyn Error>> {
+    let key = match serde_json::from_str::<String>(token)?.as_str() {
+      "type" => JsonKey::Type,
+      "de_bruijn_index" => JsonKey::DeBruijnIndex,
+      "parameter_name" => JsonKey::ParameterName,
+      "body" => JsonKey::Body,
+      "left" => JsonKey::Left,
+      "right" => JsonKey::Right,
+      name => return Err(format!("unknown field `{name}`").into()),
+    };
+
+    let is_duplicate = match key {
+      JsonKey::Type => self.expr_type.is_some(),
+      JsonKey::DeBruijnIndex => self.de_bruijn_index.is_some(),
+      JsonKey::ParameterName => self.parameter_name.is_some(),
+      JsonKey::Body => self.body.is_some(),
+      JsonKey::Left => self.left.is_some(),
+      JsonKey::Right => self.right.is_some(),
+    };
+    if is_duplicate {
+      return Err(format!("duplicate field `{key}`").into());
+    }
+
+    self.key = Some(key);
+    Ok(())
+  }
+
+  /// Set the current field to a string or a number
+  fn set_value(&mut self, token: &str) -> Result<(), Box<dyn Error>> {
+    match self.key.take().expect("key is read before the value") {
+      JsonKey::Type => self.expr_type = Some(serde_json::from_str(token)?),
+      JsonKey::ParameterName => self.parameter_name = Some(serde_json::from_str(token)?),
+      JsonKey::DeBruijnIndex => {
+        let index = serde_json::from_str(token).map_err(|_| format!("invalid de Bruijn index {token}"))?;
+        self.de_bruijn_index = Some(index);
+      },
+      key => return Err(format!("field `{key}` must be an object, found {token}").into()),
+    }
+
+    Ok(())
+  }
+
+  /// Set the current field to an object that has been fully read
+  fn set_child(&mut self, child: ExprRef<'a>) -> Result<(), Box<dyn Error>> {
+    match self.key.take().expect("key is read before the value") {
+      JsonKey::Body => self.body = Some(child),
+      JsonKey::Left => self.left = Some(child),
+      JsonKey::Right => self.right = Some(child),
+      key => return Err(format!("field `{key}` cannot be an object").into()),
+    }
+
+    Ok(())
+  }
+
+  /// Build the expression through the allocator, checking that every value can be stored
+  fn build(self, allocator: &'a Allocator, text_data: &'a Arena<String>) -> Result<ExprRef<'a>, Box<dyn Error>> {
+    fn missing(key: JsonKey) -> String {
+      format!("missing field `{key}`")
+    }
+
+    match self.expr_type.as_deref() {
+      Some("term") => {
+        let de_bruijn_index = self.de_bruijn_index.ok_or_else(|| missing(JsonKey::DeBruijnIndex))?;
+        let de_bruijn_index = NonZero::new(de_bruijn_index)
+          .ok_or_else(|| format!("invalid de Bruijn index {de_bruijn_index}: must be between 1 and {TERM_MASK}"))?;
+        Ok(allocator.try_new_term(de_bruijn_index).map_err(|e| e.to_string())?)
+      },
+      Some("lambda") => {
+        let parameter_name = self.parameter_name.ok_or_else(|| missing(JsonKey::ParameterName))?;
+        if parameter_name.is_empty() || parameter_name.len() as u64 > MAX_STR_LENGTH {
+          return Err(format!("invalid parameter name {parameter_name:?}: must be 1 to {MAX_STR_LENGTH} bytes").into());
+        }
+
+        let body = self.body.ok_or_else(|| missing(JsonKey::Body))?;
+        let parameter_name = text_data.alloc(parameter_name).as_str();
+        Ok(allocator.new_lambda(parameter_name, body))
+      },
+      Some("eval") => {
+        let left = self.left.ok_or_else(|| missing(JsonKey::Left))?;
+        let right = self.right.ok_or_else(|| missing(JsonKey::Right))?;
+        Ok(allocator.new_eval(left, right))
+      },
+      Some(expr_type) => Err(format!("unknown type `{expr_type}`, expected `term`, `lambda` or `eval`").into()),
+      None => Err(missing(JsonKey::Type).into()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn json_loads_back_the_same_expression() {
+    let allocator = Allocator::new();
+    let text_data = Arena::new();
+    let x = allocator.new_term(NonZero::new(1).unwrap());
+    let y = allocator.new_term(NonZero::new(2).unwrap());
+    let expr = allocator.new_lambda("x", allocator.new_lambda("\"y\"", allocator.new_eval(y, x)));
+
+    let json = expr_to_json(expr);
+    assert_eq!(
+      json,
+      r#"{"type":"lambda","parameter_name":"x","body":{"type":"lambda","parameter_name":"\"y\"","body":{"type":"eval","left":{"type":"term","de_bruijn_index":2},"right":{"type":"term","de_bruijn_index":1}}}}"#
+    );
+
+    let loaded = expr_from_json(&allocator, &text_data, &json).unwrap();
+    assert!(loaded.structurally_eq(expr));
+    assert_eq!(loaded.to_string(), expr.to_string());
+
+    // Fields can be in any order, with any whitespace between the tokens
+    let reordered =
+      r#" { "body" : { "de_bruijn_index" : 1 , "type" : "term" } , "parameter_name" : "z" , "type" : "lambda" } "#;
+    let loaded = expr_from_json(&allocator, &text_data, reordered).unwrap();
+    assert_eq!(loaded.to_string(), r"\z.z");
+  }
+
+  #[test]
+  fn deeply_nested_json_does_not_overflow_the_stack() {
+    let allocator = Allocator::new();
+    let text_data = Arena::new();
+    let depth = 200_000;
+
+    let lambda = r#"{"type":"lambda","parameter_name":"x","body":"#;
+    let term = r#"{"type":"term","de_bruijn_index":1}"#;
+    let json = format!("{}{term}{}", lambda.repeat(depth), "}".repeat(depth));
+    let expr = expr_from_json(&allocator, &text_data, &json).unwrap();
+    assert_eq!(expr.size(), depth as u64 + 1);
+    assert_eq!(expr_to_json(expr), json);
+
+    // Deep input that is cut off is an error too
+    let error = expr_from_json(&allocator, &text_data, &json[..json.len() - 1]).unwrap_err();
+    assert_eq!(error.to_string(), "unexpected end of JSON");
+  }
+
+  #[test]
+  fn invalid_json_is_an_error() {
+    let allocator = Allocator::new();
+    let text_data = Arena::new();
+    let error = |json| expr_from_json(&allocator, &text_data, json).unwrap_err().to_string();
+
+    assert_eq!(error(r#"{"type":"term"}"#), "missing field `de_bruijn_index`");
+    assert_eq!(
+      error(r#"{"type":"term","de_bruijn_index":0}"#),
+      format!("invalid de Bruijn index 0: must be between 1 and {TERM_MASK}")
+    );
+    assert_eq!(
+      error(r#"{"type":"term","de_bruijn_index":-1}"#),
+      "invalid de Bruijn index -1"
+    );
+    assert_eq!(error(r#"{"type":"term","type":"term"}"#), "duplicate field `type`");
+    assert_eq!(
+      error(r#"{"type":"lambda","parameter_name":"x","body":1}"#),
+      "field `body` must be an object, found 1"
+    );
+    assert_eq!(
+      error(r#"{"type":"lambda","parameter_name":"","body":{}}"#),
+      "missing field `type`"
+    );
+    assert_eq!(
+      error(r#"{"type":"lambda","parameter_name":"","body":{"type":"term","de_bruijn_index":1}}"#),
+      r#"invalid parameter name "": must be 1 to 32767 bytes"#
+    );
+    assert_eq!(error(r#"{"kind":"term"}"#), "unknown field `kind`");
+    assert_eq!(
+      error(r#"{"type":"var","de_bruijn_index":1}"#),
+      "unknown type `var`, expected `term`, `lambda` or `eval`"
+    );
+    assert_eq!(
+      error(r#"{"type":"term","de_bruijn_index":1}}"#),
+      "unexpected } at byte 35 after the end of the JSON"
+    );
+    assert_eq!(
+      error(r#"{"type" "term"}"#),
+      r#"unexpected "term" at byte 8 of the JSON"#
+    );
+    assert_eq!(error(r#"[1]"#), "unexpected [ at byte 0 of the JSON");
+    assert_eq!(
+      error(r#"{"type":"term}"#),
+      "string at byte 8 of the JSON is never closed"
+    );
+  }
+}
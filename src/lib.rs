@@ -0,0 +1,55 @@
+//! Lambda calculus interpreter, which can also be used as a library to build other frontends.
+//!
+//! The main types are re-exported here:
+//!
+//! - [Executor] loads code and evaluates expressions, which is the easiest place to start
+//! - [SymbolTable] compiles code into expressions, and is used by the generated parsers
+//! - [Allocator] stores expressions, and [ExprRef] points to one of them
+//! - [ExprVisitor] and [UnpackedExpr] walk through the structure of an expression
+//!
+//! ```no_run
+//! use rusty_lambda::{Allocator, EvalOptions, Executor, PRELUDE};
+//!
+//! let executor = Executor::new();
+//! executor.load_code(PRELUDE, Some("prelude")).unwrap();
+//!
+//! let eval_allocator = Allocator::new();
+//! let expr = executor.load_expression(&eval_allocator, "(succ 1)").unwrap();
+//! let result = executor.evaluate(&eval_allocator, expr, EvalOptions::default());
+//! println!("{result:#}");
+//! ```
+//!
+//! The command line tools and REPL are behind the `cli` feature, which is on by default.
+//! Building with `--no-default-features` leaves only the evaluator, which also builds for `wasm32-unknown-unknown`.
+
+use lalrpop_util::lalrpop_mod;
+
+mod command;
+mod environment;
+mod expr;
+mod format;
+#[cfg(feature = "cli")]
+mod json;
+mod readable;
+mod symbol_table;
+
+lalrpop_mod!(lambda);
+#[cfg(feature = "cli")]
+lalrpop_mod!(syntax);
+
+pub use command::executor::{EvalError, EvalLimits, EvalOptions, Executor, NormalForm, ReductionStrategy, TraceFormat};
+pub use expr::{Allocator, DisplayOptions, ExprRef, ExprVisitor, UnpackedExpr};
+pub use symbol_table::SymbolTable;
+
+/// Arguments of each command of the `lambda` binary, which aren't part of the library API
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod cli {
+  pub use crate::command::{
+    BenchArgs, CommandResult, CompileArgs, DecodeArgs, DiffArgs, EncodeArgs, EnvArgs, EquivClassesArgs, FmtArgs,
+    RandomArgs, ReduceArgs, RunArgs,
+  };
+}
+
+/// Source code of the built-in definitions, like `true`, `pair` and `succ`
+pub static PRELUDE: &str = include_str!("prelude.txt");
@@ -1,19 +1,11 @@
 use clap::{Parser, Subcommand};
-use lalrpop_util::lalrpop_mod;
-
-pub mod command;
-pub mod expr;
-pub mod symbol_table;
-
-lalrpop_mod!(pub lambda);
-
-pub static PRELUDE: &str = include_str!("prelude.txt");
+use rusty_lambda::cli;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Opt {
   #[clap(flatten)]
-  run_args: command::RunArgs,
+  run_args: cli::RunArgs,
 
   #[clap(subcommand)]
   subcommand: Option<SubCommand>,
@@ -21,11 +13,19 @@ struct Opt {
 
 #[derive(Subcommand)]
 enum SubCommand {
-  Encode(command::EncodeArgs),
-  Decode(command::DecodeArgs),
+  Encode(cli::EncodeArgs),
+  Decode(cli::DecodeArgs),
+  Random(cli::RandomArgs),
+  Env(cli::EnvArgs),
+  Fmt(cli::FmtArgs),
+  EquivClasses(cli::EquivClassesArgs),
+  Reduce(cli::ReduceArgs),
+  Diff(cli::DiffArgs),
+  Compile(cli::CompileArgs),
+  Bench(cli::BenchArgs),
 }
 
-fn main() -> command::CommandResult {
+fn main() -> cli::CommandResult {
   let opt = Opt::parse();
   match opt.subcommand {
     None => opt.run_args.execute(),
@@ -34,6 +34,14 @@ fn main() -> command::CommandResult {
       match command {
         Encode(args) => args.execute(),
         Decode(args) => args.execute(),
+        Random(args) => args.execute(),
+        Env(args) => args.execute(),
+        Fmt(args) => args.execute(),
+        EquivClasses(args) => args.execute(),
+        Reduce(args) => args.execute(),
+        Diff(args) => args.execute(),
+        Compile(args) => args.execute(),
+        Bench(args) => args.execute(),
       }
     },
   }
@@ -0,0 +1,468 @@
+use crate::expr::{Allocator, ExprRef, UnpackedExpr};
+#[cfg(feature = "cli")]
+use crate::expr::{DisplayOptions, ExprVisitor};
+#[cfg(feature = "cli")]
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "cli")]
+use std::num::NonZero;
+
+/// Which Church encodings to print in a more readable form
+#[cfg(feature = "cli")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadableOptions {
+  /// Print `\x y.x` as `true` and `\x y.y` as `false`
+  pub booleans: bool,
+
+  /// Print `\f.((f a) b)` as `(a, b)`
+  pub pairs: bool,
+
+  /// Print Church numerals `\f.\x.(f (f ... (f x)))` as plain numbers
+  pub numerals: bool,
+
+  /// Print right-fold lists `\c.\n.((c a) ((c b) n))` as `[a, b]`
+  pub lists: bool,
+
+  /// Print Scott numerals `\z s.z` and `\z s.(s n)` as `scott(n)`, up to this value
+  pub scott_numerals: Option<u64>,
+
+  /// Print anything that isn't decoded using de Bruijn indices, like `λ.λ.(2 1)`
+  pub de_bruijn_indices: bool,
+
+  /// Leave out redundant parentheses, so `(((a b) c) d)` is printed as `a b c d`
+  pub minimal_parens: bool,
+
+  /// Print anything nested deeper than this as `…`
+  pub max_depth: Option<NonZero<u64>>,
+
+  /// Don't add any characters outside of ASCII, so `λx.λx′.x` is printed as `\x.\x_1.x`
+  pub ascii: bool,
+}
+
+/// Format an expression, replacing any enabled Church encodings with a readable form.
+/// Anything that isn't recognized is printed as usual.
+#[cfg(feature = "cli")]
+pub fn format_readable(expr: ExprRef<'_>, options: ReadableOptions) -> String {
+  let allocator = Allocator::new();
+  format_with_allocator(expr, &allocator, options, None::<&KnownTerms>)
+}
+
+/// Same as [format_readable], but any part of the expression that has the same structure as a known term
+/// is printed using the name of the term instead.
+#[cfg(feature = "cli")]
+pub fn format_readable_with_known_terms(expr: ExprRef<'_>, options: ReadableOptions, known: &KnownTerms<'_>) -> String {
+  let allocator = Allocator::new();
+  format_with_allocator(expr, &allocator, options, Some(known))
+}
+
+#[cfg(feature = "cli")]
+fn format_with_allocator<'a, 'g: 'a>(
+  expr: ExprRef<'a>,
+  allocator: &'a Allocator,
+  options: ReadableOptions,
+  known: Option<&KnownTerms<'g>>,
+) -> String {
+  // Zero and false are the same expression, so numerals are checked first
+  let decoded = None
+    .or_else(|| options.numerals.then(|| decode_numeral(expr)).flatten())
+    .or_else(|| options.booleans.then(|| decode_boolean(expr)).flatten())
+    .or_else(|| {
+      options
+        .pairs
+        .then(|| decode_pair(expr, allocator, options, known))
+        .flatten()
+    })
+    .or_else(|| {
+      options
+        .lists
+        .then(|| decode_list(expr, allocator, options, known))
+        .flatten()
+    })
+    .or_else(|| {
+      options
+        .scott_numerals
+        .and_then(|limit| decode_scott_numeral(expr, limit))
+    });
+
+  decoded.unwrap_or_else(|| {
+    let display = DisplayOptions {
+      minimal_parens: options.minimal_parens,
+      max_depth: options.max_depth,
+      ascii: options.ascii,
+    };
+
+    if options.de_bruijn_indices {
+      format!("{:+#}", expr.display_with(&[], display))
+    } else if let Some(known) = known
+      && expr.is_closed()
+    {
+      let (expr, names) = replace_known_terms(expr, allocator, known);
+      format!("{:#}", expr.display_with(&names, display))
+    } else {
+      format!("{:#}", expr.display_with(&[], display))
+    }
+  })
+}
+
+/// Reverse lookup from the structure of an expression to the name of a global with the same structure
+#[cfg(feature = "cli")]
+pub struct KnownTerms<'g> {
+  by_hash: HashMap<u64, Vec<(&'g str, ExprRef<'g>)>>,
+}
+
+#[cfg(feature = "cli")]
+impl<'g> KnownTerms<'g> {
+  /// When several globals have the same structure, the shortest name is used, then the first alphabetically
+  pub fn new(globals: &BTreeMap<&'g str, ExprRef<'g>>) -> Self {
+    let mut by_hash: HashMap<u64, Vec<(&'g str, ExprRef<'g>)>> = HashMap::new();
+    for (name, expr) in globals.iter() {
+      by_hash.entry(expr.structural_hash()).or_default().push((*name, *expr));
+    }
+
+    for globals in by_hash.values_mut() {
+      globals.sort_by_key(|(name, _)| (name.chars().count(), *name));
+    }
+
+    Self { by_hash }
+  }
+
+  /// Find the name for an expression, given its structural hash
+  fn lookup(&self, expr: ExprRef<'_>, hash: u64) -> Option<&'g str> {
+    self
+      .by_hash
+      .get(&hash)?
+      .iter()
+      .find(|(_, global)| global.structurally_eq(expr))
+      .map(|(name, _)| *name)
+  }
+}
+
+/// Replace the largest subexpressions that match a known term with free variables.
+/// Returns the new expression and the names of the variables from outermost to innermost,
+/// so it can be printed using [ExprRef::display_in_context].
+#[cfg(feature = "cli")]
+fn replace_known_terms<'a, 'g: 'a>(
+  expr: ExprRef<'a>,
+  allocator: &'a Allocator,
+  known: &KnownTerms<'g>,
+) -> (ExprRef<'a>, Vec<&'a str>) {
+  struct ReplaceVisitor<'a, 'k, 'g> {
+    allocator: &'a Allocator,
+    known: &'k KnownTerms<'g>,
+    hashes: HashMap<ExprRef<'a>, u64>,
+    names: Vec<&'g str>, // Innermost first, so indexes don't change as more names are found
+    depth: u64,
+  }
+
+  impl<'a> ReplaceVisitor<'a, '_, '_> {
+    fn replace(&mut self, expr: ExprRef<'a>) -> Option<ExprRef<'a>> {
+      let name = self.known.lookup(expr, self.hashes[&expr])?;
+      let index = match self.names.iter().position(|n| *n == name) {
+        Some(index) => index,
+        None => {
+          self.names.push(name);
+          self.names.len() - 1
+        },
+      };
+
+      let de_bruijn_index = self.depth + index as u64 + 1;
+      Some(
+        self
+          .allocator
+          .new_term(NonZero::new(de_bruijn_index).expect("invalid index")),
+      )
+    }
+  }
+
+  impl<'a> ExprVisitor<'a> for ReplaceVisitor<'a, '_, '_> {
+    type Output = ExprRef<'a>;
+
+    fn visit_term(&mut self, expr: ExprRef<'a>, _: NonZero<u64>) -> Self::Output {
+      expr // Globals are closed, so they can never be a single term
+    }
+
+    fn visit_lambda(&mut self, expr: ExprRef<'a>, body: ExprRef<'a>, parameter_name: &'a str) -> Self::Output {
+      if let Some(replaced) = self.replace(expr) {
+        return replaced;
+      }
+
+      self.depth += 1;
+      let body = body.visit(self);
+      self.depth -= 1;
+      self.allocator.new_lambda(parameter_name, body)
+    }
+
+    fn visit_eval(&mut self, expr: ExprRef<'a>, left: ExprRef<'a>, right: ExprRef<'a>) -> Self::Output {
+      if let Some(replaced) = self.replace(expr) {
+        return replaced;
+      }
+
+      let left = left.visit(self);
+      let right = right.visit(self);
+      self.allocator.new_eval(left, right)
+    }
+  }
+
+  let mut visitor = ReplaceVisitor {
+    allocator,
+    known,
+    hashes: expr.structural_hashes(),
+    names: Vec::new(),
+    depth: 0,
+  };
+  let expr = expr.visit(&mut visitor);
+
+  (expr, visitor.names.into_iter().rev().collect())
+}
+
+/// Print `\x y.x` as `true` and `\x y.y` as `false`
+#[cfg(feature = "cli")]
+pub fn decode_boolean(expr: ExprRef<'_>) -> Option<String> {
+  use UnpackedExpr::*;
+
+  let Lambda { body, .. } = expr.unpack() else {
+    return None;
+  };
+  let Lambda { body, .. } = body.unpack() else {
+    return None;
+  };
+
+  match body.unpack() {
+    Term { de_bruijn_index } if de_bruijn_index.get() == 2 => Some("true".into()),
+    Term { de_bruijn_index } if de_bruijn_index.get() == 1 => Some("false".into()),
+    _ => None,
+  }
+}
+
+/// Print a Church numeral as a plain number
+#[cfg(feature = "cli")]
+pub fn decode_numeral(expr: ExprRef<'_>) -> Option<String> {
+  as_church_numeral(expr).map(|n| n.to_string())
+}
+
+/// Print a Church pair as `(a, b)`, where both sides are also decoded using the options
+#[cfg(feature = "cli")]
+pub fn decode_pair<'a, 'g: 'a>(
+  expr: ExprRef<'a>,
+  allocator: &'a Allocator,
+  options: ReadableOptions,
+  known: Option<&KnownTerms<'g>>,
+) -> Option<String> {
+  let (left, right) = as_pair(expr, allocator)?;
+  Some(format!(
+    "({}, {})",
+    format_with_allocator(left, allocator, options, known),
+    format_with_allocator(right, allocator, options, known)
+  ))
+}
+
+/// Print a right-fold list as `[a, b, c]`, where every element is also decoded using the options
+#[cfg(feature = "cli")]
+pub fn decode_list<'a, 'g: 'a>(
+  expr: ExprRef<'a>,
+  allocator: &'a Allocator,
+  options: ReadableOptions,
+  known: Option<&KnownTerms<'g>>,
+) -> Option<String> {
+  let elements = as_church_list(expr, allocator)?;
+  let elements: Vec<_> = elements
+    .into_iter()
+    .map(|element| format_with_allocator(element, allocator, options, known))
+    .collect();
+  Some(format!("[{}]", elements.join(", ")))
+}
+
+/// Print a Scott numeral as `scott(n)`
+#[cfg(feature = "cli")]
+pub fn decode_scott_numeral(expr: ExprRef<'_>, limit: u64) -> Option<String> {
+  as_scott_numeral(expr, limit).map(|n| format!("scott({n})"))
+}
+
+/// Match a Church pair `\f.((f a) b)`, where neither `a` nor `b` use `f`.
+/// Returns `a` and `b` shifted outside of the lambda.
+pub fn as_pair<'a>(expr: ExprRef<'a>, allocator: &'a Allocator) -> Option<(ExprRef<'a>, ExprRef<'a>)> {
+  use UnpackedExpr::*;
+
+  let Lambda { body, .. } = expr.unpack() else {
+    return None;
+  };
+  let Eval { left, right: b } = body.unpack() else {
+    return None;
+  };
+  let Eval { left: f, right: a } = left.unpack() else {
+    return None;
+  };
+  let Term { de_bruijn_index } = f.unpack() else {
+    return None;
+  };
+  if de_bruijn_index.get() != 1 {
+    return None;
+  }
+
+  // Shifting fails if either side references the parameter
+  let a = a.shift(allocator, 1, -1).ok()?;
+  let b = b.shift(allocator, 1, -1).ok()?;
+  Some((a, b))
+}
+
+/// Match a right-fold list, where `nil = \c.\n.n` and `cons = \h.\t.\c.\n.((c h) (t c n))`,
+/// so the normal form of `[a, b]` is `\c.\n.((c a) ((c b) n))`. None of the elements can use `c` or `n`.
+/// Returns the elements shifted outside of both lambdas.
+#[cfg(feature = "cli")]
+pub fn as_church_list<'a>(expr: ExprRef<'a>, allocator: &'a Allocator) -> Option<Vec<ExprRef<'a>>> {
+  use UnpackedExpr::*;
+
+  let Lambda { body, .. } = expr.unpack() else {
+    return None;
+  };
+  let Lambda { body, .. } = body.unpack() else {
+    return None;
+  };
+
+  // Loop instead of recursing, since long lists are nested very deeply
+  let mut elements = Vec::new();
+  let mut expr = body;
+  loop {
+    match expr.unpack() {
+      Term { de_bruijn_index } if de_bruijn_index.get() == 1 => return Some(elements),
+      Eval { left, right } => {
+        let Eval {
+          left: c,
+          right: element,
+        } = left.unpack()
+        else {
+          return None;
+        };
+        if !matches!(c.unpack(), Term { de_bruijn_index } if de_bruijn_index.get() == 2) {
+          return None;
+        }
+
+        // Shifting fails if the element references either parameter
+        elements.push(element.shift(allocator, 1, -2).ok()?);
+        expr = right;
+      },
+      _ => return None,
+    }
+  }
+}
+
+/// Match a Church numeral `\f.\x.(f (f ... (f x)))`, returning the number of times `f` is applied
+#[cfg(feature = "cli")]
+pub fn as_church_numeral(expr: ExprRef<'_>) -> Option<u64> {
+  use UnpackedExpr::*;
+
+  let Lambda { body, .. } = expr.unpack() else {
+    return None;
+  };
+  let Lambda { body, .. } = body.unpack() else {
+    return None;
+  };
+
+  // Loop instead of recursing, since large numbers are nested very deeply
+  let mut n = 0;
+  let mut expr = body;
+  loop {
+    match expr.unpack() {
+      Term { de_bruijn_index } if de_bruijn_index.get() == 1 => return Some(n),
+      Eval { left, right } if matches!(left.unpack(), Term { de_bruijn_index } if de_bruijn_index.get() == 2) => {
+        n += 1;
+        expr = right;
+      },
+      _ => return None,
+    }
+  }
+}
+
+/// Match a Scott numeral, where zero is `\z s.z` and the successor of `n` is `\z s.(s n)`.
+///
+/// Large numerals are nested very deeply, so this loops instead of recursing.
+/// Returns `None` for any numeral larger than the limit.
+#[cfg(feature = "cli")]
+pub fn as_scott_numeral(mut expr: ExprRef<'_>, limit: u64) -> Option<u64> {
+  use UnpackedExpr::*;
+
+  for n in 0..=limit {
+    let Lambda { body, .. } = expr.unpack() else {
+      return None;
+    };
+    let Lambda { body, .. } = body.unpack() else {
+      return None;
+    };
+
+    match body.unpack() {
+      Term { de_bruijn_index } if de_bruijn_index.get() == 2 => return Some(n),
+      Eval { left, right } if matches!(left.unpack(), Term { de_bruijn_index } if de_bruijn_index.get() == 1) => {
+        expr = right; // Any reference to z or s fails to match on the next layer
+      },
+      _ => return None,
+    }
+  }
+
+  None
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+  use std::num::NonZero;
+
+  use super::*;
+
+  /// Build the Scott numeral for `n` directly, since parsing a term this deep would overflow the stack
+  fn scott_numeral(allocator: &Allocator, n: u64) -> ExprRef<'_> {
+    let z = allocator.new_term(NonZero::new(2).unwrap());
+    let s = allocator.new_term(NonZero::new(1).unwrap());
+
+    let mut numeral = allocator.new_lambda("z", allocator.new_lambda("s", z));
+    for _ in 0..n {
+      numeral = allocator.new_lambda("z", allocator.new_lambda("s", allocator.new_eval(s, numeral)));
+    }
+    numeral
+  }
+
+  #[test]
+  fn scott_numerals_are_recognized_up_to_the_limit() {
+    let allocator = Allocator::new();
+    let numeral = scott_numeral(&allocator, 200_000);
+
+    assert_eq!(as_scott_numeral(numeral, 1_000_000), Some(200_000));
+    assert_eq!(as_scott_numeral(numeral, 200_000), Some(200_000));
+    assert_eq!(as_scott_numeral(numeral, 199_999), None);
+    assert_eq!(as_scott_numeral(scott_numeral(&allocator, 0), 0), Some(0));
+  }
+
+  #[test]
+  fn scott_numerals_cannot_reference_outer_parameters() {
+    let allocator = Allocator::new();
+    let z = allocator.new_term(NonZero::new(2).unwrap());
+    let s = allocator.new_term(NonZero::new(1).unwrap());
+
+    // \z s.(s z) isn't the successor of anything
+    let expr = allocator.new_lambda("z", allocator.new_lambda("s", allocator.new_eval(s, z)));
+    assert_eq!(as_scott_numeral(expr, 10), None);
+  }
+
+  #[test]
+  fn pairs_are_printed_as_tuples() {
+    let executor = crate::Executor::new();
+    executor.load_code(crate::PRELUDE, Some("prelude")).unwrap();
+    let options = ReadableOptions {
+      pairs: true,
+      numerals: true,
+      ..ReadableOptions::default()
+    };
+
+    let eval_allocator = Allocator::new();
+    let format = |code| {
+      let expr = executor.load_expression(&eval_allocator, code).unwrap();
+      let result = executor.evaluate(&eval_allocator, expr, crate::EvalOptions::default());
+      format_readable(result, options)
+    };
+
+    assert_eq!(format("(pair 1 2)"), "(1, 2)");
+    assert_eq!(format("(pair (pair 1 2) 3)"), "((1, 2), 3)");
+
+    // Only two arguments applied to the parameter are a pair
+    assert_eq!(format(r"\p.(p \x.x)"), "λp.(p λx.x)");
+    assert_eq!(format(r"\p.(p \x.x \y.y \z.z)"), "λp.(((p λx.x) λy.y) λz.z)");
+    assert_eq!(format(r"\a.\p.(a p \y.y)"), "λa.λp.((a p) λy.y)");
+  }
+}
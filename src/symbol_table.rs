@@ -1,9 +1,27 @@
 use crate::expr::{Allocator, ExprRef};
+use crate::format::{blank_block_comments, find_comments};
+use crate::lambda::ProgramParser;
+#[cfg(feature = "cli")]
 use crossterm::style::Stylize;
 use itertools::Itertools;
-use lalrpop_util::{ErrorRecovery, lexer::Token};
+use lalrpop_util::{ErrorRecovery, ParseError, lexer::Token};
 use num_traits::Num;
-use std::{borrow::Cow, collections::BTreeMap, fmt, num::NonZero};
+use serde::Serialize;
+use std::{
+  borrow::Cow,
+  collections::{BTreeMap, HashMap, HashSet},
+  fmt, fs, mem,
+  num::NonZero,
+  path::PathBuf,
+};
+use typed_arena::Arena;
+
+/// Parameter names to use for `\f.\x.(f x)` in Church numerals, in order of preference
+const NUMERAL_FUNCTION_NAMES: &[&str] = &["f", "g", "h", "k"];
+const NUMERAL_VALUE_NAMES: &[&str] = &["x", "y", "z", "n"];
+
+/// Largest number literal allowed by default, since every number is a chain of that many applications
+pub const DEFAULT_MAX_NUMBER: u64 = 1_000_000;
 
 /// - Assigning an expression keeps results allocated permanently.
 /// - Evaluating an expression only computes results then clears allocations.
@@ -18,10 +36,60 @@ where
   numbers: &'numbers mut Vec<ExprRef<'assign>>,
   assign_scopes: Vec<&'assign str>,
   eval_scopes: Vec<&'eval str>,
+  includes: Option<IncludeContext<'assign, 'globals>>,
+  lambda_locations: Option<LambdaLocations<'eval, 'globals>>,
+  lambda_offsets: Vec<Offset>,
+  max_number: u64,
+  assertions: Vec<Assertion<'eval>>,
 
   messages: CompilerMessages,
 }
 
+/// `assert left == right` statement, which gets checked once the whole program is parsed.
+/// The location is looked up right away, since included files have their own line numbers.
+struct Assertion<'eval> {
+  left: ExprRef<'eval>,
+  right: ExprRef<'eval>,
+  file_name: Option<String>,
+  line_number: Option<LineNumber>,
+}
+
+/// Everything needed to load other files using `include "file"`
+pub struct IncludeContext<'assign, 'globals> {
+  text_data: &'assign Arena<String>,
+  included_files: &'globals mut HashSet<PathBuf>,
+  include_stack: Vec<PathBuf>,
+}
+
+impl<'assign, 'globals> IncludeContext<'assign, 'globals> {
+  /// Current file should be the canonical path of the file being loaded, if any.
+  /// Without a file, includes are relative to the current working directory.
+  pub fn new(
+    text_data: &'assign Arena<String>,
+    included_files: &'globals mut HashSet<PathBuf>,
+    current_file: Option<PathBuf>,
+  ) -> Self {
+    Self {
+      text_data,
+      included_files,
+      include_stack: current_file.into_iter().collect(),
+    }
+  }
+}
+
+/// Side table that remembers where each lambda was written in the source code
+pub struct LambdaLocations<'eval, 'globals> {
+  locations: &'globals mut HashMap<ExprRef<'eval>, SourceLocation>,
+  file_name: Option<String>,
+}
+
+impl<'eval, 'globals> LambdaLocations<'eval, 'globals> {
+  /// File name is used for any lambdas that are not inside an included file
+  pub fn new(locations: &'globals mut HashMap<ExprRef<'eval>, SourceLocation>, file_name: Option<String>) -> Self {
+    Self { locations, file_name }
+  }
+}
+
 impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, 'numbers> {
   pub fn new(
     assign_allocator: &'assign Allocator,
@@ -36,6 +104,11 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
       numbers,
       assign_scopes: Vec::new(),
       eval_scopes: Vec::new(),
+      includes: None,
+      lambda_locations: None,
+      lambda_offsets: Vec::new(),
+      max_number: DEFAULT_MAX_NUMBER,
+      assertions: Vec::new(),
       messages: CompilerMessages::new(),
     }
   }
@@ -52,19 +125,177 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
     self.messages.print_messages();
   }
 
+  pub fn eprint_messages(&self) {
+    self.messages.eprint_messages();
+  }
+
+  pub fn eprint_json_messages(&self) {
+    self.messages.eprint_json_messages();
+  }
+
   pub fn parse_error(&mut self, parse_error: ErrorRecovery<usize, Token<'assign>, &'static str>) {
     self.messages.parse_error(parse_error);
   }
 
   /// Figure out the line numbers before attempting to compile a program
   pub fn set_line_numbers(&mut self, full_program: &str) {
-    self.messages.set_offset_map(
-      full_program
-        .lines()
-        .zip(1usize..)
-        .map(|(line_str, line_number)| (line_str.as_ptr() as usize - full_program.as_ptr() as usize, line_number))
-        .collect(),
-    )
+    self.messages.set_source(full_program);
+  }
+
+  /// Replace any block comments with spaces before parsing, and report the ones that are never closed.
+  /// The blanked code is stored in the text arena, so the parsed names can point into it.
+  pub fn strip_block_comments(&mut self, code: &'assign str, text_data: &'assign Arena<String>) -> &'assign str {
+    for comment in find_comments(code).into_iter().filter(|c| c.is_unclosed) {
+      self
+        .messages
+        .error("block comment is never closed with -}", Some(Offset(comment.offset)));
+    }
+
+    match blank_block_comments(code) {
+      Some(blanked) => text_data.alloc(blanked).as_str(),
+      None => code,
+    }
+  }
+
+  /// Allow the code to use `include "file"` statements
+  pub fn set_include_context(&mut self, includes: IncludeContext<'assign, 'globals>) {
+    self.includes = Some(includes);
+  }
+
+  /// Record the source location of every lambda that gets built
+  pub fn set_lambda_locations(&mut self, lambda_locations: LambdaLocations<'eval, 'globals>) {
+    self.lambda_locations = Some(lambda_locations);
+  }
+
+  /// Number literals larger than this are reported as errors instead of being built
+  pub fn set_max_number(&mut self, max_number: u64) {
+    self.max_number = max_number;
+  }
+
+  fn record_lambda_location(&mut self, lambda: ExprRef<'eval>, offset: Offset) {
+    let Some(lambda_locations) = self.lambda_locations.as_mut() else {
+      return;
+    };
+
+    let Some(line) = self.messages.lookup_line_number(offset.0) else {
+      return;
+    };
+
+    let file = self
+      .messages
+      .file_name
+      .clone()
+      .or_else(|| lambda_locations.file_name.clone());
+    lambda_locations.locations.insert(lambda, SourceLocation { file, line });
+  }
+
+  // ====================================
+  //     Assertions
+  // ====================================
+
+  /// Remember an `assert left == right` statement, to be checked by [SymbolTable::check_assertions]
+  pub fn assert_equal(&mut self, left: ExprRef<'eval>, right: ExprRef<'eval>, offset: Offset) {
+    self.assertions.push(Assertion {
+      left,
+      right,
+      file_name: self.messages.file_name.clone(),
+      line_number: self.messages.lookup_line_number(offset.0),
+    });
+  }
+
+  /// Report an error for every assertion where `evaluate` gives a different result for each side.
+  /// Assertions are skipped if there are already errors, since the expressions might be incomplete.
+  pub fn check_assertions<F>(&mut self, mut evaluate_equal: F)
+  where
+    F: FnMut(ExprRef<'eval>, ExprRef<'eval>) -> Result<(), String>,
+  {
+    if self.has_errors() {
+      return;
+    }
+
+    for assertion in mem::take(&mut self.assertions) {
+      if let Err(reason) = evaluate_equal(assertion.left, assertion.right) {
+        let message = match assertion.file_name {
+          Some(file_name) => format!("{file_name}: assertion failed: {reason}"),
+          None => format!("assertion failed: {reason}"),
+        };
+        self.messages.messages.push(CompilerMessage::Error {
+          message: message.into(),
+          line_number: assertion.line_number,
+        });
+      }
+    }
+  }
+
+  // ====================================
+  //     Includes
+  // ====================================
+
+  /// Load all definitions from another file into the globals.
+  /// Each file is only included once, and any top-level expressions are ignored.
+  pub fn include(&mut self, path: &'assign str, offset: Offset) {
+    let Some(includes) = self.includes.as_mut() else {
+      return self.messages.error("include is not allowed here", Some(offset));
+    };
+
+    // Paths are relative to the directory of the including file
+    let full_path = match includes.include_stack.last().and_then(|file| file.parent()) {
+      Some(directory) => directory.join(path),
+      None => PathBuf::from(path),
+    };
+
+    let full_path = match fs::canonicalize(&full_path) {
+      Ok(full_path) => full_path,
+      Err(e) => {
+        return self
+          .messages
+          .error(format!("cannot include \"{path}\": {e}"), Some(offset));
+      },
+    };
+
+    if includes.include_stack.contains(&full_path) {
+      return self.messages.error(
+        format!("include cycle: \"{path}\" is already being loaded"),
+        Some(offset),
+      );
+    }
+
+    if !includes.included_files.insert(full_path.clone()) {
+      return; // Already included
+    }
+
+    let text_data = includes.text_data;
+    let code = match fs::read_to_string(&full_path) {
+      Ok(code) => text_data.alloc(code).as_str(),
+      Err(e) => {
+        return self
+          .messages
+          .error(format!("cannot include \"{path}\": {e}"), Some(offset));
+      },
+    };
+    includes.include_stack.push(full_path);
+
+    // Messages inside the included file use its own line numbers
+    let old_offset_map = mem::take(&mut self.messages.offset_map);
+    let old_source = mem::take(&mut self.messages.source);
+    let old_file_name = self.messages.file_name.replace(path.to_string());
+    self.set_line_numbers(code);
+    let code = self.strip_block_comments(code, text_data);
+
+    let result = ProgramParser::new().parse(self, code);
+
+    self.messages.offset_map = old_offset_map;
+    self.messages.source = old_source;
+    self.messages.file_name = old_file_name;
+    if let Some(includes) = self.includes.as_mut() {
+      includes.include_stack.pop();
+    }
+
+    if let Err(e) = result {
+      self
+        .messages
+        .error(format!("cannot include \"{path}\": parsing error: {e}"), Some(offset));
+    }
   }
 
   // ====================================
@@ -82,24 +313,36 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
   pub fn build_assign_term(&mut self, name: &'assign str, offset: Offset) -> ExprRef<'assign> {
     // O(n) search for the last time a term was used
     // (We can probably find a more efficient way to do this...)
+    // Wildcard parameters still take up an index, but can never be referenced
     let found_index = self
       .assign_scopes
       .iter()
       .rev()
       .zip(1u64..)
-      .filter_map(|(n, index)| (*n == name).then_some(index))
+      .filter_map(|(n, index)| (*n == name && !is_wildcard(n)).then_some(index))
       .next();
 
     if let Some(de_bruijn_index) = found_index {
       // Parent scopes have the highest priority
-      self
-        .assign_allocator
-        .new_term(NonZero::new(de_bruijn_index).expect("invalid index"))
+      let index = NonZero::new(de_bruijn_index).expect("invalid index");
+      self.assign_allocator.try_new_term(index).unwrap_or_else(|e| {
+        self.messages.error(e.to_string(), Some(offset));
+
+        // Term 1 is always valid, return it so we can continue parsing
+        self.assign_allocator.new_term(unsafe { NonZero::new_unchecked(1) })
+      })
     } else if let Some(global_expr) = self.globals.get(name) {
       // Global expressions are substituted verbatim
       *global_expr
     } else {
-      self.messages.error(format!("unknown term: {name}"), Some(offset));
+      if self.assign_scopes.contains(&name) {
+        self.messages.error(
+          format!("parameter {name} starts with _, so it cannot be used as a variable"),
+          Some(offset),
+        );
+      } else {
+        self.messages.error(format!("unknown term: {name}"), Some(offset));
+      }
 
       // Term 1 is always valid, return it so we can continue parsing
       self.assign_allocator.new_term(unsafe { NonZero::new_unchecked(1) })
@@ -107,13 +350,14 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
   }
 
   pub fn start_assign_lambda(&mut self, name: &'assign str, offset: Offset) {
-    // Show warnings (but not errors) about shadowed variables
-    if self.assign_scopes.contains(&name) {
+    // Show warnings (but not errors) about shadowed variables, unless the parameter can't be used anyway
+    let can_shadow = !is_wildcard(name);
+    if can_shadow && self.assign_scopes.contains(&name) {
       self.messages.warning(
         format!("parameter {name} shadows outer parameter of the same name"),
         Some(offset),
       );
-    } else if self.globals.contains_key(name) {
+    } else if can_shadow && self.globals.contains_key(name) {
       self.messages.warning(
         format!("parameter {name} shadows variable of the same name"),
         Some(offset),
@@ -121,12 +365,19 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
     }
 
     self.assign_scopes.push(name);
+    self.lambda_offsets.push(offset);
   }
 
+  /// `\x y z.body` is sugar for `\x.\y.\z.body`, so every name was already declared by its own
+  /// `start_assign_lambda()`, and the scopes are popped from the innermost name outwards
   pub fn build_assign_lambda(&mut self, names: Vec<&'assign str>, body: ExprRef<'assign>) -> ExprRef<'assign> {
     names.into_iter().rev().fold(body, |body, name| {
       self.assign_scopes.pop();
-      self.assign_allocator.new_lambda(name, body)
+      let lambda = self.assign_allocator.new_lambda(name, body);
+      if let Some(offset) = self.lambda_offsets.pop() {
+        self.record_lambda_location(lambda, offset);
+      }
+      lambda
     })
   }
 
@@ -136,9 +387,34 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
       .fold(left, |left, right| self.assign_allocator.new_eval(left, right))
   }
 
+  /// `(x |> f |> g)` is the same as `(g (f x))`, so each stage is applied to the result of the stage before it
+  pub fn build_assign_pipe(&mut self, first: ExprRef<'assign>, stages: Vec<ExprRef<'assign>>) -> ExprRef<'assign> {
+    stages
+      .into_iter()
+      .fold(first, |argument, stage| self.assign_allocator.new_eval(stage, argument))
+  }
+
+  /// `let a = x, b = y in body` is the same as `(\a.((\b.body) y)) x`
+  pub fn build_assign_let(
+    &mut self,
+    bindings: Vec<(&'assign str, ExprRef<'assign>)>,
+    body: ExprRef<'assign>,
+  ) -> ExprRef<'assign> {
+    bindings.into_iter().rev().fold(body, |body, (name, value)| {
+      let lambda = self.build_assign_lambda(vec![name], body);
+      self.build_assign_eval(lambda, vec![value])
+    })
+  }
+
   pub fn build_assign_list(&mut self, terms: Vec<ExprRef<'assign>>) -> ExprRef<'assign> {
-    // Safety: false is defined in the prelude before a list is declared
-    let nil_expr = self.globals.get("false").cloned().expect("false is not defined");
+    // Lists end with false = \x y.y, which is built here so lists also work without the prelude
+    let nil_expr = self.assign_allocator.new_lambda(
+      "x",
+      self.assign_allocator.new_lambda(
+        "y",
+        self.assign_allocator.new_term(unsafe { NonZero::new_unchecked(1) }),
+      ),
+    );
 
     // Fold into (pair 1 (pair 2 (pair ... false))) where pair = \x y.\L.(L x y)
     terms.into_iter().rfold(nil_expr, |list, term| {
@@ -166,34 +442,73 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
     )
   }
 
-  pub fn build_number(&mut self, number: u64) -> ExprRef<'assign> {
-    // 0 should always exist in the list
-    if self.numbers.is_empty() {
-      self
-        .numbers
-        .push(self.assign_allocator.new_term(unsafe { NonZero::new_unchecked(1) }));
-    }
-
-    let mut lambda_number = self
-      .numbers
-      .get(number as usize)
-      .or_else(|| self.numbers.last())
-      .cloned()
-      .unwrap();
+  /// Parse the digits of a number literal, like `1_000` or `0xFF`.
+  /// Returns 0 after reporting an error if the literal is invalid or too large, so we can continue parsing.
+  pub fn parse_number(&mut self, literal: &str, offset: Offset) -> u64 {
+    let number = match parse_integer_literal::<u64>(literal) {
+      Ok(number) => number,
+      Err(e) => {
+        self
+          .messages
+          .error(format!("invalid number {literal}: {e}"), Some(offset));
+        return 0;
+      },
+    };
 
-    // Recursively build out the number (f (f (f x)))
-    while (self.numbers.len() as u64) <= number {
-      lambda_number = self.assign_allocator.new_eval(
-        self.assign_allocator.new_term(unsafe { NonZero::new_unchecked(2) }),
-        lambda_number,
+    if number > self.max_number {
+      self.messages.error(
+        format!("number {literal} is larger than the limit of {}", self.max_number),
+        Some(offset),
       );
-      self.numbers.push(lambda_number);
+      return 0;
     }
 
+    number
+  }
+
+  pub fn build_number(&mut self, number: u64) -> ExprRef<'assign> {
+    extend_numbers(self.assign_allocator, self.numbers, number);
+    let lambda_number = self.numbers[number as usize];
+
     // Then wrap it in a lambda expression (\f.\x.(f (f (f x))))
+    let f = self.unused_parameter_name(NUMERAL_FUNCTION_NAMES);
+    let x = self.unused_parameter_name(NUMERAL_VALUE_NAMES);
     self
       .assign_allocator
-      .new_lambda("f", self.assign_allocator.new_lambda("x", lambda_number))
+      .new_lambda(f, self.assign_allocator.new_lambda(x, lambda_number))
+  }
+
+  /// Signed integers are a pair of Church numerals `\p.(p a b)` that stands for `a - b`,
+  /// so `+3` is `{3 0}` and `-3` is `{0 3}`. This means `-0` and `+0` are both `{0 0}`.
+  /// Plain numbers without a sign are still unsigned Church numerals.
+  ///
+  /// The lexer matches `[-+]digits` before identifiers, so `-1` is always a signed number and never a
+  /// name, while `-1x` is still an identifier.
+  pub fn build_signed_number(&mut self, negative: bool, magnitude: u64) -> ExprRef<'assign> {
+    let zero = self.build_number(0);
+    let magnitude = self.build_number(magnitude);
+    let (left, right) = if negative { (zero, magnitude) } else { (magnitude, zero) };
+
+    // Same shape as `pair` in the prelude
+    self.assign_allocator.new_lambda(
+      "p",
+      self.assign_allocator.new_eval(
+        self.assign_allocator.new_eval(
+          self.assign_allocator.new_term(unsafe { NonZero::new_unchecked(1) }),
+          left,
+        ),
+        right,
+      ),
+    )
+  }
+
+  /// Pick the first name that isn't a parameter of any enclosing lambda, so a numeral written inside
+  /// of `\f.\x.(...)` doesn't print with shadow marks. Falls back to the first name if all are taken.
+  fn unused_parameter_name(&self, names: &[&'static str]) -> &'static str {
+    names
+      .iter()
+      .find(|name| !self.assign_scopes.contains(name) && !self.eval_scopes.contains(name))
+      .unwrap_or(&names[0])
   }
 
   // ====================================
@@ -203,24 +518,36 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
   pub fn build_eval_term(&mut self, name: &'assign str, offset: Offset) -> ExprRef<'eval> {
     // O(n) search for the last time a term was used
     // (We can probably find a more efficient way to do this...)
+    // Wildcard parameters still take up an index, but can never be referenced
     let found_index = self
       .eval_scopes
       .iter()
       .rev()
       .zip(1u64..)
-      .filter_map(|(n, index)| (*n == name).then_some(index))
+      .filter_map(|(n, index)| (*n == name && !is_wildcard(n)).then_some(index))
       .next();
 
     if let Some(de_bruijn_index) = found_index {
       // Parent scopes have the highest priority
-      self
-        .eval_allocator
-        .new_term(NonZero::new(de_bruijn_index).expect("invalid index"))
+      let index = NonZero::new(de_bruijn_index).expect("invalid index");
+      self.eval_allocator.try_new_term(index).unwrap_or_else(|e| {
+        self.messages.error(e.to_string(), Some(offset));
+
+        // Term 1 is always valid, return it so we can continue parsing
+        self.eval_allocator.new_term(unsafe { NonZero::new_unchecked(1) })
+      })
     } else if let Some(global_expr) = self.globals.get(name) {
       // Global expressions are substituted verbatim
       *global_expr
     } else {
-      self.messages.error(format!("unknown term: {name}"), Some(offset));
+      if self.eval_scopes.contains(&name) {
+        self.messages.error(
+          format!("parameter {name} starts with _, so it cannot be used as a variable"),
+          Some(offset),
+        );
+      } else {
+        self.messages.error(format!("unknown term: {name}"), Some(offset));
+      }
 
       // Term 1 is always valid, return it so we can continue parsing
       self.eval_allocator.new_term(unsafe { NonZero::new_unchecked(1) })
@@ -228,13 +555,14 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
   }
 
   pub fn start_eval_lambda(&mut self, name: &'assign str, offset: Offset) {
-    // Show warnings (but not errors) about shadowed variables
-    if self.eval_scopes.contains(&name) {
+    // Show warnings (but not errors) about shadowed variables, unless the parameter can't be used anyway
+    let can_shadow = !is_wildcard(name);
+    if can_shadow && self.eval_scopes.contains(&name) {
       self.messages.warning(
         format!("parameter {name} shadows outer parameter of the same name"),
         Some(offset),
       );
-    } else if self.globals.contains_key(name) {
+    } else if can_shadow && self.globals.contains_key(name) {
       self.messages.warning(
         format!("parameter {name} shadows variable of the same name"),
         Some(offset),
@@ -242,12 +570,18 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
     }
 
     self.eval_scopes.push(name);
+    self.lambda_offsets.push(offset);
   }
 
+  /// Same as build_assign_lambda(), but for the eval scopes declared by `start_eval_lambda()`
   pub fn build_eval_lambda(&mut self, names: Vec<&'assign str>, body: ExprRef<'eval>) -> ExprRef<'eval> {
     names.into_iter().rev().fold(body, |body, name| {
       self.eval_scopes.pop();
-      self.eval_allocator.new_lambda(name, body)
+      let lambda = self.eval_allocator.new_lambda(name, body);
+      if let Some(offset) = self.lambda_offsets.pop() {
+        self.record_lambda_location(lambda, offset);
+      }
+      lambda
     })
   }
 
@@ -257,9 +591,33 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
       .fold(left, |left, right| self.eval_allocator.new_eval(left, right))
   }
 
+  /// `(x |> f |> g)` is the same as `(g (f x))`, so each stage is applied to the result of the stage before it
+  pub fn build_eval_pipe(&mut self, first: ExprRef<'eval>, stages: Vec<ExprRef<'eval>>) -> ExprRef<'eval> {
+    stages
+      .into_iter()
+      .fold(first, |argument, stage| self.eval_allocator.new_eval(stage, argument))
+  }
+
+  /// `let a = x, b = y in body` is the same as `(\a.((\b.body) y)) x`
+  pub fn build_eval_let(
+    &mut self,
+    bindings: Vec<(&'assign str, ExprRef<'eval>)>,
+    body: ExprRef<'eval>,
+  ) -> ExprRef<'eval> {
+    bindings.into_iter().rev().fold(body, |body, (name, value)| {
+      let lambda = self.build_eval_lambda(vec![name], body);
+      self.build_eval_eval(lambda, vec![value])
+    })
+  }
+
   pub fn build_eval_list(&mut self, terms: Vec<ExprRef<'eval>>) -> ExprRef<'eval> {
-    // Safety: false is defined in the prelude before a list is declared
-    let nil_expr = self.globals.get("false").cloned().expect("false is not defined");
+    // Lists end with false = \x y.y, which is built here so lists also work without the prelude
+    let nil_expr = self.eval_allocator.new_lambda(
+      "x",
+      self
+        .eval_allocator
+        .new_lambda("y", self.eval_allocator.new_term(unsafe { NonZero::new_unchecked(1) })),
+    );
 
     // Fold into (pair 1 (pair 2 (pair ... false))) where pair = \x y.\L.(L x y)
     terms.into_iter().rfold(nil_expr, |list, term| {
@@ -291,6 +649,8 @@ impl<'assign, 'eval, 'globals, 'numbers> SymbolTable<'assign, 'eval, 'globals, '
 struct CompilerMessages {
   messages: Vec<CompilerMessage>,
   offset_map: BTreeMap<usize, usize>, // Maps byte offset to line number
+  source: String,                     // Code the offsets point into, used to count the characters in a column
+  file_name: Option<String>,          // Set when messages come from an included file
 }
 
 impl CompilerMessages {
@@ -298,11 +658,18 @@ impl CompilerMessages {
     Self {
       messages: vec![],
       offset_map: BTreeMap::new(),
+      source: String::new(),
+      file_name: None,
     }
   }
 
-  pub fn set_offset_map(&mut self, map: BTreeMap<usize, usize>) {
-    self.offset_map = map;
+  pub fn set_source(&mut self, source: &str) {
+    self.offset_map = source
+      .lines()
+      .zip(1usize..)
+      .map(|(line_str, line_number)| (line_str.as_ptr() as usize - source.as_ptr() as usize, line_number))
+      .collect();
+    self.source = source.to_string();
   }
 
   pub fn has_errors(&self) -> bool {
@@ -311,36 +678,70 @@ impl CompilerMessages {
 
   pub fn warning<T: Into<Cow<'static, str>>>(&mut self, msg: T, offset: Option<Offset>) {
     self.messages.push(CompilerMessage::Warning {
-      message: msg.into(),
+      message: self.with_file_name(msg.into()),
       line_number: offset.and_then(|o| self.lookup_line_number(o.0)),
     });
   }
 
   pub fn error<T: Into<Cow<'static, str>>>(&mut self, msg: T, offset: Option<Offset>) {
     self.messages.push(CompilerMessage::Error {
-      message: msg.into(),
+      message: self.with_file_name(msg.into()),
       line_number: offset.and_then(|o| self.lookup_line_number(o.0)),
     });
   }
 
+  fn with_file_name(&self, msg: Cow<'static, str>) -> Cow<'static, str> {
+    match self.file_name.as_ref() {
+      Some(file_name) => format!("{file_name}: {msg}").into(),
+      None => msg,
+    }
+  }
+
+  /// Same messages as lalrpop, but the location is stored like any other error instead of inside the text
   pub fn parse_error(&mut self, parse_error: ErrorRecovery<usize, Token<'_>, &'static str>) {
-    let error = parse_error
-      .error
-      .map_location(|l| self.lookup_line_number(l).unwrap_or(LineNumber::new(l)));
+    let (message, location) = match parse_error.error {
+      ParseError::User { error } => (error.to_string(), None),
+      ParseError::InvalidToken { location } => ("Invalid token".to_string(), Some(location)),
+      ParseError::UnrecognizedEof { location, expected } => (
+        format!("Unrecognized EOF{}", expected_tokens(&expected)),
+        Some(location),
+      ),
+      ParseError::UnrecognizedToken {
+        token: (start, token, _),
+        expected,
+      } => (
+        format!("Unrecognized token `{token}`{}", expected_tokens(&expected)),
+        Some(start),
+      ),
+      ParseError::ExtraToken {
+        token: (start, token, _),
+      } => (format!("Extra token {token}"), Some(start)),
+    };
 
-    self.error(format!("{}", error), None);
+    self.error(message, location.map(Offset));
   }
 
   pub fn print_messages(&self) {
     self.messages.iter().for_each(CompilerMessage::print);
   }
 
+  pub fn eprint_messages(&self) {
+    self.messages.iter().for_each(CompilerMessage::eprint);
+  }
+
+  pub fn eprint_json_messages(&self) {
+    self.messages.iter().for_each(CompilerMessage::eprint_json);
+  }
+
   fn lookup_line_number(&self, offset: usize) -> Option<LineNumber> {
-    self
-      .offset_map
-      .range(..=offset)
-      .last()
-      .map(|(key, value)| LineNumber::new_with_offset(*value, offset - *key))
+    let (line_start, line) = self.offset_map.range(..=offset).last()?;
+
+    // Count characters instead of bytes, so identifiers like `café` only take up one column per letter
+    let characters = match self.source.get(*line_start..offset) {
+      Some(before) => before.chars().count(),
+      None => offset - line_start,
+    };
+    Some(LineNumber::new_with_column(*line, characters + 1))
   }
 }
 
@@ -381,12 +782,39 @@ impl CompilerMessage {
   }
 
   pub fn print(&self) {
-    let (prefix, message, line_number) = match self {
-      Self::Warning { message, line_number } => ("Warning".yellow(), message, line_number),
-      Self::Error { message, line_number } => ("Error".red(), message, line_number),
-    };
+    println!("{self}");
+  }
+
+  pub fn eprint(&self) {
+    eprintln!("{self}");
+  }
 
-    let message = if let Some(line_number) = line_number {
+  /// Print to stderr as a single line of JSON, like `{"level":"error","message":"...","line":1,"column":5}`
+  pub fn eprint_json(&self) {
+    let json = serde_json::to_string(&self.diagnostic()).expect("diagnostic can always be serialized");
+    eprintln!("{json}");
+  }
+
+  /// Plain data inside of the message, shared by the colored and JSON output
+  pub fn diagnostic(&self) -> Diagnostic<'_> {
+    let line_number = self.line_number();
+    Diagnostic {
+      level: if self.is_error() { "error" } else { "warning" },
+      message: self.message(),
+      line: line_number.map(|l| l.line),
+      column: line_number.and_then(|l| l.column),
+    }
+  }
+}
+
+impl fmt::Display for CompilerMessage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let Diagnostic { message, .. } = self.diagnostic();
+    let prefix = if self.is_error() { "Error" } else { "Warning" };
+    #[cfg(feature = "cli")]
+    let prefix = if self.is_error() { prefix.red() } else { prefix.yellow() };
+
+    let message = if let Some(line_number) = self.line_number() {
       match message.lines().collect_vec()[..] {
         [] => format!("(on line {line_number})"),
         [message] => format!("{message} (on line {line_number})"),
@@ -396,33 +824,44 @@ impl CompilerMessage {
       message.to_string()
     };
 
-    println!("{prefix}: {message}");
+    write!(f, "{prefix}: {message}")
   }
 }
 
+/// Level, text and location of a compiler message
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic<'m> {
+  /// Either `warning` or `error`
+  pub level: &'static str,
+  pub message: &'m str,
+  pub line: Option<usize>,
+  pub column: Option<usize>,
+}
+
+/// Line in the source code, with an optional column. Both start from 1, and columns count characters instead of bytes.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct LineNumber {
   pub line: usize,
-  pub offset: Option<usize>,
+  pub column: Option<usize>,
 }
 
 impl LineNumber {
   pub fn new(line: usize) -> Self {
-    Self { line, offset: None }
+    Self { line, column: None }
   }
 
-  pub fn new_with_offset(line: usize, offset: usize) -> Self {
+  pub fn new_with_column(line: usize, column: usize) -> Self {
     Self {
       line,
-      offset: Some(offset),
+      column: Some(column),
     }
   }
 }
 
 impl fmt::Display for LineNumber {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    if let Some(offset) = self.offset {
-      format!("{}:{}", self.line, offset)
+    if let Some(column) = self.column {
+      format!("{}:{}", self.line, column)
     } else {
       format!("{}", self.line)
     }
@@ -430,6 +869,23 @@ impl fmt::Display for LineNumber {
   }
 }
 
+/// Where something was written in the source code
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+  /// Name of the file, if known
+  pub file: Option<String>,
+  pub line: LineNumber,
+}
+
+impl fmt::Display for SourceLocation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.file.as_ref() {
+      Some(file) => write!(f, "{file}:{}", self.line),
+      None => write!(f, "{}", self.line),
+    }
+  }
+}
+
 /// Store a byte index inside the source code for better error handling.
 /// Define it as a new type variable so the compiler enforces type safety.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -448,10 +904,291 @@ impl From<Offset> for usize {
   }
 }
 
-/// Convert an integer literal string into an integer
+/// Build the body of every numeral up to and including `number` that isn't already in the list,
+/// so `numbers[n]` is always `(f (f ... x))` with `n` applications. Existing entries are reused.
+pub fn extend_numbers<'assign>(allocator: &'assign Allocator, numbers: &mut Vec<ExprRef<'assign>>, number: u64) {
+  // 0 should always exist in the list
+  if numbers.is_empty() {
+    numbers.push(allocator.new_term(unsafe { NonZero::new_unchecked(1) }));
+  }
+
+  // Iteratively build out the number (f (f (f x)))
+  let mut lambda_number = *numbers.last().unwrap();
+  while (numbers.len() as u64) <= number {
+    lambda_number = allocator.new_eval(allocator.new_term(unsafe { NonZero::new_unchecked(2) }), lambda_number);
+    numbers.push(lambda_number);
+  }
+}
+
+/// List the tokens that a parse error expected on a new line, like `Expected one of "(", "\\" or "λ"`
+fn expected_tokens(expected: &[String]) -> String {
+  match expected {
+    [] => String::new(),
+    [only] => format!("\nExpected one of {only}"),
+    [rest @ .., last] => format!("\nExpected one of {} or {last}", rest.join(", ")),
+  }
+}
+
+/// Parameters starting with `_`, like `\_.x`, are placeholders that can never be referenced
+fn is_wildcard(name: &str) -> bool {
+  name.starts_with('_')
+}
+
+/// Convert an integer literal string into an integer.
+/// Literals starting with `0x`, `0o` or `0b` are read as hexadecimal, octal or binary.
 pub fn parse_integer_literal<T: Num>(input: &str) -> Result<T, T::FromStrRadixErr> {
+  let (input, radix) = match input.get(..2) {
+    Some("0x") => (&input[2..], 16),
+    Some("0o") => (&input[2..], 8),
+    Some("0b") => (&input[2..], 2),
+    _ => (input, 10),
+  };
+
   // Filter any underscore characters
   let input: String = input.chars().filter(|c| *c != '_').collect();
 
-  T::from_str_radix(&input, 10)
+  T::from_str_radix(&input, radix)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Executor, PRELUDE};
+
+  /// Parse both expressions with the prelude loaded, and check that they built the same expression
+  fn assert_parses_like(code: &'static str, expected: &'static str) {
+    let executor = Executor::new();
+    executor.load_code(PRELUDE, Some("prelude")).unwrap();
+
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, code).unwrap();
+    let expected_expr = executor.load_expression(&eval_allocator, expected).unwrap();
+    assert!(
+      expr.structurally_eq(expected_expr),
+      "{code} parsed as {expr}, expected {expected_expr}"
+    );
+  }
+
+  #[test]
+  fn function_calls_apply_every_argument() {
+    assert_parses_like("add(1, 2)", "(add 1 2)");
+    assert_parses_like("add(1, 2,)", "(add 1 2)");
+    assert_parses_like("add(succ 1, 2)", "(add (succ 1) 2)");
+  }
+
+  #[test]
+  fn numerals_pick_parameter_names_that_are_not_shadowed() {
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+    let display = |code| executor.load_expression(&eval_allocator, code).unwrap().to_string();
+
+    assert_eq!(display("2"), r"\f.\x.(f (f x))");
+    assert_eq!(display(r"\f.\x.2"), r"\f.\x.\g.\y.(g (g y))");
+    assert_eq!(display(r"\x.\g.\f.1"), r"\x.\g.\f.\h.\y.(h y)");
+
+    executor.load_code("two = \\f.\\x.2", None).unwrap();
+    assert_eq!(
+      executor.get_global("two").unwrap().to_string(),
+      r"\f.\x.\g.\y.(g (g y))"
+    );
+  }
+
+  #[test]
+  fn signed_numbers_are_pairs_of_numerals() {
+    assert_parses_like("+3", r"\p.(p 3 0)");
+    assert_parses_like("-3", r"\p.(p 0 3)");
+    assert_parses_like("-0", "+0");
+
+    // -0 is a pair, unlike the unsigned numeral 0
+    let executor = Executor::new();
+    let eval_allocator = Allocator::new();
+    let negative_zero = executor.load_expression(&eval_allocator, "-0").unwrap();
+    let zero = executor.load_expression(&eval_allocator, "0").unwrap();
+    assert!(!negative_zero.structurally_eq(zero));
+
+    // Signed numbers take priority over identifiers, but not over longer identifiers
+    executor.load_code("-1 = \\x.x", None).unwrap_err();
+    executor.load_code("-1x = \\x.x", None).unwrap();
+    assert!(executor.get_global("-1x").is_some());
+    assert_parses_like("(- 5 3)", "(sub 5 3)");
+  }
+
+  #[test]
+  fn pipes_apply_each_stage_to_the_previous_result() {
+    // Evaluated expressions, where each stage is a whole application
+    assert_parses_like("(1 |> succ)", "(succ 1)");
+    assert_parses_like("(1 |> add 2 |> succ)", "(succ (add 2 1))");
+    assert_parses_like("(succ 1 |> mul 2 3)", "(mul 2 3 (succ 1))");
+    assert_parses_like("(1 |> (2 |> add) |> \\n.(mul n n))", "(\\n.(mul n n) (add 2 1))");
+
+    // Global definitions, where a stage can refer to the parameters of an outer lambda
+    let executor = Executor::new();
+    executor.load_code(PRELUDE, Some("prelude")).unwrap();
+    executor
+      .load_code(
+        "with-pipe = \\x.(x |> add x |> succ)\nwithout-pipe = \\x.(succ (add x x))",
+        None,
+      )
+      .unwrap();
+    let with_pipe = executor.get_global("with-pipe").unwrap();
+    let without_pipe = executor.get_global("without-pipe").unwrap();
+    assert!(with_pipe.structurally_eq(without_pipe), "{with_pipe} != {without_pipe}");
+  }
+
+  #[test]
+  fn let_bindings_desugar_from_left_to_right() {
+    // Evaluated expressions
+    assert_parses_like("let a = 1 in (succ a)", r"(\a.(succ a) 1)");
+    assert_parses_like(
+      "let a = 1, b = (succ a) in (add a b)",
+      r"(\a.(\b.(add a b) (succ a)) 1)",
+    );
+    assert_parses_like("let a = 1, a = (succ a) in a", r"(\a.(\a.a (succ a)) 1)");
+
+    // Global definitions, where the body can also refer to the parameters of an outer lambda
+    let executor = Executor::new();
+    executor.load_code(PRELUDE, Some("prelude")).unwrap();
+    executor
+      .load_code(
+        "with-let = \\x.let a = x, b = (succ a) in (add a b)\nwithout-let = \\x.(\\a.(\\b.(add a b) (succ a)) x)",
+        None,
+      )
+      .unwrap();
+    let with_let = executor.get_global("with-let").unwrap();
+    let without_let = executor.get_global("without-let").unwrap();
+    assert!(with_let.structurally_eq(without_let), "{with_let} != {without_let}");
+  }
+
+  #[test]
+  fn let_and_in_are_reserved_words() {
+    let executor = Executor::new();
+    for code in ["let = \\x.x", "in = \\x.x", "f = \\let.let", "(\\x.in \\y.y)"] {
+      assert!(executor.load_code(code, None).is_err(), "{code} should not parse");
+    }
+  }
+
+  #[test]
+  fn identifiers_can_use_unicode_except_lambda() {
+    let executor = Executor::new();
+    executor
+      .load_code("función = \\x.x\n∑ = (función función)", None)
+      .unwrap();
+
+    let eval_allocator = Allocator::new();
+    let expr = executor.load_expression(&eval_allocator, "(∑ función)").unwrap();
+    assert_eq!(expr.to_string(), r"((\x.x \x.x) \x.x)");
+    assert!(executor.get_global("∑").is_some());
+
+    // λ ends the name right before it, even without a space
+    assert_parses_like("(succλx.x)", "(succ λx.x)");
+  }
+
+  /// Parse a program on its own, returning each message with the place it points to
+  fn program_messages(code: &str) -> Vec<(String, String)> {
+    let allocator = Allocator::new();
+    let text_data = Arena::new();
+    let mut globals = BTreeMap::new();
+    let mut numbers = Vec::new();
+
+    let mut symbol_table = SymbolTable::new(&allocator, &allocator, &mut globals, &mut numbers);
+    symbol_table.set_line_numbers(code);
+    let code = symbol_table.strip_block_comments(code, &text_data);
+    ProgramParser::new().parse(&mut symbol_table, code).unwrap();
+    symbol_table
+      .get_compiler_messages()
+      .iter()
+      .map(|m| {
+        let line_number = m.line_number().map(|n| n.to_string()).unwrap_or_default();
+        (line_number, m.message().to_string())
+      })
+      .collect()
+  }
+
+  #[test]
+  fn columns_count_characters_instead_of_bytes() {
+    let messages = program_messages("café = \\x.x\nbar = (café qux)\ncafé = \\é.\\é.é\n");
+    let expected = [
+      ("2:13", "unknown term: qux"),
+      ("3:12", "parameter é shadows outer parameter of the same name"),
+      ("3:1", "duplicate variable café"),
+    ];
+    assert_eq!(
+      messages,
+      expected.map(|(location, message)| (location.to_string(), message.to_string()))
+    );
+  }
+
+  #[test]
+  fn commented_out_definitions_create_no_globals() {
+    let executor = Executor::new();
+    executor
+      .load_code(
+        "kept = \\x.x\n# hash = \\x.x\n; semicolon = \\x.x\n{- block = \\x.x\n{- nested = \\x.x -}\nstill-block = \\x.x -}\nafter = kept\n",
+        None,
+      )
+      .unwrap();
+
+    let globals: Vec<_> = executor
+      .all_globals()
+      .borrow()
+      .keys()
+      .map(|name| name.to_string())
+      .collect();
+    assert_eq!(globals, ["after", "kept"]);
+  }
+
+  #[test]
+  fn line_numbers_count_the_lines_inside_block_comments() {
+    let messages = program_messages("{- one\ntwo -} a = \\x.x\n{-\n-}\nb = unknown\n");
+    assert_eq!(messages, [("5:5".to_string(), "unknown term: unknown".to_string())]);
+  }
+
+  /// Include the first file from a new directory containing all of the files, returning the globals and messages
+  fn include_files(directory: &str, files: &[(&str, &str)]) -> (Vec<String>, Vec<String>) {
+    let directory = std::env::temp_dir().join(format!("rusty-lambda-{}-{directory}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for (name, code) in files {
+      fs::write(directory.join(name), code).unwrap();
+    }
+
+    let allocator = Allocator::new();
+    let text_data = Arena::new();
+    let mut globals = BTreeMap::new();
+    let mut numbers = Vec::new();
+    let mut included_files = HashSet::new();
+
+    let path = text_data.alloc(directory.join(files[0].0).to_string_lossy().into_owned());
+    let mut symbol_table = SymbolTable::new(&allocator, &allocator, &mut globals, &mut numbers);
+    symbol_table.set_include_context(IncludeContext::new(&text_data, &mut included_files, None));
+    symbol_table.include(path, Offset(0));
+
+    let messages = symbol_table
+      .get_compiler_messages()
+      .iter()
+      .map(|m| m.message().to_string())
+      .collect();
+    (globals.keys().map(|name| name.to_string()).collect(), messages)
+  }
+
+  #[test]
+  fn included_files_can_use_each_others_globals() {
+    let (globals, messages) = include_files(
+      "include",
+      &[
+        ("a.txt", "include \"b.txt\"\na-value = (b-value b-value)\n"),
+        ("b.txt", "b-value = \\x.x\n"),
+      ],
+    );
+    assert_eq!(messages, Vec::<String>::new());
+    assert_eq!(globals, ["a-value", "b-value"]);
+  }
+
+  #[test]
+  fn include_cycles_are_reported() {
+    let (_, messages) = include_files(
+      "include-cycle",
+      &[("a.txt", "include \"b.txt\"\n"), ("b.txt", "include \"a.txt\"\n")],
+    );
+    assert_eq!(messages, [r#"b.txt: include cycle: "a.txt" is already being loaded"#]);
+  }
 }
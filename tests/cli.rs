@@ -0,0 +1,177 @@
+//! Runs the `lambda` binary on small files to check what gets printed
+
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Any test that takes longer than this is assumed to be stuck evaluating a divergent term
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Write the code into a new file in the temporary directory, unique to this test
+fn code_file(name: &str, code: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("rusty-lambda-{}-{name}", std::process::id()));
+  fs::write(&path, code).unwrap();
+  path
+}
+
+/// Run the binary with the arguments and stdin, failing the test if it doesn't exit in time
+fn lambda(args: &[&str], stdin: &str) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_lambda"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+
+  // Feed stdin and collect the output on other threads, so a large input or output can't block the timeout
+  let mut input = child.stdin.take().unwrap();
+  let stdin = stdin.to_owned();
+  thread::spawn(move || input.write_all(stdin.as_bytes()));
+  let (sender, receiver) = mpsc::channel();
+  thread::spawn(move || sender.send(child.wait_with_output()));
+
+  match receiver.recv_timeout(TIMEOUT) {
+    Ok(output) => output.unwrap(),
+    Err(_) => panic!("lambda {args:?} did not finish within {TIMEOUT:?}"),
+  }
+}
+
+fn stdout(output: &Output) -> String {
+  assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+  String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn no_eval_prints_the_parsed_forms_without_evaluating() {
+  let file = code_file("no-eval.txt", "omega = \\x.(x x)\n(omega omega)\n(succ 1)\n");
+  let output = lambda(&["--no-eval", file.to_str().unwrap()], "");
+  assert_eq!(
+    stdout(&output),
+    "(λx.(x x) λx.(x x))\n(λn.λf.λx.((n f) (f x)) λf.λx.(f x))\n"
+  );
+}
+
+#[test]
+fn only_evaluates_the_selected_expression() {
+  let file = code_file("only.txt", "(succ 0)\n(succ 1)\n(succ 2)\n");
+  let output = lambda(&["--only", "2", file.to_str().unwrap()], "");
+  assert_eq!(stdout(&output), "λf.λx.(f (f x))\n");
+}
+
+#[test]
+fn decode_stats_counts_the_reduction_steps() {
+  // (\x.(x x) \y.y) takes two steps: one to copy the argument, and one to apply it to itself
+  let output = lambda(&["decode", "--evaluate", "--stats"], "01000110100010");
+  assert_eq!(stdout(&output), "\\x1.x1\n");
+  assert_eq!(String::from_utf8_lossy(&output.stderr), "Steps: 2, Size: 2\n");
+}
+
+#[test]
+fn repl_paste_loads_every_line_as_one_program() {
+  // The pasted definition is still there after paste mode ends
+  let output = lambda(
+    &["--decode-numerals"],
+    ":paste\ntwo = 2\n(succ two)\n;;\n(add two two)\n:quit\n",
+  );
+  let results: Vec<_> = stdout(&output)
+    .lines()
+    .filter_map(|line| line.split_once(' '))
+    .filter(|(result, _)| result.parse::<u64>().is_ok())
+    .map(|(result, _)| result.to_string())
+    .collect();
+  assert_eq!(results, ["3", "4"]);
+}
+
+#[test]
+fn repl_count_prints_the_steps_and_size_without_the_result() {
+  let output = lambda(&["--decode-numerals"], ":count (add 2 2)\n:quit\n");
+  let stdout = stdout(&output);
+  assert!(stdout.contains("Steps: 10, Size: 11\n"), "{stdout}");
+  assert!(!stdout.lines().any(|line| line.starts_with('4')), "{stdout}");
+}
+
+#[test]
+fn encode_writes_the_exact_bytes() {
+  let encode = |args: &[&str]| {
+    let output = lambda(&[&["encode", "--term", r"\x.x"][..], args].concat(), "");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output.stdout
+  };
+
+  assert_eq!(encode(&[]), b"0010\n");
+  assert_eq!(encode(&["--no-trailing-newline"]), b"0010");
+  assert_eq!(encode(&["--binary"]), [0b0010_0000]);
+
+  // Zero-width characters are usually pasted into other text, so they only get a newline if asked for
+  let zero_width = "\u{ffa0}\u{ffa0}\u{3164}\u{ffa0}";
+  assert_eq!(encode(&["--zero-width"]), zero_width.as_bytes());
+  assert_eq!(
+    encode(&["--zero-width", "--trailing-newline"]),
+    format!("{zero_width}\n").as_bytes()
+  );
+}
+
+#[test]
+fn table_applies_the_term_to_each_row_and_skips_comments() {
+  let table = code_file(
+    "table.txt",
+    "; header\n1 2\n\n# 5 5\n{- 6 6\n7 7 -}\n3 {- inline -} 4 ; trailing\n",
+  );
+  let output = lambda(
+    &["--decode-numerals", "--table", table.to_str().unwrap(), "--term", "add"],
+    "",
+  );
+  assert_eq!(stdout(&output), "3\n7\n");
+}
+
+/// Check that the bits are exactly one closed Binary Lambda Calculus term, returning the number of bits used
+fn closed_blc_term(bits: &[u8], scope: usize) -> Option<usize> {
+  match bits {
+    [b'0', b'0', rest @ ..] => closed_blc_term(rest, scope + 1).map(|length| length + 2),
+    [b'0', b'1', rest @ ..] => {
+      let left = closed_blc_term(rest, scope)?;
+      let right = closed_blc_term(&rest[left..], scope)?;
+      Some(2 + left + right)
+    },
+    _ => {
+      let index = bits.iter().position(|bit| *bit == b'0')?;
+      (index >= 1 && index <= scope).then_some(index + 1)
+    },
+  }
+}
+
+#[test]
+fn repl_env_prints_the_encoding_of_every_global() {
+  let output = lambda(&[], ":env\n:quit\n");
+  let stdout = stdout(&output);
+  let globals: Vec<_> = stdout.lines().filter_map(|line| line.split_once('=')).collect();
+  assert!(globals.contains(&("true", "0000110")), "{stdout}");
+
+  for (name, bits) in globals {
+    assert!(
+      name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+      "{name} isn't a shell variable"
+    );
+    assert_eq!(closed_blc_term(bits.as_bytes(), 0), Some(bits.len()), "{name}={bits}");
+  }
+}
+
+#[test]
+fn decode_names_alpha_renames_every_parameter_by_depth() {
+  let output = lambda(&["decode", "--names", "alpha"], "00000111010");
+  assert_eq!(stdout(&output), "\\x.\\y.(x y)\n");
+
+  // Names from the input and lambdas substituted by --evaluate are renamed too
+  let sexpr = "(app (lam f (lam n (app (var 1) (var 2)))) (lam a (lam b (var 1))))";
+  let output = lambda(&["decode", "--sexpr", "--evaluate", "--names", "alpha"], sexpr);
+  assert_eq!(stdout(&output), "\\x.(x \\y.\\z.z)\n");
+  let output = lambda(&["decode", "--sexpr", "--evaluate"], sexpr);
+  assert_eq!(stdout(&output), "\\n.(n \\a.\\b.b)\n");
+}